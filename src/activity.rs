@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Rate at which a paused wallpaper is still updated, to notice when it can resume
+pub(crate) const PAUSED_FRAMERATE: f64 = 2.;
+
+/// Why the wallpaper runs slower, or not at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Reason {
+    /// The window system reported the window as occluded
+    Occluded,
+    /// A fullscreen or maximized window is on the monitor
+    Covered,
+    /// Running on battery, see `PowerConfig`
+    Battery,
+    /// No input for `IdleConfig::timeout_s`
+    Idle,
+    /// The session is locked
+    Locked,
+    /// The monitors are powered down
+    DisplayOff,
+    /// The system is about to sleep
+    Sleeping,
+    /// Over the CPU budget, see `Governor`
+    CpuBudget,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Throttle {
+    Pause,
+    Framerate(f64),
+}
+
+/// Throttles currently applied to the wallpaper, by reason. The strongest one wins.
+#[derive(Debug, Default)]
+pub(crate) struct Activity {
+    throttles: BTreeMap<Reason, Throttle>,
+}
+
+impl Activity {
+    /// Sets or clears (`None`) the throttle of `reason`, returns whether anything changed.
+    pub(crate) fn set(&mut self, reason: Reason, throttle: Option<Throttle>) -> bool {
+        let previous = match throttle {
+            Some(throttle) => self.throttles.insert(reason, throttle),
+            None => self.throttles.remove(&reason),
+        };
+        previous != throttle
+    }
+
+    #[inline]
+    pub(crate) fn paused(&self) -> bool {
+        self.throttles.values().any(|t| *t == Throttle::Pause)
+    }
+
+    /// Strongest throttle, `None` if running at full speed
+    pub(crate) fn throttle(&self) -> Option<Throttle> {
+        if self.paused() {
+            return Some(Throttle::Pause);
+        }
+        self.framerate().map(Throttle::Framerate)
+    }
+
+    /// Lowest framerate asked for, whether or not it is also paused
+    pub(crate) fn framerate(&self) -> Option<f64> {
+        self.throttles
+            .values()
+            .filter_map(|throttle| match throttle {
+                Throttle::Framerate(framerate) => Some(*framerate),
+                Throttle::Pause => None,
+            })
+            .reduce(f64::min)
+    }
+
+    #[inline]
+    pub(crate) fn reasons(&self) -> Vec<Reason> {
+        self.throttles.keys().copied().collect()
+    }
+}
@@ -1,4 +1,8 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::Mutex;
 
@@ -6,7 +10,26 @@ use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
 use winit::{dpi::PhysicalSize, event::Event, window::Window};
 use winit_input_helper::WinitInputHelper;
 
-use crate::video::Video;
+use crate::{
+    activity::{Activity, Reason, Throttle},
+    cache::{self, optimize},
+    config::Config,
+    config::Wallpaper,
+    decoder,
+    governor::{self, Governor, Level},
+    library::{self, Position},
+    platform_specific::{Coverage, MonitorArea, Presence},
+    power::{self, Restrictions},
+    reactive::Modulator,
+    recovery::Recovery,
+    scene::Scene,
+    slideshow::{Slideshow, SystemClock},
+    source::Source,
+    status::{PlaybackState, Status},
+    still::Still,
+    video::{Video, VideoError},
+    visualizer::Visualizer,
+};
 
 pub(crate) struct App {
     _inner: Arc<Mutex<AppInner>>,
@@ -24,8 +47,41 @@ pub(crate) struct AppInner {
     input_helper: WinitInputHelper,
 
     pixels: Pixels,
+    /// Size of the pixels buffer, which follows the frames of the source
+    buffer_size: PhysicalSize<u32>,
+    /// Size of the window, which the buffer is scaled to
+    window_size: PhysicalSize<u32>,
 
-    video: Video,
+    config: Config,
+
+    /// `None` while waiting for a retry after an error
+    source: Option<Box<dyn Source>>,
+    /// Shown once `recovery` gave up on the source
+    fallback: Option<Still>,
+    recovery: Recovery,
+    /// Making the optimized copy of the wallpaper, if `decode.optimize` is set
+    optimize_job: Option<optimize::Job>,
+    /// Clips and sizes whose optimized copy couldn't be made, not tried again
+    optimize_failures: HashSet<(String, PhysicalSize<u32>)>,
+
+    /// Audio-reactive effects, if enabled
+    modulator: Option<Modulator>,
+
+    /// Why the wallpaper is paused or slowed down
+    activity: Activity,
+    /// `None` if covering is ignored or can't be watched
+    coverage: Option<Coverage>,
+    /// `None` if neither idle time nor DPMS are watched, or if they can't be
+    presence: Option<Presence>,
+    /// `None` without a CPU budget, or if the CPU time can't be measured
+    governor: Option<Governor>,
+    /// Applied power profiles
+    power: Restrictions,
+    /// `None` without power profiles, or if the power supplies can't be read
+    next_power_poll: Option<Instant>,
+
+    /// Last published status
+    status: Status,
 
     // TODO: Use scale factor for HIDPI
     scale_factor: f64,
@@ -33,7 +89,17 @@ pub(crate) struct AppInner {
 
 impl AppInner {
     pub(crate) fn render(&mut self) -> Result<(), pixels::Error> {
-        if self.video.render(self.pixels.frame_mut()) {
+        let frame = self.pixels.frame_mut();
+        let mut rendered = match (&mut self.source, &mut self.fallback) {
+            (Some(source), _) => source.render(frame),
+            (None, Some(fallback)) => fallback.render(frame),
+            (None, None) => false,
+        };
+        if let Some(modulator) = &mut self.modulator {
+            rendered = modulator.render(frame, rendered);
+        }
+
+        if rendered {
             self.pixels.render_with(|encoder, render_target, ctx| {
                 ctx.scaling_renderer.render(encoder, render_target);
                 Ok(())
@@ -43,9 +109,412 @@ impl AppInner {
         }
     }
 
-    #[inline]
     pub(crate) fn update(&mut self) {
-        self.video.update().unwrap();
+        let now = Instant::now();
+
+        match self.source.as_mut().map(|source| source.update()) {
+            Some(Ok(())) => self.recovery.record_success(now),
+            Some(Err(e)) => self.fail_source(e),
+            None => {
+                if !self.power.stop && self.recovery.should_retry(now) {
+                    self.start_source();
+                }
+            }
+        }
+
+        if let Some(succeeded) = self.optimize_job.as_ref().and_then(optimize::Job::result) {
+            let job = self.optimize_job.take().expect("Checked above");
+            if !succeeded {
+                let (uri, size) = job.target();
+                self.optimize_failures.insert((uri.to_owned(), size));
+            }
+            // `video::policy` picks the optimized copy up
+            if succeeded && self.source.is_some() {
+                self.stop_source();
+                self.start_source();
+            }
+        }
+
+        if self.next_power_poll.map_or(false, |next| now >= next) {
+            self.poll_power(now);
+        }
+
+        if let Some(coverage) = &self.coverage {
+            let throttle = self.config.covered.throttle();
+            self.set_throttle(Reason::Covered, throttle.filter(|_| coverage.covered()));
+        }
+
+        if let Some(governor) = &mut self.governor {
+            let previous = governor.level();
+            let level = match governor::process_cpu_time() {
+                Ok(cpu_time) => governor.update(now, cpu_time),
+                Err(e) => {
+                    eprintln!("Error measuring CPU time, CPU budget disabled: {:#}", e);
+                    self.governor = None;
+                    Some(Level::Full)
+                }
+            };
+            if let Some(level) = level {
+                self.apply_level(previous, level);
+            }
+        }
+
+        if let Some(presence) = &self.presence {
+            let state = presence.state();
+            let idle = self
+                .config
+                .idle
+                .timeout()
+                .map_or(false, |timeout| state.idle >= timeout);
+            let display_off = self.config.idle.display_off && state.display_off;
+
+            self.set_throttle(Reason::Idle, idle.then_some(Throttle::Pause));
+            self.set_throttle(Reason::DisplayOff, display_off.then_some(Throttle::Pause));
+        }
+
+        if let Some(modulator) = &mut self.modulator {
+            let energies = self.source.as_ref().and_then(|source| source.energies());
+            modulator.update(energies, now);
+        }
+
+        self.publish_status();
+    }
+
+    /// Sets or clears the throttle of `reason`, pausing, resuming or slowing the source
+    /// accordingly.
+    pub(crate) fn set_throttle(&mut self, reason: Reason, throttle: Option<Throttle>) {
+        let (paused, framerate) = (self.activity.paused(), self.activity.framerate());
+        if !self.activity.set(reason, throttle) {
+            return;
+        }
+
+        if self.activity.paused() != paused {
+            self.apply_pause();
+        }
+        if self.activity.framerate() != framerate {
+            self.apply_framerate();
+        }
+    }
+
+    fn poll_power(&mut self, now: Instant) {
+        let restrictions = match power::read(&self.config.power.sysfs) {
+            Ok(state) => {
+                self.next_power_poll = Some(now + self.config.power.poll_interval());
+                Restrictions::of(&self.config.power, state)
+            }
+            Err(e) => {
+                eprintln!(
+                    "Error reading power supplies, power profiles disabled: {:#}",
+                    e
+                );
+                self.next_power_poll = None;
+                Restrictions::default()
+            }
+        };
+
+        if restrictions != self.power {
+            self.apply_power(restrictions);
+        }
+    }
+
+    fn apply_power(&mut self, restrictions: Restrictions) {
+        let previous = std::mem::replace(&mut self.power, restrictions);
+
+        let throttle = if restrictions.still {
+            Some(Throttle::Pause)
+        } else {
+            restrictions.framerate.map(Throttle::Framerate)
+        };
+        self.set_throttle(Reason::Battery, throttle);
+
+        if restrictions.mute != previous.mute {
+            if let Some(source) = &mut self.source {
+                source.set_muted(restrictions.mute);
+            }
+        }
+
+        if restrictions.stop && !previous.stop {
+            self.stop_source();
+            if self.fallback.is_none() {
+                self.show_fallback();
+            }
+        } else if !restrictions.stop && previous.stop {
+            self.start_source();
+        }
+    }
+
+    #[inline]
+    fn level(&self) -> Level {
+        self.governor.as_ref().map_or(Level::Full, Governor::level)
+    }
+
+    /// Largest size sources are decoded at, smaller than the window when over the CPU budget
+    fn decode_size(&self) -> PhysicalSize<u32> {
+        let scale = self.level().decode_scale();
+        // Even sizes suit the video formats better
+        let scaled = |length: u32| ((length as f64 * scale) as u32 & !1).max(2);
+
+        PhysicalSize::new(
+            scaled(self.window_size.width),
+            scaled(self.window_size.height),
+        )
+    }
+
+    fn set_buffer_size(&mut self, size: PhysicalSize<u32>) {
+        if let Err(e) = self.pixels.resize_buffer(size.width, size.height) {
+            eprintln!("Error resizing the pixels buffer: {}", e);
+            return;
+        }
+        self.buffer_size = size;
+
+        self.modulator = self
+            .config
+            .reactive
+            .clone()
+            .map(|reactive| Modulator::new(reactive, size));
+        if self.fallback.is_some() {
+            self.show_fallback();
+        }
+    }
+
+    fn apply_level(&mut self, previous: Level, level: Level) {
+        let throttle = match level {
+            Level::Full => None,
+            Level::Freeze => Some(Throttle::Pause),
+            _ => Some(Throttle::Framerate(self.config.framerate / 2.)),
+        };
+        self.set_throttle(Reason::CpuBudget, throttle);
+
+        // Encoding would only add to the usage, it starts over once back under the budget
+        if level > Level::Full {
+            self.optimize_job = None;
+        }
+
+        // Only a new pipeline picks up another size or other decoders
+        let redecode = previous.decode_scale() != level.decode_scale()
+            || previous.favor_hardware() != level.favor_hardware();
+        if redecode && self.source.is_some() {
+            self.stop_source();
+            self.start_source();
+        } else if level == Level::Full {
+            self.optimize();
+        }
+    }
+
+    fn apply_pause(&mut self) {
+        let paused = self.activity.paused();
+
+        if let Some(Err(e)) = self.source.as_mut().map(|source| source.set_paused(paused)) {
+            self.fail_source(e);
+        }
+    }
+
+    /// Slows the source itself down, not only how often `FrameManager` draws it
+    fn apply_framerate(&mut self) {
+        let framerate = self.activity.framerate();
+
+        if let Some(Err(e)) = self
+            .source
+            .as_mut()
+            .map(|source| source.set_max_framerate(framerate))
+        {
+            self.fail_source(e);
+        }
+    }
+
+    /// Strongest throttle for `FrameManager`, `None` at full speed
+    #[inline]
+    pub(crate) fn throttle(&self) -> Option<Throttle> {
+        self.activity.throttle()
+    }
+
+    fn publish_status(&mut self) {
+        let state = match (&self.source, &self.fallback) {
+            (None, _) if self.power.stop => PlaybackState::Stopped,
+            (Some(_), _) if self.activity.paused() => PlaybackState::Paused,
+            (Some(_), _) => PlaybackState::Playing,
+            (None, Some(_)) => PlaybackState::Fallback,
+            (None, None) => PlaybackState::Retrying,
+        };
+
+        let status = Status {
+            pid: std::process::id(),
+            wallpaper: self.config.wallpaper.to_string(),
+            state,
+            decoder: self.source.as_ref().and_then(|source| source.decoder()),
+            failures: self.recovery.failures(),
+            throttled: self.activity.reasons(),
+            cpu_level: self.level(),
+            cpu_usage: self.governor.as_ref().and_then(Governor::usage_percent),
+        };
+
+        if status != self.status {
+            if let Err(e) = status.publish() {
+                eprintln!("Error publishing status: {:#}", e);
+            }
+            self.status = status;
+        }
+    }
+
+    fn create_source(&self) -> Result<Box<dyn Source>, anyhow::Error> {
+        Ok(match &self.config.wallpaper {
+            Wallpaper::Video { uri } => {
+                let entry = library::find(uri);
+                if entry.as_ref().map_or(false, |entry| entry.image) {
+                    let path = gst::glib::filename_from_uri(uri)?.0;
+                    return Ok(Box::new(Still::image(&path, self.decode_size())?));
+                }
+
+                // Set when importing the wallpaper from another program
+                let settings = entry.map(|entry| entry.settings).unwrap_or_default();
+                let mut config = self.config.clone();
+                if let Some(volume) = settings.volume {
+                    config.audio.volume = volume;
+                }
+
+                Box::new(Video::new(
+                    self.decode_size(),
+                    uri,
+                    &config,
+                    self.level().favor_hardware(),
+                    settings,
+                )?)
+            }
+            Wallpaper::Scene { path } => Box::new(Scene::open(self.decode_size(), path)?),
+            Wallpaper::Slideshow { path } => {
+                Box::new(Slideshow::open(self.decode_size(), path, SystemClock)?)
+            }
+            Wallpaper::Visualizer(visualizer) => Box::new(Visualizer::new(
+                self.decode_size(),
+                visualizer,
+                self.config.framerate,
+            )?),
+        })
+    }
+
+    /// Drops the source, on a thread of its own once its audio faded out, so that the next one
+    /// can start meanwhile.
+    fn stop_source(&mut self) {
+        let Some(source) = self.source.take() else {
+            return;
+        };
+        let fade = source.fade_out();
+        if fade.is_zero() {
+            return;
+        }
+
+        let spawned = std::thread::Builder::new()
+            .name("awa-fade-out".to_owned())
+            .spawn(move || {
+                std::thread::sleep(fade);
+                drop(source);
+            });
+        if let Err(e) = spawned {
+            eprintln!("Error fading the audio out: {:#}", e);
+        }
+    }
+
+    fn start_source(&mut self) {
+        match self.create_source() {
+            Ok(source) => {
+                // Scaled up to the window when rendering
+                let frame_size = source.frame_size();
+                if frame_size != self.buffer_size {
+                    self.set_buffer_size(frame_size);
+                }
+
+                self.source = Some(source);
+                self.fallback = None;
+                self.recovery.record_start(Instant::now());
+
+                if self.power.mute {
+                    if let Some(source) = &mut self.source {
+                        source.set_muted(true);
+                    }
+                }
+                if self.activity.paused() {
+                    self.apply_pause();
+                }
+                if self.activity.framerate().is_some() {
+                    self.apply_framerate();
+                }
+                self.optimize();
+            }
+            Err(e) => self.fail_source(e),
+        }
+    }
+
+    /// Starts making the optimized copy of a local clip for the current decode size, if missing.
+    /// Not while over the CPU budget, which it would only make worse.
+    fn optimize(&mut self) {
+        let Wallpaper::Video { uri } = &self.config.wallpaper else {
+            return;
+        };
+        let size = self.decode_size();
+        if !self.config.decode.optimize
+            || self.level() > Level::Full
+            || self.optimize_job.is_some()
+            || self.optimize_failures.contains(&(uri.clone(), size))
+            || !uri.starts_with("file://")
+            || cache::has_optimized(uri, size)
+            // Images aren't played as videos, cached copies are cropped in the middle
+            || library::find(uri).map_or(false, |entry| {
+                entry.image || entry.settings.position.unwrap_or_default() != Position::Center
+            })
+        {
+            return;
+        }
+
+        let audio = !(self.config.audio.muted || self.power.mute);
+        match optimize::Job::spawn(uri.clone(), size, audio) {
+            Ok(job) => self.optimize_job = Some(job),
+            Err(e) => eprintln!("Error starting to optimize {}: {:#}", uri, e),
+        }
+    }
+
+    fn fail_source(&mut self, error: anyhow::Error) {
+        eprintln!("Error playing {}: {:#}", self.config.wallpaper, error);
+
+        // Stops the pipeline once faded out
+        self.stop_source();
+
+        if let Some(VideoError::Hardware { factory, .. }) = error.downcast_ref::<VideoError>() {
+            // Not the wallpaper's fault, rebuild it right away with software decoding
+            eprintln!(
+                "Disabling {} and falling back to software decoding",
+                factory
+            );
+            match decoder::disable(factory) {
+                // Only once per decoder, a decoder failing again goes through the backoff
+                Ok(true) => {
+                    self.start_source();
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Error recording broken decoder {}: {:#}", factory, e),
+            }
+        }
+
+        let backoff = self.recovery.record_failure(Instant::now());
+        eprintln!(
+            "Retrying in {:.1}s (failure {})",
+            backoff.as_secs_f64(),
+            self.recovery.failures()
+        );
+
+        if self.recovery.exhausted() && self.fallback.is_none() {
+            self.show_fallback();
+        }
+    }
+
+    fn show_fallback(&mut self) {
+        let fallback = Still::new(&self.config.fallback, self.buffer_size).unwrap_or_else(|e| {
+            eprintln!("Error loading fallback: {:#}", e);
+            Still::new(&Default::default(), self.buffer_size)
+                .expect("Solid color fallback can't fail")
+        });
+
+        self.fallback = Some(fallback);
     }
 
     pub(crate) async fn update_surface_size<S>(&mut self, size: S) -> Result<(), anyhow::Error>
@@ -53,8 +522,16 @@ impl AppInner {
         S: Into<PhysicalSize<u32>>,
     {
         let size: PhysicalSize<u32> = size.into();
+        // Sources started from now on are decoded at the new size
+        self.window_size = size;
 
-        self.video.update_surface_size(size)?;
+        if let Some(Err(e)) = self
+            .source
+            .as_mut()
+            .map(|source| source.update_surface_size(size))
+        {
+            self.fail_source(e);
+        }
         self.pixels
             .resize_surface(size.width, size.height)
             .map_err(anyhow::Error::from)
@@ -68,13 +545,24 @@ impl AppInner {
     #[inline]
     pub(crate) async fn handle_input<'e, T>(&mut self, event: &Event<'e, T>) {
         if self.input_helper.update(event) {
+            let size = self.window_size;
+            let pointer = self.input_helper.cursor().map(|(x, y)| {
+                [
+                    x / size.width.max(1) as f32 * 2. - 1.,
+                    1. - y / size.height.max(1) as f32 * 2.,
+                ]
+            });
+            if let Some(source) = &mut self.source {
+                source.set_pointer(pointer);
+            }
+
             self.update();
         }
     }
 }
 
 impl App {
-    pub(crate) fn new(window: &Window) -> Self {
+    pub(crate) fn new(window: &Window, config: Config) -> Self {
         let size = window.inner_size();
         let surface_texture = SurfaceTexture::new(size.width, size.height, window);
 
@@ -88,18 +576,57 @@ impl App {
             })
             .build()
             .expect("Failed to create pixels object");
-        let video = Video::new(size).unwrap();
+
+        let mut inner = AppInner {
+            input_helper: WinitInputHelper::new(),
+            pixels,
+            buffer_size: size,
+            window_size: size,
+            recovery: Recovery::new(config.recovery.clone()),
+            optimize_job: None,
+            optimize_failures: HashSet::new(),
+            modulator: config
+                .reactive
+                .clone()
+                .map(|reactive| Modulator::new(reactive, size)),
+            activity: Activity::default(),
+            coverage: Self::watch_coverage(window, &config),
+            presence: Self::watch_presence(&config),
+            governor: config.cpu_budget.as_ref().map(Governor::new),
+            power: Restrictions::default(),
+            next_power_poll: (!config.power.profiles.is_empty()).then(Instant::now),
+            config,
+            source: None,
+            fallback: None,
+            status: Status::default(),
+            scale_factor: window.scale_factor(),
+        };
+        inner.start_source();
 
         Self {
-            _inner: Arc::new(Mutex::new(AppInner {
-                input_helper: WinitInputHelper::new(),
-                pixels,
-                video,
-                scale_factor: window.scale_factor(),
-            })),
+            _inner: Arc::new(Mutex::new(inner)),
         }
     }
 
+    fn watch_coverage(window: &Window, config: &Config) -> Option<Coverage> {
+        config.covered.throttle()?;
+        let area = MonitorArea::of(window)?;
+
+        Coverage::spawn(area, config.covered.poll_interval())
+            .map_err(|e| eprintln!("Error watching windows, not pausing when covered: {:#}", e))
+            .ok()
+    }
+
+    fn watch_presence(config: &Config) -> Option<Presence> {
+        if config.idle.timeout_s.is_none() && !config.idle.display_off {
+            return None;
+        }
+
+        Presence::spawn(config.idle.poll_interval())
+            .map_err(|e| eprintln!("Error watching idle time, not pausing when idle: {:#}", e))
+            .ok()
+    }
+
     #[inline]
     async fn inner(&self) -> tokio::sync::MutexGuard<'_, AppInner> {
         self._inner.lock().await
@@ -115,6 +642,40 @@ impl App {
         self.inner().await.update();
     }
 
+    #[inline]
+    pub(crate) async fn throttle(&self) -> Option<Throttle> {
+        self.inner().await.throttle()
+    }
+
+    /// Pauses the wallpaper for `reason`, or resumes it once no other reason is left.
+    pub(crate) async fn pause(&self, reason: Reason, paused: bool) {
+        let mut inner = self.inner().await;
+        let throttle = match reason {
+            Reason::Locked | Reason::Sleeping if !inner.config.idle.lock => None,
+            _ => paused.then_some(Throttle::Pause),
+        };
+        inner.set_throttle(reason, throttle);
+    }
+
+    /// Pauses the wallpaper (if configured so) while the window system reports it occluded.
+    pub(crate) async fn set_occluded(&self, occluded: bool) {
+        let mut inner = self.inner().await;
+        let throttle = inner.config.covered.throttle().filter(|_| occluded);
+        inner.set_throttle(Reason::Occluded, throttle);
+    }
+
+    /// Fades the audio out before exiting.
+    pub(crate) async fn shutdown(&self) {
+        let fade = self
+            .inner()
+            .await
+            .source
+            .as_ref()
+            .map_or(Duration::ZERO, |source| source.fade_out());
+
+        tokio::time::sleep(fade).await;
+    }
+
     #[inline]
     pub(crate) async fn update_surface_size<S>(&self, size: S) -> Result<(), anyhow::Error>
     where
@@ -1 +1,252 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use cpal::traits::{DeviceTrait, HostTrait};
+use gst::{prelude::*, ElementFactory, GhostPad};
+use gst_app::AppSinkCallbacks;
+
+use crate::config::AudioConfig;
+
+mod ducking;
+mod gain;
+mod output;
+
+use ducking::Ducker;
+use gain::Gain;
+use output::Output;
+
+/// How much decoded audio is kept ahead of the output before the oldest is dropped
+const MAX_QUEUED: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    pub(crate) rate: u32,
+    pub(crate) channels: u16,
+}
+
+/// State shared between the GStreamer streaming thread and the output thread
+pub(crate) struct Shared {
+    format: Format,
+
+    /// Interleaved `f32` samples
+    queue: Mutex<VecDeque<f32>>,
+    gain: Mutex<Gain>,
+}
+
+impl Shared {
+    fn push(&self, samples: &[f32]) {
+        let max = (MAX_QUEUED.as_secs_f64() * self.format.rate as f64 * self.format.channels as f64)
+            as usize;
+
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(samples);
+
+        if queue.len() > max {
+            // Drop whole frames so that the channels stay in place
+            let channels = self.format.channels.max(1) as usize;
+            let excess = queue.len() - max;
+            let excess = excess + (channels - excess % channels) % channels;
+            queue.drain(..excess.min(queue.len()));
+        }
+    }
+
+    /// Fills `buffer` with queued samples, silence on underrun, and applies the gain.
+    pub(crate) fn read(&self, buffer: &mut [f32]) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            let available = queue.len().min(buffer.len());
+
+            for (out, sample) in buffer.iter_mut().zip(queue.drain(..available)) {
+                *out = sample;
+            }
+            buffer[available..].fill(0.);
+        }
+
+        self.gain
+            .lock()
+            .unwrap()
+            .apply(buffer, self.format.channels as usize);
+    }
+}
+
+/// Audio of a wallpaper, played on the configured output device.
+pub(crate) struct Audio {
+    shared: Arc<Shared>,
+    fade: Duration,
+
+    _output: Output,
+    _ducker: Option<Ducker>,
+}
+
+impl Audio {
+    /// Opens the output and starts fading in.
+    pub(crate) fn new(config: &AudioConfig) -> Result<Self, anyhow::Error> {
+        let device = config.device.as_deref();
+        let format = Output::format(device)?;
+
+        let fade = config.fade();
+        let mut gain = Gain::new(config.volume, config.muted);
+        gain.fade_to(1., fade, format.rate);
+
+        let shared = Arc::new(Shared {
+            format,
+            queue: Mutex::new(VecDeque::new()),
+            gain: Mutex::new(gain),
+        });
+
+        Ok(Self {
+            _output: Output::open(device, shared.clone())?,
+            _ducker: Ducker::spawn(&config.ducking, shared.clone())?,
+            shared,
+            fade,
+        })
+    }
+
+    /// Starts fading out, returns how long it takes.
+    pub(crate) fn fade_out(&self) -> Duration {
+        self.shared
+            .gain
+            .lock()
+            .unwrap()
+            .fade_to(0., self.fade, self.shared.format.rate);
+        self.fade
+    }
+
+    /// Fades out to silence, or back in.
+    pub(crate) fn set_muted(&self, muted: bool) {
+        let target = if muted { 0. } else { 1. };
+        self.shared
+            .gain
+            .lock()
+            .unwrap()
+            .fade_to(target, self.fade, self.shared.format.rate);
+    }
+
+    /// Sink for `playbin`'s `audio-sink` which feeds this output
+    pub(crate) fn sink(&self) -> Result<gst::Element, anyhow::Error> {
+        // audioconvert ! audioresample ! appsink caps=audio/x-raw,format=F32LE,layout=interleaved,...
+        let bin = gst::Bin::builder().name("audiosinkbin").build();
+
+        let audioconvert = ElementFactory::make("audioconvert").build()?;
+        let audioresample = ElementFactory::make("audioresample").build()?;
+
+        let caps = gst_audio::AudioCapsBuilder::new_interleaved()
+            .format(gst_audio::AUDIO_FORMAT_F32)
+            .rate(self.shared.format.rate as i32)
+            .channels(self.shared.format.channels as i32)
+            .build();
+
+        let shared = self.shared.clone();
+        let appsink = gst_app::AppSink::builder()
+            .caps(&caps)
+            .sync(true)
+            .callbacks(
+                AppSinkCallbacks::builder()
+                    .new_sample(move |appsink| {
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        let buffer = sample.buffer().ok_or(gst::FlowError::Error)?;
+                        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+
+                        let samples = map
+                            .chunks_exact(4)
+                            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                            .collect::<Vec<_>>();
+                        shared.push(&samples);
+
+                        Ok(gst::FlowSuccess::Ok)
+                    })
+                    .build(),
+            )
+            .build()
+            .upcast::<gst::Element>();
+
+        bin.add_many([&audioconvert, &audioresample, &appsink])?;
+        gst::Element::link_many([&audioconvert, &audioresample, &appsink])?;
+
+        let pad = audioconvert.static_pad("sink").unwrap();
+        let ghost_pad = GhostPad::builder_with_target(&pad)?.build();
+        ghost_pad.set_active(true)?;
+        bin.add_pad(&ghost_pad)?;
+
+        Ok(bin.upcast())
+    }
+}
+
+/// `awa devices`
+pub(crate) fn print_devices() -> Result<(), anyhow::Error> {
+    let host = cpal::default_host();
+    let default = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    println!("Audio output devices ({:?}):", host.id());
+    for device in host.output_devices()? {
+        let name = device.name()?;
+        let format = device
+            .default_output_config()
+            .map(|config| {
+                format!(
+                    "{} Hz, {} channel(s)",
+                    config.sample_rate().0,
+                    config.channels()
+                )
+            })
+            .unwrap_or_else(|e| format!("unusable: {}", e));
+
+        let marker = if Some(&name) == default.as_ref() {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {} ({})", marker, name, format);
+    }
+    println!("  {} (discards the audio)", output::NULL_DEVICE);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Whether `condition` holds within a second
+    fn eventually(audio: &Audio, condition: impl Fn(&Shared) -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            if condition(&audio.shared) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    fn gain(shared: &Shared) -> f32 {
+        shared.gain.lock().unwrap().current()
+    }
+
+    #[test]
+    fn fades_on_the_null_device() {
+        let audio = Audio::new(&AudioConfig {
+            device: Some(output::NULL_DEVICE.to_owned()),
+            fade_ms: 100,
+            ..AudioConfig::default()
+        })
+        .unwrap();
+        assert!(gain(&audio.shared) < 1.);
+
+        // Ramped as the device plays
+        assert!(eventually(&audio, |shared| gain(shared) == 1.));
+        audio.shared.push(&[1.; 4800]);
+        let drained = |shared: &Shared| shared.queue.lock().unwrap().is_empty();
+        assert!(eventually(&audio, drained));
+
+        assert_eq!(audio.fade_out(), Duration::from_millis(100));
+        assert!(eventually(&audio, |shared| gain(shared) == 0.));
+    }
+}
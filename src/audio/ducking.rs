@@ -0,0 +1,200 @@
+use std::{
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+use crate::config::{DuckingConfig, DuckingMode};
+
+use super::Shared;
+
+/// Watches the other audio streams of the system through `pactl`, which talks to PulseAudio and
+/// PipeWire alike, and lowers the wallpaper's audio while any of them plays.
+pub(crate) struct Ducker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Ducker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Ducker {
+    /// `None` if ducking is off.
+    pub(crate) fn spawn(
+        config: &DuckingConfig,
+        shared: Arc<Shared>,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        let level = match config.mode {
+            DuckingMode::Off => return Ok(None),
+            DuckingMode::Duck => config.level,
+            DuckingMode::Mute => 0.,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_ref = stop.clone();
+        let config = config.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("awa-ducking".to_owned())
+            .spawn(move || {
+                let own_pid = std::process::id();
+                let mut ducked = false;
+
+                while !stop_ref.load(Ordering::Acquire) {
+                    let active = match sink_inputs() {
+                        Ok(output) => active_streams(&output, own_pid) > 0,
+                        Err(e) => {
+                            eprintln!("Error watching audio streams, ducking disabled: {:#}", e);
+                            break;
+                        }
+                    };
+
+                    if active != ducked {
+                        ducked = active;
+
+                        let rate = shared.format.rate;
+                        let target = if ducked { level } else { 1. };
+                        shared
+                            .gain
+                            .lock()
+                            .unwrap()
+                            .duck_to(target, config.fade(), rate);
+                    }
+
+                    std::thread::park_timeout(config.poll_interval());
+                }
+            })?;
+
+        Ok(Some(Self {
+            stop,
+            handle: Some(handle),
+        }))
+    }
+}
+
+fn sink_inputs() -> Result<String, anyhow::Error> {
+    let output = Command::new("pactl")
+        .args(["list", "sink-inputs"])
+        .env("LC_ALL", "C")
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run pactl: {}", e))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "pactl failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Counts the playing, unmuted sink inputs in `pactl list sink-inputs` output which don't belong
+/// to `own_pid`.
+fn active_streams(output: &str, own_pid: u32) -> usize {
+    #[derive(Default)]
+    struct SinkInput {
+        corked: bool,
+        muted: bool,
+        pid: Option<u32>,
+    }
+
+    let mut inputs = Vec::new();
+    for line in output.lines() {
+        let line = line.trim();
+
+        if line.starts_with("Sink Input #") {
+            inputs.push(SinkInput::default());
+            continue;
+        }
+        let Some(input) = inputs.last_mut() else {
+            continue;
+        };
+
+        if let Some(value) = line.strip_prefix("Corked:") {
+            input.corked = value.trim() == "yes";
+        } else if let Some(value) = line.strip_prefix("Mute:") {
+            input.muted = value.trim() == "yes";
+        } else if let Some(value) = line.strip_prefix("application.process.id = ") {
+            input.pid = value.trim_matches('"').parse().ok();
+        }
+    }
+
+    inputs
+        .iter()
+        .filter(|input| !input.corked && !input.muted && input.pid != Some(own_pid))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sink input as `pactl list sink-inputs` prints it
+    fn sink_input(index: u32, corked: bool, muted: bool, pid: u32) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        format!(
+            "Sink Input #{index}
+\tDriver: protocol-native.c
+\tOwner Module: 10
+\tClient: 42
+\tSink: 0
+\tSample Specification: float32le 2ch 48000Hz
+\tChannel Map: front-left,front-right
+\tFormat: pcm, format.sample_format = \"\\\"float32le\\\"\"
+\tCorked: {corked}
+\tMute: {muted}
+\tVolume: front-left: 65536 / 100% / 0.00 dB,   front-right: 65536 / 100% / 0.00 dB
+\t        balance 0.00
+\tBuffer Latency: 0 usec
+\tSink Latency: 0 usec
+\tResample method: n/a
+\tProperties:
+\t\tapplication.name = \"Firefox\"
+\t\tapplication.process.id = \"{pid}\"
+\t\tapplication.process.binary = \"firefox\"
+\t\tmedia.name = \"AudioStream\"
+
+",
+            corked = yes_no(corked),
+            muted = yes_no(muted),
+        )
+    }
+
+    const OWN_PID: u32 = 1000;
+
+    #[test]
+    fn counts_no_streams() {
+        assert_eq!(active_streams("", OWN_PID), 0);
+    }
+
+    #[test]
+    fn counts_playing_streams() {
+        let output = sink_input(1, false, false, 2000) + &sink_input(2, false, false, 3000);
+        assert_eq!(active_streams(&output, OWN_PID), 2);
+    }
+
+    #[test]
+    fn excludes_own_streams() {
+        let output = sink_input(1, false, false, OWN_PID) + &sink_input(2, false, false, 2000);
+        assert_eq!(active_streams(&output, OWN_PID), 1);
+    }
+
+    #[test]
+    fn excludes_corked_and_muted_streams() {
+        let output = sink_input(1, true, false, 2000)
+            + &sink_input(2, false, true, 3000)
+            + &sink_input(3, false, false, 4000);
+        assert_eq!(active_streams(&output, OWN_PID), 1);
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+/// Multiplier from 0 to 1, moving linearly towards its target frame by frame
+#[derive(Debug, Clone)]
+struct Ramp {
+    value: f32,
+    target: f32,
+    /// Change of `value` per frame
+    step: f32,
+}
+
+impl Ramp {
+    #[inline]
+    fn new(value: f32) -> Self {
+        Self {
+            value,
+            target: value,
+            step: 0.,
+        }
+    }
+
+    fn ramp_to(&mut self, target: f32, duration: Duration, rate: u32) {
+        self.target = target.clamp(0., 1.);
+
+        let frames = duration.as_secs_f32() * rate as f32;
+        if frames < 1. {
+            self.value = self.target;
+            self.step = 0.;
+        } else {
+            self.step = (self.target - self.value).abs() / frames;
+        }
+    }
+
+    fn step(&mut self) {
+        if self.value < self.target {
+            self.value = (self.value + self.step).min(self.target);
+        } else if self.value > self.target {
+            self.value = (self.value - self.step).max(self.target);
+        }
+    }
+}
+
+/// Volume, mute, fades and ducking of a wallpaper's audio, applied frame by frame.
+#[derive(Debug, Clone)]
+pub(crate) struct Gain {
+    volume: f32,
+    muted: bool,
+
+    fade: Ramp,
+    /// Lowered while other applications play sound
+    duck: Ramp,
+}
+
+impl Gain {
+    /// Starts silent, see `fade_to`.
+    pub(crate) fn new(volume: f32, muted: bool) -> Self {
+        Self {
+            volume: volume.clamp(0., 1.),
+            muted,
+            fade: Ramp::new(0.),
+            duck: Ramp::new(1.),
+        }
+    }
+
+    /// Ramps the fade multiplier linearly to `target` over `duration`.
+    #[inline]
+    pub(crate) fn fade_to(&mut self, target: f32, duration: Duration, rate: u32) {
+        self.fade.ramp_to(target, duration, rate);
+    }
+
+    /// Ramps the ducking multiplier linearly to `target` over `duration`, 1 to restore.
+    #[inline]
+    pub(crate) fn duck_to(&mut self, target: f32, duration: Duration, rate: u32) {
+        self.duck.ramp_to(target, duration, rate);
+    }
+
+    /// Current multiplier of the samples
+    #[inline]
+    pub(crate) fn current(&self) -> f32 {
+        if self.muted {
+            0.
+        } else {
+            self.volume * self.fade.value * self.duck.value
+        }
+    }
+
+    /// Scales interleaved `samples` of `channels` channels.
+    pub(crate) fn apply(&mut self, samples: &mut [f32], channels: usize) {
+        for frame in samples.chunks_mut(channels.max(1)) {
+            self.fade.step();
+            self.duck.step();
+
+            let gain = self.current();
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample,
+};
+
+use super::{Format, Shared};
+
+/// Name of the device which discards the samples at the pace of a real one
+pub(crate) const NULL_DEVICE: &str = "null";
+
+const NULL_FORMAT: Format = Format {
+    rate: 48000,
+    channels: 2,
+};
+
+/// Thread owning the output stream, since `cpal::Stream` isn't `Send` on every platform.
+pub(crate) struct Output {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Output {
+    /// Format of the output `device`, the default one if `None`
+    pub(crate) fn format(device: Option<&str>) -> Result<Format, anyhow::Error> {
+        if device == Some(NULL_DEVICE) {
+            return Ok(NULL_FORMAT);
+        }
+
+        let config = find_device(device)?.default_output_config()?;
+        Ok(Format {
+            rate: config.sample_rate().0,
+            channels: config.channels(),
+        })
+    }
+
+    /// Starts playing the samples queued in `shared` on `device`.
+    pub(crate) fn open(device: Option<&str>, shared: Arc<Shared>) -> Result<Self, anyhow::Error> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_ref = stop.clone();
+
+        let handle = if device == Some(NULL_DEVICE) {
+            std::thread::Builder::new()
+                .name("awa-audio-null".to_owned())
+                .spawn(move || run_null(shared, stop_ref))?
+        } else {
+            let device = device.map(str::to_owned);
+            let (init_tx, init_rx) = mpsc::channel();
+
+            let handle = std::thread::Builder::new()
+                .name("awa-audio".to_owned())
+                .spawn(move || match open_stream(device.as_deref(), shared) {
+                    Ok(stream) => {
+                        let _ = init_tx.send(Ok(()));
+                        while !stop_ref.load(Ordering::Acquire) {
+                            std::thread::park_timeout(Duration::from_millis(100));
+                        }
+                        drop(stream);
+                    }
+                    Err(e) => {
+                        let _ = init_tx.send(Err(e));
+                    }
+                })?;
+
+            init_rx
+                .recv()
+                .map_err(|_| anyhow::anyhow!("Audio thread exited"))??;
+            handle
+        };
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+fn find_device(name: Option<&str>) -> Result<cpal::Device, anyhow::Error> {
+    let host = cpal::default_host();
+
+    if let Some(name) = name {
+        let device = host
+            .output_devices()?
+            .find(|device| device.name().map_or(false, |n| n == name));
+        if let Some(device) = device {
+            return Ok(device);
+        }
+        eprintln!("Audio device {:?} not found, using the default one", name);
+    }
+
+    host.default_output_device()
+        .ok_or_else(|| anyhow::anyhow!("No audio output device"))
+}
+
+fn open_stream(name: Option<&str>, shared: Arc<Shared>) -> Result<cpal::Stream, anyhow::Error> {
+    let device = find_device(name)?;
+    let supported = device.default_output_config()?;
+    let config = supported.config();
+
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, shared),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, shared),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, shared),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, shared),
+        format => return Err(anyhow::anyhow!("Unsupported sample format {:?}", format)),
+    }?;
+    stream.play()?;
+
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    shared: Arc<Shared>,
+) -> Result<cpal::Stream, anyhow::Error>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut buffer = Vec::new();
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            buffer.resize(data.len(), 0.);
+            shared.read(&mut buffer);
+
+            for (out, sample) in data.iter_mut().zip(&buffer) {
+                *out = T::from_sample(*sample);
+            }
+        },
+        |e| eprintln!("Error playing audio: {}", e),
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+fn run_null(shared: Arc<Shared>, stop: Arc<AtomicBool>) {
+    let mut buffer = Vec::new();
+    let mut previous = Instant::now();
+
+    while !stop.load(Ordering::Acquire) {
+        std::thread::park_timeout(Duration::from_millis(10));
+
+        let now = Instant::now();
+        let frames = (now - previous).as_secs_f64() * NULL_FORMAT.rate as f64;
+        previous = now;
+
+        buffer.resize(frames as usize * NULL_FORMAT.channels as usize, 0.);
+        shared.read(&mut buffer);
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use gst::{prelude::*, ElementFactory};
+use winit::dpi::PhysicalSize;
+
+use crate::{paths, platform_specific};
+
+pub(crate) mod optimize;
+
+/// How often a running encoding checks whether it should stop
+const STOP_POLL: gst::ClockTime = gst::ClockTime::from_mseconds(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Codec {
+    H264,
+    H265,
+    Vp9,
+    Av1,
+}
+
+impl Codec {
+    pub(crate) const ALL: [Codec; 4] = [Self::H264, Self::H265, Self::Vp9, Self::Av1];
+
+    pub(crate) fn caps(self) -> gst::Caps {
+        match self {
+            Self::H264 => gst::Caps::builder("video/x-h264").build(),
+            Self::H265 => gst::Caps::builder("video/x-h265").build(),
+            Self::Vp9 => gst::Caps::builder("video/x-vp9").build(),
+            Self::Av1 => gst::Caps::builder("video/x-av1").build(),
+        }
+    }
+
+    /// MP4 with AAC, or WebM with Opus for VP9
+    fn container(self) -> (gst::Caps, gst::Caps, &'static str) {
+        match self {
+            Self::Vp9 => (
+                gst::Caps::builder("video/webm").build(),
+                gst::Caps::builder("audio/x-opus").build(),
+                "webm",
+            ),
+            _ => (
+                gst::Caps::builder("video/quicktime")
+                    .field("variant", "iso")
+                    .build(),
+                gst::Caps::builder("audio/mpeg")
+                    .field("mpegversion", 4i32)
+                    .build(),
+                "mp4",
+            ),
+        }
+    }
+}
+
+/// What a variant is encoded with
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Encoding {
+    pub(crate) size: PhysicalSize<u32>,
+    pub(crate) codec: Codec,
+    /// Frames between keyframes, the encoder's default if `None`
+    pub(crate) gop: Option<u32>,
+    pub(crate) audio: bool,
+    /// Made by `awa optimize`, preferred over the plain variants
+    pub(crate) optimized: bool,
+}
+
+impl Encoding {
+    /// Resolution-matched copy, as made by `awa cache`
+    pub(crate) fn scaled(size: PhysicalSize<u32>) -> Self {
+        Self {
+            size,
+            codec: Codec::H264,
+            gop: None,
+            audio: true,
+            optimized: false,
+        }
+    }
+}
+
+/// Re-encoded copies of clips under `$XDG_CACHE_HOME/awa/variants/<key>/`, one directory per
+/// source file, named `<width>x<height>[.optimized].<mp4|webm>`.
+#[inline]
+pub(crate) fn variants_dir() -> PathBuf {
+    paths::cache_dir().join("variants")
+}
+
+/// Start of `hash_more`
+const HASH_START: u64 = 0xcbf29ce484222325;
+
+/// 64-bit FNV-1a, stable across runs and builds unlike `DefaultHasher`
+#[inline]
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    hash_more(HASH_START, bytes)
+}
+
+/// Continues `hash` with more bytes, for data read in chunks
+fn hash_more(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// `hash` of the whole file at `path`
+pub(crate) fn hash_file(path: &Path) -> Result<u64, anyhow::Error> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    hash_read(HASH_START, file)
+}
+
+/// `hash` of the size, first and last `len` bytes of the file at `path`, which tell media files
+/// apart without reading all of them
+pub(crate) fn hash_ends(path: &Path, len: u64) -> Result<u64, anyhow::Error> {
+    let mut file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let size = file.metadata()?.len();
+
+    let hash = hash_more(HASH_START, &size.to_le_bytes());
+    let hash = hash_read(hash, (&mut file).take(len))?;
+    // Containers often keep their index at the end
+    file.seek(SeekFrom::Start(size.saturating_sub(len).max(len)))?;
+    hash_read(hash, file.take(len))
+}
+
+/// Continues `hash` with everything `reader` reads
+fn hash_read(mut hash: u64, mut reader: impl Read) -> Result<u64, anyhow::Error> {
+    let mut buf = vec![0; 1 << 20];
+
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        hash = hash_more(hash, &buf[..read]);
+    }
+}
+
+/// Directory of the variants of the local file at `uri`, `None` for other URIs. Changes with the
+/// file's size and modification time, so that stale variants are never played.
+pub(crate) fn key_dir(uri: &str) -> Option<PathBuf> {
+    let path = gst::glib::filename_from_uri(uri).ok()?.0;
+    let (len, modified) = paths::stamp(&path)?;
+
+    let key = format!("{}\0{}\0{}", path.display(), len, modified.as_nanos());
+    Some(variants_dir().join(format!("{:016x}", hash(key.as_bytes()))))
+}
+
+fn variant_path(dir: &Path, encoding: &Encoding) -> PathBuf {
+    let (_, _, extension) = encoding.codec.container();
+    let tag = if encoding.optimized { ".optimized" } else { "" };
+
+    dir.join(format!(
+        "{}x{}{}.{}",
+        encoding.size.width, encoding.size.height, tag, extension
+    ))
+}
+
+/// Size of a variant and whether it is optimized, from its file name
+fn parse_name(path: &Path) -> Option<(PhysicalSize<u32>, bool)> {
+    let extension = path.extension()?;
+    if extension != "mp4" && extension != "webm" {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    let (size, optimized) = match stem.strip_suffix(".optimized") {
+        Some(size) => (size, true),
+        None => (stem, false),
+    };
+    let (width, height) = size.split_once('x')?;
+    Some((
+        PhysicalSize::new(width.parse().ok()?, height.parse().ok()?),
+        optimized,
+    ))
+}
+
+/// Smallest variant of `uri` at least as large as `size` with the same aspect ratio, optimized
+/// ones first.
+pub(crate) fn lookup(uri: &str, size: PhysicalSize<u32>) -> Option<PathBuf> {
+    variants(uri, size)
+        .into_iter()
+        .min_by_key(|(variant, optimized, _)| {
+            (variant.width as u64 * variant.height as u64, !optimized)
+        })
+        .map(|(_, _, path)| path)
+}
+
+/// Whether `awa optimize` already made a variant of `uri` for `size`
+pub(crate) fn has_optimized(uri: &str, size: PhysicalSize<u32>) -> bool {
+    variants(uri, size)
+        .iter()
+        .any(|(_, optimized, _)| *optimized)
+}
+
+/// Variants of `uri` at least as large as `size` with the same aspect ratio
+fn variants(uri: &str, size: PhysicalSize<u32>) -> Vec<(PhysicalSize<u32>, bool, PathBuf)> {
+    let Some(entries) = key_dir(uri).and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter_map(|path| {
+            let (size, optimized) = parse_name(&path)?;
+            Some((size, optimized, path))
+        })
+        .filter(|(variant, _, _)| {
+            variant.width >= size.width
+                && variant.height >= size.height
+                // Same aspect ratio, give or take the rounding
+                && (variant.width as u64 * size.height as u64)
+                    .abs_diff(variant.height as u64 * size.width as u64)
+                    <= variant.width.max(variant.height) as u64
+        })
+        .collect()
+}
+
+/// Encodes `uri` cropped and scaled to `encoding.size`, next to its other variants. Gives up when
+/// `stop` is set.
+pub(crate) fn generate(
+    uri: &str,
+    encoding: &Encoding,
+    stop: &AtomicBool,
+) -> Result<PathBuf, anyhow::Error> {
+    gst::init()?;
+
+    let dir = key_dir(uri)
+        .ok_or_else(|| anyhow::anyhow!("Only local files can be cached, not {}", uri))?;
+    std::fs::create_dir_all(&dir)?;
+    let path = variant_path(&dir, encoding);
+    // Never played half written
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    // uridecodebin uri={uri} ! aspectratiocrop ! videoconvertscale ! {caps} ! encodebin ! filesink
+    //                        ! audioconvert ! audioresample ! encodebin.
+    let pipeline = gst::Pipeline::new();
+
+    let uridecodebin = ElementFactory::make("uridecodebin")
+        .property("uri", uri)
+        .build()?;
+    let encodebin = ElementFactory::make("encodebin")
+        .property("profile", &profile(encoding))
+        .build()?;
+    if let Some(gop) = encoding.gop {
+        encodebin.connect_deep_element_added(move |_, _, element| {
+            set_short_gop(element, gop);
+        });
+    }
+    let filesink = ElementFactory::make("filesink")
+        .property("location", &tmp)
+        .build()?;
+
+    pipeline.add_many([&uridecodebin, &encodebin, &filesink])?;
+    encodebin.link(&filesink)?;
+
+    let link_error = Arc::new(Mutex::new(None));
+    let link_error_ref = link_error.clone();
+    let pipeline_weak = pipeline.downgrade();
+    let (size, audio) = (encoding.size, encoding.audio);
+    uridecodebin.connect_pad_added(move |_, pad| {
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            return;
+        };
+        if let Err(e) = link_decoded_pad(&pipeline, &encodebin, pad, size, audio) {
+            *link_error_ref.lock().unwrap() = Some(e);
+        }
+    });
+
+    let result = run_to_eos(&pipeline, stop);
+    let _ = pipeline.set_state(gst::State::Null);
+
+    if let Some(e) = link_error.lock().unwrap().take() {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+
+    std::fs::rename(&tmp, &path)?;
+    Ok(path)
+}
+
+fn profile(encoding: &Encoding) -> gst_pbutils::EncodingContainerProfile {
+    let (container, audio_caps, _) = encoding.codec.container();
+
+    let video = gst_pbutils::EncodingVideoProfile::builder(&encoding.codec.caps())
+        .presence(1)
+        .build();
+    let builder = gst_pbutils::EncodingContainerProfile::builder(&container)
+        .name("awa")
+        .add_profile(video);
+
+    if encoding.audio {
+        let audio = gst_pbutils::EncodingAudioProfile::builder(&audio_caps).build();
+        builder.add_profile(audio).build()
+    } else {
+        builder.build()
+    }
+}
+
+/// Keyframe interval properties of the common encoders, e.g. `x264enc`'s `key-int-max`
+const GOP_PROPERTIES: &[&str] = &[
+    "key-int-max",
+    "keyframe-period",
+    "gop-size",
+    "keyframe-max-dist",
+    "max-key-frame-interval",
+    "intra-period-length",
+];
+
+/// B-frame count properties of the common encoders
+const B_FRAME_PROPERTIES: &[&str] = &["bframes", "max-bframes", "b-frames"];
+
+/// Keyframes every `gop` frames without B-frames, which makes looping and seeking cheap.
+fn set_short_gop(element: &gst::Element, gop: u32) {
+    let is_encoder = element
+        .factory()
+        .and_then(|factory| {
+            factory
+                .metadata(gst::ELEMENT_METADATA_KLASS)
+                .map(str::to_owned)
+        })
+        .map_or(false, |klass| {
+            klass.contains("Encoder") && klass.contains("Video")
+        });
+    if !is_encoder {
+        return;
+    }
+
+    for (properties, value) in [(GOP_PROPERTIES, gop), (B_FRAME_PROPERTIES, 0)] {
+        for name in properties {
+            let Some(spec) = element.find_property(name) else {
+                continue;
+            };
+
+            // The same idea comes with different integer types
+            let value = match spec.value_type() {
+                t if t == u32::static_type() => value.to_value(),
+                t if t == i32::static_type() => (value as i32).to_value(),
+                t if t == u64::static_type() => (value as u64).to_value(),
+                t if t == i64::static_type() => (value as i64).to_value(),
+                _ => continue,
+            };
+            element.set_property_from_value(name, &value);
+        }
+    }
+}
+
+/// Links a decoded stream of `uridecodebin` to `encodebin`, through scaling for video.
+fn link_decoded_pad(
+    pipeline: &gst::Pipeline,
+    encodebin: &gst::Element,
+    pad: &gst::Pad,
+    size: PhysicalSize<u32>,
+    audio: bool,
+) -> Result<(), anyhow::Error> {
+    let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+    let Some(name) = caps.structure(0).map(|s| s.name().to_string()) else {
+        return Ok(());
+    };
+
+    let elements = if name.starts_with("video/") {
+        vec![
+            ElementFactory::make("aspectratiocrop")
+                .property(
+                    "aspect-ratio",
+                    gst::Fraction::new(size.width as _, size.height as _),
+                )
+                .build()?,
+            ElementFactory::make("videoconvertscale").build()?,
+            ElementFactory::make("capsfilter")
+                .property(
+                    "caps",
+                    gst_video::VideoCapsBuilder::new()
+                        .width(size.width as _)
+                        .height(size.height as _)
+                        .pixel_aspect_ratio(gst::Fraction::new(1, 1))
+                        .build(),
+                )
+                .build()?,
+        ]
+    } else if name.starts_with("audio/") && audio {
+        vec![
+            ElementFactory::make("audioconvert").build()?,
+            ElementFactory::make("audioresample").build()?,
+        ]
+    } else {
+        return Ok(());
+    };
+
+    let template = if name.starts_with("video/") {
+        "video_%u"
+    } else {
+        "audio_%u"
+    };
+    let sink = encodebin
+        .request_pad_simple(template)
+        .ok_or_else(|| anyhow::anyhow!("encodebin has no {} pad", template))?;
+
+    pipeline.add_many(&elements)?;
+    gst::Element::link_many(&elements)?;
+    for element in &elements {
+        element.sync_state_with_parent()?;
+    }
+
+    pad.link(&elements[0].static_pad("sink").unwrap())?;
+    elements
+        .last()
+        .unwrap()
+        .static_pad("src")
+        .unwrap()
+        .link(&sink)?;
+    Ok(())
+}
+
+fn run_to_eos(pipeline: &gst::Pipeline, stop: &AtomicBool) -> Result<(), anyhow::Error> {
+    let bus = pipeline
+        .bus()
+        .ok_or_else(|| anyhow::anyhow!("Pipeline has no bus"))?;
+    pipeline.set_state(gst::State::Playing)?;
+
+    loop {
+        if stop.load(Ordering::Acquire) {
+            return Err(anyhow::anyhow!("Stopped"));
+        }
+        let Some(msg) = bus.timed_pop(STOP_POLL) else {
+            continue;
+        };
+
+        match msg.view() {
+            gst::MessageView::Eos(_) => return Ok(()),
+            gst::MessageView::Error(e) => {
+                return Err(anyhow::anyhow!(
+                    "Error from {:?}: {} ({:?})",
+                    e.src().map(|s| s.path_string()),
+                    e.error(),
+                    e.debug()
+                ))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `awa cache`, at the primary monitor's resolution unless `width` and `height` are given
+pub(crate) fn print_generate(
+    arg: &str,
+    width: Option<u32>,
+    height: Option<u32>,
+) -> Result<(), anyhow::Error> {
+    let uri = paths::to_uri(arg)?;
+    let size = target_size(width, height)?;
+
+    println!("Encoding {} at {}x{}...", uri, size.width, size.height);
+    let path = generate(&uri, &Encoding::scaled(size), &AtomicBool::new(false))?;
+    println!("Cached as {}", path.display());
+    Ok(())
+}
+
+/// `width`x`height` from the command line, or the primary monitor's size if neither is given
+pub(crate) fn target_size(
+    width: Option<u32>,
+    height: Option<u32>,
+) -> Result<PhysicalSize<u32>, anyhow::Error> {
+    match (width, height) {
+        (Some(width), Some(height)) => Ok(PhysicalSize::new(width, height)),
+        (None, None) => platform_specific::primary_monitor_size(),
+        _ => Err(anyhow::anyhow!(
+            "Give both --width and --height, or neither"
+        )),
+    }
+}
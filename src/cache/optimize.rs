@@ -0,0 +1,161 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use gst::ElementFactory;
+use winit::dpi::PhysicalSize;
+
+use super::{Codec, Encoding};
+use crate::{config::Config, paths, probe};
+
+/// Used when the clip's framerate is unknown
+const DEFAULT_FRAMERATE: f64 = 30.;
+
+/// Encoding of the optimized variant of `uri` at `size`: the first codec this machine decodes in
+/// hardware and can encode, about a second between keyframes, and no audio if it won't be heard.
+///
+/// `decoder::setup` doesn't need to have been called.
+pub(crate) fn encoding(
+    uri: &str,
+    size: PhysicalSize<u32>,
+    audio: bool,
+) -> Result<Encoding, anyhow::Error> {
+    gst::init()?;
+
+    let codec = Codec::ALL
+        .into_iter()
+        .find(|codec| {
+            let caps = codec.caps();
+            !probe::hardware_decoders_for(&caps).is_empty() && has_encoder(&caps)
+        })
+        // Decoded quickly enough in software too
+        .unwrap_or(Codec::H264);
+
+    let framerate = probe::probe(uri)
+        .ok()
+        .and_then(|report| report.video)
+        .and_then(|video| video.framerate)
+        .unwrap_or(DEFAULT_FRAMERATE);
+
+    Ok(Encoding {
+        size,
+        codec,
+        gop: Some(framerate.round().max(1.) as u32),
+        audio,
+        optimized: true,
+    })
+}
+
+fn has_encoder(caps: &gst::Caps) -> bool {
+    ElementFactory::factories_with_type(gst::ElementFactoryType::VIDEO_ENCODER, gst::Rank::Marginal)
+        .iter()
+        .any(|factory| factory.can_src_any_caps(caps))
+}
+
+/// Makes the optimized variant of a clip on a thread of its own, stopped when dropped.
+pub(crate) struct Job {
+    uri: String,
+    size: PhysicalSize<u32>,
+    /// `None` while running, then whether the variant was made
+    result: Arc<Mutex<Option<bool>>>,
+
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Job {
+    pub(crate) fn spawn(
+        uri: String,
+        size: PhysicalSize<u32>,
+        audio: bool,
+    ) -> Result<Self, anyhow::Error> {
+        let result = Arc::new(Mutex::new(None));
+        let result_ref = result.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_ref = stop.clone();
+        let job_uri = uri.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("awa-optimize".to_owned())
+            .spawn(move || {
+                let uri = job_uri;
+                let generated = encoding(&uri, size, audio)
+                    .and_then(|encoding| super::generate(&uri, &encoding, &stop_ref));
+
+                let succeeded = match generated {
+                    Ok(_) => true,
+                    // Dropped, not a failure
+                    Err(_) if stop_ref.load(Ordering::Acquire) => false,
+                    Err(e) => {
+                        eprintln!("Error optimizing {}: {:#}", uri, e);
+                        false
+                    }
+                };
+                *result_ref.lock().unwrap() = Some(succeeded);
+            })?;
+
+        Ok(Self {
+            uri,
+            size,
+            result,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// `None` while running, then whether the variant was made
+    #[inline]
+    pub(crate) fn result(&self) -> Option<bool> {
+        *self.result.lock().unwrap()
+    }
+
+    /// The clip and size the variant is made for
+    #[inline]
+    pub(crate) fn target(&self) -> (&str, PhysicalSize<u32>) {
+        (&self.uri, self.size)
+    }
+}
+
+/// `awa optimize`
+pub(crate) fn print(
+    arg: &str,
+    width: Option<u32>,
+    height: Option<u32>,
+    no_audio: bool,
+) -> Result<(), anyhow::Error> {
+    let uri = paths::to_uri(arg)?;
+    let size = super::target_size(width, height)?;
+    let muted = Config::load().map_or(false, |config| config.audio.muted);
+
+    let encoding = encoding(&uri, size, !(no_audio || muted))?;
+    println!(
+        "Encoding {} at {}x{} as {:?}, keyframes every {} frames{}...",
+        uri,
+        size.width,
+        size.height,
+        encoding.codec,
+        encoding.gop.unwrap_or_default(),
+        if encoding.audio {
+            ""
+        } else {
+            ", without audio"
+        }
+    );
+    let path = super::generate(&uri, &encoding, &AtomicBool::new(false))?;
+    println!("Cached as {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::library::MAX_RATING;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "An animated cross-platform wallpaper manager")]
+pub(crate) struct Cli {
+    /// Plays the wallpaper when omitted
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Lists the audio output devices
+    Devices,
+
+    /// Checks the GStreamer installation, display and config
+    Doctor,
+
+    /// Inspects a media file or URI to see whether it will play well
+    Probe {
+        /// Path or URI of the media
+        file: String,
+
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Encodes a resolution-matched copy of a clip, which is then played instead of it
+    Cache {
+        /// Path of the clip
+        file: String,
+
+        /// Width of the copy, the primary monitor's by default
+        #[arg(long, requires = "height")]
+        width: Option<u32>,
+
+        /// Height of the copy, the primary monitor's by default
+        #[arg(long, requires = "width")]
+        height: Option<u32>,
+    },
+
+    /// Encodes a copy of a clip which plays as cheaply as possible on this machine: decoded in
+    /// hardware, with frequent keyframes and no audio when muted
+    Optimize {
+        /// Path of the clip
+        file: String,
+
+        /// Width of the copy, the primary monitor's by default
+        #[arg(long, requires = "height")]
+        width: Option<u32>,
+
+        /// Height of the copy, the primary monitor's by default
+        #[arg(long, requires = "width")]
+        height: Option<u32>,
+
+        /// Leaves the audio out even if `audio.muted` isn't set
+        #[arg(long)]
+        no_audio: bool,
+    },
+
+    /// Generates PNG thumbnails and an animated GIF preview of clips
+    Thumbnail {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Generates them again even if they exist
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Adds wallpapers of other programs to the library
+    Import {
+        /// Folders of wallpapers or Lively `.zip` packages, or folders of those like Steam's
+        /// workshop folder
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Inspects Wallpaper Engine `.pkg` archives
+    Pkg {
+        #[command(subcommand)]
+        command: PkgCommand,
+    },
+
+    /// Converts a Wallpaper Engine `.tex` texture to an image, or to a GIF if it is animated
+    Tex {
+        file: PathBuf,
+
+        /// PNG, JPEG, GIF, ... by its extension
+        output: PathBuf,
+    },
+
+    /// Shows what the running instance is playing
+    Status {
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Manages the wallpapers found in `library.dirs`
+    Library {
+        #[command(subcommand)]
+        command: LibraryCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum PkgCommand {
+    /// Lists the entries with their sizes
+    Ls {
+        file: PathBuf,
+
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Writes the entries into a directory, refusing to overwrite files
+    Extract {
+        file: PathBuf,
+
+        dir: PathBuf,
+
+        /// Names of the entries to extract, all of them by default
+        entries: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum LibraryCommand {
+    /// Adds the new media and drops the removed ones
+    Scan,
+
+    /// Lists the wallpapers, all of them unless filtered
+    Ls {
+        /// Only the ones with this tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only the ones rated at least this
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=MAX_RATING as i64))]
+        min_rating: Option<u8>,
+
+        /// Only the favorites
+        #[arg(long)]
+        favorites: bool,
+
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Tags a wallpaper
+    Tag {
+        file: PathBuf,
+
+        #[arg(required = true)]
+        tags: Vec<String>,
+
+        /// Removes the tags instead
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Rates a wallpaper, 0 to clear the rating
+    Rate {
+        file: PathBuf,
+
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=MAX_RATING as i64))]
+        rating: u8,
+    },
+
+    /// Marks a wallpaper as a favorite
+    Favorite {
+        file: PathBuf,
+
+        /// Unmarks it instead
+        #[arg(long)]
+        off: bool,
+    },
+}
@@ -0,0 +1,707 @@
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{activity::Throttle, paths, power};
+
+const DEFAULT_URI: &str = "https://gstreamer.freedesktop.org/media/sintel_trailer-480p.webm";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    /// Also read from the `uri` string of older configs
+    #[serde(alias = "uri", deserialize_with = "wallpaper_or_uri")]
+    pub(crate) wallpaper: Wallpaper,
+
+    pub(crate) framerate: f64,
+
+    pub(crate) decoders: DecoderConfig,
+
+    /// Resolution the wallpaper is decoded at
+    pub(crate) decode: DecodeConfig,
+
+    /// Replays short loops from memory, off if `None`
+    pub(crate) loop_cache: Option<LoopCacheConfig>,
+
+    pub(crate) audio: AudioConfig,
+
+    /// Effects following the audio, off if `None`
+    pub(crate) reactive: Option<ReactiveConfig>,
+
+    /// What to do while the desktop is hidden by other windows
+    pub(crate) covered: CoveredConfig,
+
+    /// Restrictions while running on battery
+    pub(crate) power: PowerConfig,
+
+    /// Pausing while the user is away
+    pub(crate) idle: IdleConfig,
+
+    /// Limit on awa's CPU usage, none if `None`
+    pub(crate) cpu_budget: Option<CpuBudgetConfig>,
+
+    pub(crate) recovery: RecoveryConfig,
+
+    /// Directories of wallpapers indexed by `awa library`
+    pub(crate) library: LibraryConfig,
+
+    /// Shown after the wallpaper failed `recovery.max-retries` times in a row
+    pub(crate) fallback: Fallback,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            wallpaper: Wallpaper::default(),
+            framerate: 60.,
+            decoders: DecoderConfig::default(),
+            decode: DecodeConfig::default(),
+            loop_cache: None,
+            audio: AudioConfig::default(),
+            reactive: None,
+            covered: CoveredConfig::default(),
+            power: PowerConfig::default(),
+            idle: IdleConfig::default(),
+            cpu_budget: None,
+            recovery: RecoveryConfig::default(),
+            library: LibraryConfig::default(),
+            fallback: Fallback::default(),
+        }
+    }
+}
+
+impl Config {
+    #[inline]
+    pub(crate) fn path() -> PathBuf {
+        paths::config_dir().join("config.json")
+    }
+
+    /// Loads `$XDG_CONFIG_HOME/awa/config.json`, or the default config if it doesn't exist.
+    pub(crate) fn load() -> Result<Self, anyhow::Error> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let file = std::fs::File::open(&path)?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Problems which parse fine but will fail at runtime
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        match &self.wallpaper {
+            Wallpaper::Video { uri } => {
+                if !uri.contains("://") {
+                    problems.push(format!(
+                        "uri {:?} is not a URI, use file:///path/to/file for local files",
+                        uri
+                    ));
+                }
+            }
+            Wallpaper::Scene { path } => {
+                if !path.exists() {
+                    problems.push(format!("scene {} doesn't exist", path.display()));
+                }
+            }
+            Wallpaper::Slideshow { path } => {
+                if !path.is_file() {
+                    problems.push(format!("slideshow {} doesn't exist", path.display()));
+                }
+            }
+            Wallpaper::Visualizer(visualizer) => {
+                if let Some(file) = &visualizer.file {
+                    if !file.is_file() {
+                        problems.push(format!("visualizer file {} doesn't exist", file.display()));
+                    }
+                }
+                if visualizer.bar_count == 0 {
+                    problems.push("visualizer bar-count must be positive".to_owned());
+                }
+                if !(0. ..1.).contains(&visualizer.smoothing) {
+                    problems.push(format!(
+                        "visualizer smoothing {} must be between 0 and 1",
+                        visualizer.smoothing
+                    ));
+                }
+            }
+        }
+        if self.framerate.is_nan() || self.framerate <= 0. {
+            problems.push(format!("framerate {} must be positive", self.framerate));
+        }
+        if let Fallback::Image { path } = &self.fallback {
+            if !path.is_file() {
+                problems.push(format!("fallback image {} doesn't exist", path.display()));
+            }
+        }
+        if !(0. ..=1.).contains(&self.audio.volume) {
+            problems.push(format!(
+                "audio volume {} must be between 0 and 1",
+                self.audio.volume
+            ));
+        }
+        if !(0. ..=1.).contains(&self.audio.ducking.level) {
+            problems.push(format!(
+                "audio ducking level {} must be between 0 and 1",
+                self.audio.ducking.level
+            ));
+        }
+        if self.covered.action == CoveredAction::Throttle
+            && (self.covered.framerate.is_nan() || self.covered.framerate <= 0.)
+        {
+            problems.push(format!(
+                "covered framerate {} must be positive",
+                self.covered.framerate
+            ));
+        }
+        if let Some(budget) = &self.cpu_budget {
+            if budget.percent.is_nan() || budget.percent <= 0. {
+                problems.push(format!(
+                    "cpu-budget percent {} must be positive",
+                    budget.percent
+                ));
+            }
+        }
+        for profile in &self.power.profiles {
+            if let PowerAction::Framerate { framerate } = profile.action {
+                if framerate.is_nan() || framerate <= 0. {
+                    problems.push(format!("power framerate {} must be positive", framerate));
+                }
+            }
+            if let Some(below) = profile.below {
+                if !(0. ..=100.).contains(&below) {
+                    problems.push(format!("power below {} must be between 0 and 100", below));
+                }
+            }
+        }
+        for name in &self.decoders.prefer {
+            if self.decoders.deny.contains(name) {
+                problems.push(format!("decoder {} is both preferred and denied", name));
+            }
+        }
+        for dir in &self.library.dirs {
+            if !dir.is_dir() {
+                problems.push(format!("library dir {} doesn't exist", dir.display()));
+            }
+        }
+
+        problems
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum Wallpaper {
+    Video {
+        uri: String,
+    },
+    /// Spectrum of the system audio or of a file
+    Visualizer(VisualizerConfig),
+    /// Wallpaper Engine 2D scene, a `scene.pkg` or its project folder
+    Scene {
+        path: PathBuf,
+    },
+    /// GNOME `<background>` XML, images changing with the time of day
+    Slideshow {
+        path: PathBuf,
+    },
+}
+
+/// A `Wallpaper`, or the URI of a video like the `uri` key before there were other wallpapers
+fn wallpaper_or_uri<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Wallpaper, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    match value {
+        serde_json::Value::String(uri) => Ok(Wallpaper::Video { uri }),
+        value => Wallpaper::deserialize(value).map_err(serde::de::Error::custom),
+    }
+}
+
+impl Default for Wallpaper {
+    fn default() -> Self {
+        Self::Video {
+            uri: DEFAULT_URI.to_owned(),
+        }
+    }
+}
+
+impl std::fmt::Display for Wallpaper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Video { uri } => write!(f, "{}", uri),
+            Self::Visualizer(visualizer) => match &visualizer.file {
+                Some(file) => write!(f, "{:?} visualizer of {}", visualizer.style, file.display()),
+                None => write!(f, "{:?} visualizer", visualizer.style),
+            },
+            Self::Scene { path } => write!(f, "scene {}", path.display()),
+            Self::Slideshow { path } => write!(f, "slideshow {}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum VisualizerStyle {
+    #[default]
+    Bars,
+    Waveform,
+    Radial,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct VisualizerConfig {
+    pub(crate) style: VisualizerStyle,
+    /// Input device to capture, e.g. a monitor source. Output devices are captured as loopback
+    /// where supported. The default input device if `None`.
+    pub(crate) device: Option<String>,
+    /// Audio file to visualize instead of capturing, played in a loop
+    pub(crate) file: Option<PathBuf>,
+    pub(crate) bar_count: usize,
+    /// How much of the previous frame is kept, from 0 (none) to 1 (exclusive)
+    pub(crate) smoothing: f32,
+    pub(crate) background: [u8; 3],
+    /// Color of quiet bars, blended towards `peak` for loud ones
+    pub(crate) foreground: [u8; 3],
+    pub(crate) peak: [u8; 3],
+}
+
+impl Default for VisualizerConfig {
+    fn default() -> Self {
+        Self {
+            style: VisualizerStyle::Bars,
+            device: None,
+            file: None,
+            bar_count: 64,
+            smoothing: 0.7,
+            background: [0x10, 0x10, 0x18],
+            foreground: [0x29, 0x79, 0xff],
+            peak: [0xff, 0x40, 0x81],
+        }
+    }
+}
+
+/// Element factory names, e.g. `avdec_h264` or `nvh264dec`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct DecoderConfig {
+    /// Picked over everything else, most preferred first
+    pub(crate) prefer: Vec<String>,
+    /// Never picked
+    pub(crate) deny: Vec<String>,
+    /// Explicit GStreamer ranks, e.g. 256 for primary
+    pub(crate) ranks: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct DecodeConfig {
+    /// Never decodes wider than this, e.g. 1920 on a 4K monitor
+    pub(crate) max_width: Option<u32>,
+    /// Never decodes taller than this
+    pub(crate) max_height: Option<u32>,
+    /// Scales with `vaapipostproc` when the clip is decoded with VA-API
+    pub(crate) gpu_scaling: bool,
+    /// Plays the copies made by `awa cache` and `awa optimize` instead of the clips
+    pub(crate) use_cache: bool,
+    /// Makes the copy `awa optimize` would in the background, then switches to it
+    pub(crate) optimize: bool,
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            max_height: None,
+            gpu_scaling: true,
+            use_cache: true,
+            optimize: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct LoopCacheConfig {
+    /// Loops whose frames take more memory keep being decoded
+    pub(crate) max_mb: u64,
+    /// Keeps the frames compressed with LZ4, trading some CPU for a lot of memory
+    pub(crate) compress: bool,
+}
+
+impl Default for LoopCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_mb: 256,
+            compress: true,
+        }
+    }
+}
+
+impl LoopCacheConfig {
+    #[inline]
+    pub(crate) fn max_bytes(&self) -> usize {
+        (self.max_mb * 1024 * 1024) as usize
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct AudioConfig {
+    /// Output device name as listed by `awa devices`, the default one if `None`
+    pub(crate) device: Option<String>,
+    /// From 0 to 1
+    pub(crate) volume: f32,
+    pub(crate) muted: bool,
+    /// Fade in on start, fade out on stop
+    pub(crate) fade_ms: u64,
+
+    pub(crate) ducking: DuckingConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            volume: 1.,
+            muted: false,
+            fade_ms: 500,
+            ducking: DuckingConfig::default(),
+        }
+    }
+}
+
+impl AudioConfig {
+    #[inline]
+    pub(crate) fn fade(&self) -> Duration {
+        Duration::from_millis(self.fade_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum DuckingMode {
+    #[default]
+    Off,
+    /// Lower the volume to `level`
+    Duck,
+    Mute,
+}
+
+/// What to do while other applications play sound
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct DuckingConfig {
+    pub(crate) mode: DuckingMode,
+    /// Multiplier of the volume while ducked, from 0 to 1
+    pub(crate) level: f32,
+    pub(crate) poll_ms: u64,
+    pub(crate) fade_ms: u64,
+}
+
+impl Default for DuckingConfig {
+    fn default() -> Self {
+        Self {
+            mode: DuckingMode::Off,
+            level: 0.2,
+            poll_ms: 1000,
+            fade_ms: 300,
+        }
+    }
+}
+
+impl DuckingConfig {
+    #[inline]
+    pub(crate) fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms.max(100))
+    }
+
+    #[inline]
+    pub(crate) fn fade(&self) -> Duration {
+        Duration::from_millis(self.fade_ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ReactiveInput {
+    Bass,
+    Mid,
+    Treble,
+    Loudness,
+    /// Pulse decaying after each beat
+    Beat,
+}
+
+/// Effect driven by `input`, from 0 to 1, scaled by `amount`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Modulation {
+    pub(crate) input: ReactiveInput,
+    pub(crate) amount: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct ReactiveConfig {
+    /// Reacts to the system audio when the wallpaper has none of its own
+    pub(crate) capture: bool,
+    /// Input device to capture, see `VisualizerConfig::device`
+    pub(crate) device: Option<String>,
+
+    /// Added to the brightness multiplier, e.g. 0.3 for +30%
+    pub(crate) brightness: Option<Modulation>,
+    /// Added to the zoom factor, e.g. 0.05 for 5%
+    pub(crate) zoom: Option<Modulation>,
+    /// Hue shift in degrees
+    pub(crate) hue: Option<Modulation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CoveredAction {
+    /// Keep playing as usual
+    Ignore,
+    #[default]
+    Pause,
+    /// Keep playing at `CoveredConfig::framerate`
+    Throttle,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct CoveredConfig {
+    pub(crate) action: CoveredAction,
+    pub(crate) framerate: f64,
+    /// How often the windows are checked
+    pub(crate) poll_ms: u64,
+}
+
+impl Default for CoveredConfig {
+    fn default() -> Self {
+        Self {
+            action: CoveredAction::default(),
+            framerate: 1.,
+            poll_ms: 500,
+        }
+    }
+}
+
+impl CoveredConfig {
+    #[inline]
+    pub(crate) fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms)
+    }
+
+    /// `None` if covering is ignored
+    pub(crate) fn throttle(&self) -> Option<Throttle> {
+        match self.action {
+            CoveredAction::Ignore => None,
+            CoveredAction::Pause => Some(Throttle::Pause),
+            CoveredAction::Throttle => Some(Throttle::Framerate(self.framerate)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub(crate) enum PowerAction {
+    /// Lowers the framerate to at most `framerate`
+    Framerate {
+        framerate: f64,
+    },
+    Mute,
+    /// Pauses on the current frame
+    Still,
+    /// Stops the wallpaper and shows the fallback
+    Stop,
+}
+
+/// Applies `action` while on battery, and only below `below` percent of charge if set
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct PowerProfile {
+    #[serde(default)]
+    pub(crate) below: Option<f32>,
+    #[serde(flatten)]
+    pub(crate) action: PowerAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct PowerConfig {
+    /// Read instead of `/sys/class/power_supply`, e.g. a fake one for testing
+    pub(crate) sysfs: PathBuf,
+    pub(crate) poll_ms: u64,
+    pub(crate) profiles: Vec<PowerProfile>,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            sysfs: PathBuf::from(power::SYSFS_POWER_SUPPLY),
+            poll_ms: 10_000,
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl PowerConfig {
+    #[inline]
+    pub(crate) fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct IdleConfig {
+    /// Pauses after this long without input, never if `None` as by default
+    pub(crate) timeout_s: Option<u64>,
+    /// Pauses while the session is locked or the system sleeps
+    pub(crate) lock: bool,
+    /// Pauses while the monitors are powered down
+    pub(crate) display_off: bool,
+    /// How often the idle time and the monitors are checked
+    pub(crate) poll_ms: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            timeout_s: None,
+            lock: true,
+            display_off: true,
+            poll_ms: 1000,
+        }
+    }
+}
+
+impl IdleConfig {
+    #[inline]
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout_s.map(Duration::from_secs)
+    }
+
+    #[inline]
+    pub(crate) fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_ms)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct CpuBudgetConfig {
+    /// Share of one core, e.g. 5 for 5%
+    pub(crate) percent: f64,
+    /// How long the usage is measured before each adjustment
+    pub(crate) window_ms: u64,
+}
+
+impl Default for CpuBudgetConfig {
+    fn default() -> Self {
+        Self {
+            percent: 5.,
+            window_ms: 5000,
+        }
+    }
+}
+
+impl CpuBudgetConfig {
+    #[inline]
+    pub(crate) fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct LibraryConfig {
+    /// Scanned recursively
+    pub(crate) dirs: Vec<PathBuf>,
+    /// Keeps the index up to date while awa runs
+    pub(crate) watch: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            dirs: Vec::new(),
+            watch: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct RecoveryConfig {
+    pub(crate) max_retries: u32,
+    pub(crate) initial_backoff_ms: u64,
+    pub(crate) max_backoff_ms: u64,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+impl RecoveryConfig {
+    #[inline]
+    pub(crate) fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    #[inline]
+    pub(crate) fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub(crate) enum Fallback {
+    Image { path: PathBuf },
+    Color { color: [u8; 3] },
+}
+
+impl Default for Fallback {
+    fn default() -> Self {
+        Self::Color {
+            color: [0x20, 0x20, 0x20],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallpaper(json: &str) -> Result<Wallpaper, serde_json::Error> {
+        serde_json::from_str::<Config>(json).map(|config| config.wallpaper)
+    }
+
+    #[test]
+    fn reads_the_old_uri() {
+        let uri = "file:///clip.mp4".to_owned();
+        for json in [
+            r#"{ "uri": "file:///clip.mp4" }"#,
+            r#"{ "wallpaper": "file:///clip.mp4" }"#,
+            r#"{ "wallpaper": { "type": "video", "uri": "file:///clip.mp4" } }"#,
+        ] {
+            assert!(matches!(wallpaper(json), Ok(Wallpaper::Video { uri: read }) if read == uri));
+        }
+
+        assert!(matches!(
+            wallpaper(r#"{ "wallpaper": { "type": "slideshow", "path": "/slides.xml" } }"#),
+            Ok(Wallpaper::Slideshow { .. })
+        ));
+        assert!(matches!(wallpaper("{}"), Ok(Wallpaper::Video { .. })));
+        assert!(wallpaper(r#"{ "wallpaper": { "type": "gif" } }"#).is_err());
+    }
+}
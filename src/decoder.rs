@@ -0,0 +1,184 @@
+use std::{collections::BTreeSet, path::PathBuf};
+
+use cfg_if::cfg_if;
+use gst::{prelude::*, ElementFactory};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::DecoderConfig, paths};
+
+cfg_if! {
+    if #[cfg(target_os = "macos")] {
+        pub(crate) const HARDWARE_ELEMENTS: &[&str] = &["vtdec"];
+
+    } else if #[cfg(target_os = "windows")] {
+        pub(crate) const HARDWARE_ELEMENTS: &[&str] = &[
+            "d3d11h264dec",
+            "d3d11h265dec",
+            "d3d11vp8dec",
+            "d3d11vp9dec",
+            "d3d11mpeg2dec",
+            "d3d11av1dec",
+        ];
+
+    } else if #[cfg(target_os = "linux")] {
+        pub(crate) const HARDWARE_ELEMENTS: &[&str] = &[
+            "vaapih264dec",
+            "vaapivp8dec",
+            "vaapivp9dec",
+            "vaapijpegdec",
+            "vaapimpeg2dec",
+            "vaapimpeg4dec",
+            "vaapih265dec",
+            "vaapivc1dec",
+            "vaapiav1dec",
+            "vaapipostproc",
+        ];
+    }
+}
+
+/// Hardware elements that failed before, persisted across runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DecoderState {
+    broken: BTreeSet<String>,
+}
+
+impl DecoderState {
+    #[inline]
+    fn path() -> PathBuf {
+        paths::state_dir().join("decoders.json")
+    }
+
+    fn load() -> Self {
+        std::fs::read(Self::path())
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Prefers the hardware elements of this platform, except the ones which failed before, then
+/// applies the user's `config` on top. With `favor_hardware`, the hardware elements which aren't
+/// denied win over the config's preferences too.
+///
+/// `gst::init` must have been called.
+pub(crate) fn setup(config: &DecoderConfig, favor_hardware: bool) {
+    let state = DecoderState::load();
+
+    for name in HARDWARE_ELEMENTS {
+        enable_factory(name, !state.broken.contains(*name));
+    }
+
+    // Before `prefer`, which wins over them
+    for (name, rank) in &config.ranks {
+        if state.broken.contains(name) {
+            continue;
+        }
+        if !set_factory_rank(name, gst::Rank::None + *rank) {
+            eprintln!("Ranked decoder {} not found", name);
+        }
+    }
+
+    // Above the hardware elements, in the given order. Broken ones stay disabled, or they would
+    // be picked again right after failing.
+    for (i, name) in config.prefer.iter().enumerate() {
+        if state.broken.contains(name) {
+            continue;
+        }
+        let rank = gst::Rank::Primary + 16 + (config.prefer.len() - i) as u32;
+        if !set_factory_rank(name, rank) {
+            eprintln!("Preferred decoder {} not found", name);
+        }
+    }
+
+    if favor_hardware {
+        let rank = gst::Rank::Primary + 16 + config.prefer.len() as u32 + 1;
+        for name in HARDWARE_ELEMENTS {
+            if !state.broken.contains(*name) {
+                set_factory_rank(name, rank);
+            }
+        }
+    }
+
+    for name in &config.deny {
+        set_factory_rank(name, gst::Rank::None);
+    }
+}
+
+/// Where the hardware elements disabled after they failed are recorded
+#[inline]
+pub(crate) fn state_path() -> PathBuf {
+    DecoderState::path()
+}
+
+/// Hardware elements disabled after they failed
+pub(crate) fn broken() -> BTreeSet<String> {
+    DecoderState::load().broken
+}
+
+/// Whether `factory_name` is one of this platform's hardware elements, or any element whose klass
+/// says it is, e.g. from the `va` or `nvcodec` plugins
+pub(crate) fn is_hardware(factory_name: &str) -> bool {
+    HARDWARE_ELEMENTS.contains(&factory_name)
+        || ElementFactory::find(factory_name).map_or(false, |factory| is_hardware_factory(&factory))
+}
+
+pub(crate) fn is_hardware_factory(factory: &ElementFactory) -> bool {
+    HARDWARE_ELEMENTS.contains(&factory.name().as_str())
+        || factory
+            .metadata(gst::ELEMENT_METADATA_KLASS)
+            .map_or(false, |klass| klass.split('/').any(|k| k == "Hardware"))
+}
+
+/// Stops `factory_name` from being picked, now and on the next startups. Returns whether it
+/// wasn't disabled already.
+pub(crate) fn disable(factory_name: &str) -> Result<bool, anyhow::Error> {
+    enable_factory(factory_name, false);
+
+    let mut state = DecoderState::load();
+    let inserted = state.broken.insert(factory_name.to_owned());
+    if inserted {
+        state.save()?;
+    }
+    Ok(inserted)
+}
+
+/// Whether `element` is a video decoder, e.g. the one `playbin` picked.
+pub(crate) fn is_video_decoder(element: &gst::Element) -> bool {
+    element
+        .factory()
+        .and_then(|factory| {
+            factory
+                .metadata(gst::ELEMENT_METADATA_KLASS)
+                .map(|klass| klass.contains("Decoder") && klass.contains("Video"))
+        })
+        .unwrap_or(false)
+}
+
+#[inline]
+fn enable_factory(name: &str, enable: bool) -> bool {
+    if enable {
+        set_factory_rank(name, gst::Rank::Primary + 4)
+    } else {
+        set_factory_rank(name, gst::Rank::None)
+    }
+}
+
+fn set_factory_rank(name: &str, rank: gst::Rank) -> bool {
+    let registry = gst::Registry::get();
+    if let Some(factory) = ElementFactory::find(name) {
+        let factory = factory.upcast::<gst::PluginFeature>();
+        factory.set_rank(rank);
+        registry.add_feature(&factory).is_ok()
+    } else {
+        false
+    }
+}
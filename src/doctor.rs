@@ -0,0 +1,299 @@
+use gst::ElementFactory;
+
+use crate::{config::Config, decoder};
+
+/// GStreamer plugin sets, which distributions package separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PluginSet {
+    Base,
+    Good,
+    Bad,
+    Libav,
+}
+
+impl PluginSet {
+    fn package(self, distro: Distro) -> &'static str {
+        use PluginSet::*;
+
+        match (distro, self) {
+            (Distro::Debian, Base) => "gstreamer1.0-plugins-base",
+            (Distro::Debian, Good) => "gstreamer1.0-plugins-good",
+            (Distro::Debian, Bad) => "gstreamer1.0-plugins-bad",
+            (Distro::Debian, Libav) => "gstreamer1.0-libav",
+
+            (Distro::Fedora, Base) => "gstreamer1-plugins-base",
+            (Distro::Fedora, Good) => "gstreamer1-plugins-good",
+            (Distro::Fedora, Bad) => "gstreamer1-plugins-bad-free",
+            (Distro::Fedora, Libav) => "gstreamer1-plugin-libav",
+
+            (Distro::Arch, Base) => "gst-plugins-base",
+            (Distro::Arch, Good) => "gst-plugins-good",
+            (Distro::Arch, Bad) => "gst-plugins-bad",
+            (Distro::Arch, Libav) => "gst-libav",
+
+            (Distro::OpenSuse, Base) => "gstreamer-plugins-base",
+            (Distro::OpenSuse, Good) => "gstreamer-plugins-good",
+            (Distro::OpenSuse, Bad) => "gstreamer-plugins-bad",
+            (Distro::OpenSuse, Libav) => "gstreamer-plugins-libav",
+
+            (Distro::Other, Base) => "gst-plugins-base",
+            (Distro::Other, Good) => "gst-plugins-good",
+            (Distro::Other, Bad) => "gst-plugins-bad",
+            (Distro::Other, Libav) => "gst-libav",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Distro {
+    Debian,
+    Fedora,
+    Arch,
+    OpenSuse,
+    Other,
+}
+
+impl Distro {
+    fn detect() -> Self {
+        let os_release = std::fs::read_to_string("/etc/os-release").unwrap_or_default();
+
+        let ids = os_release
+            .lines()
+            .filter_map(|line| {
+                line.strip_prefix("ID=")
+                    .or_else(|| line.strip_prefix("ID_LIKE="))
+            })
+            .flat_map(|value| value.trim_matches('"').split_whitespace())
+            .collect::<Vec<_>>();
+
+        let is = |id: &str| ids.contains(&id);
+        if is("debian") || is("ubuntu") {
+            Self::Debian
+        } else if is("fedora") || is("rhel") {
+            Self::Fedora
+        } else if is("arch") {
+            Self::Arch
+        } else if is("suse") || is("opensuse") {
+            Self::OpenSuse
+        } else {
+            Self::Other
+        }
+    }
+
+    fn install_command(self, packages: &[&str]) -> String {
+        let packages = packages.join(" ");
+        match self {
+            Self::Debian => format!("sudo apt install {}", packages),
+            Self::Fedora => format!("sudo dnf install {}", packages),
+            Self::Arch => format!("sudo pacman -S {}", packages),
+            Self::OpenSuse => format!("sudo zypper install {}", packages),
+            Self::Other => format!("install {} with your package manager", packages),
+        }
+    }
+}
+
+/// Elements `Video::create_pipeline` can't do without
+const REQUIRED_ELEMENTS: &[(&str, PluginSet)] = &[
+    ("playbin", PluginSet::Base),
+    ("appsink", PluginSet::Base),
+    ("videoconvertscale", PluginSet::Base),
+    ("videorate", PluginSet::Base),
+    ("aspectratiocrop", PluginSet::Good),
+    ("autoaudiosink", PluginSet::Good),
+    ("fpsdisplaysink", PluginSet::Bad),
+];
+
+/// Elements needed by common wallpapers
+const RECOMMENDED_ELEMENTS: &[(&str, PluginSet, &str)] = &[
+    ("souphttpsrc", PluginSet::Good, "playing http(s) URIs"),
+    ("matroskademux", PluginSet::Good, "playing WebM/MKV files"),
+    ("qtdemux", PluginSet::Good, "playing MP4/MOV files"),
+    ("vp9dec", PluginSet::Good, "software VP9 decoding"),
+    ("avdec_h264", PluginSet::Libav, "software H.264 decoding"),
+];
+
+/// `videoconvertscale` was added in 1.22
+const MIN_VERSION: (u32, u32) = (1, 22);
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    suggestions: Vec<String>,
+}
+
+impl Report {
+    fn ok(&self, message: impl std::fmt::Display) {
+        println!("  [ ok ] {}", message);
+    }
+
+    fn warn(&self, message: impl std::fmt::Display) {
+        println!("  [warn] {}", message);
+    }
+
+    fn fail(&mut self, message: impl std::fmt::Display) {
+        self.failures += 1;
+        println!("  [FAIL] {}", message);
+    }
+
+    fn suggest(&mut self, suggestion: String) {
+        if !self.suggestions.contains(&suggestion) {
+            self.suggestions.push(suggestion);
+        }
+    }
+}
+
+/// `awa doctor`
+pub(crate) fn run() -> Result<(), anyhow::Error> {
+    let mut report = Report::default();
+    let distro = Distro::detect();
+
+    println!("GStreamer");
+    if let Err(e) = gst::init() {
+        report.fail(format!("Failed to initialize GStreamer: {}", e));
+        report.suggest(format!(
+            "Install GStreamer: {}",
+            distro.install_command(&[PluginSet::Base.package(distro)])
+        ));
+        return finish(report);
+    }
+
+    let (major, minor, micro, _) = gst::version();
+    if (major, minor) >= MIN_VERSION {
+        report.ok(gst::version_string());
+    } else {
+        report.fail(format!(
+            "{} is too old, awa needs {}.{} or newer",
+            gst::version_string(),
+            MIN_VERSION.0,
+            MIN_VERSION.1
+        ));
+        report.suggest(format!(
+            "Upgrade GStreamer to {}.{} or newer (found {}.{}.{})",
+            MIN_VERSION.0, MIN_VERSION.1, major, minor, micro
+        ));
+    }
+
+    println!("Elements");
+    let mut missing = Vec::new();
+    for (name, set) in REQUIRED_ELEMENTS {
+        if ElementFactory::find(name).is_some() {
+            report.ok(name);
+        } else {
+            report.fail(format!("{} is missing", name));
+            missing.push(*set);
+        }
+    }
+    for (name, set, purpose) in RECOMMENDED_ELEMENTS {
+        if ElementFactory::find(name).is_some() {
+            report.ok(name);
+        } else {
+            report.warn(format!("{} is missing, needed for {}", name, purpose));
+            missing.push(*set);
+        }
+    }
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+
+        let packages = missing
+            .iter()
+            .map(|set| set.package(distro))
+            .collect::<Vec<_>>();
+        report.suggest(format!(
+            "Install the missing plugins: {}",
+            distro.install_command(&packages)
+        ));
+    }
+
+    println!("Hardware decoders");
+    let broken = decoder::broken();
+    let mut available = 0;
+    for name in decoder::HARDWARE_ELEMENTS {
+        if ElementFactory::find(name).is_none() {
+            continue;
+        }
+
+        available += 1;
+        if broken.contains(*name) {
+            report.warn(format!("{} (disabled after it failed)", name));
+        } else {
+            report.ok(name);
+        }
+    }
+    if available == 0 {
+        report.warn("None available, videos are decoded in software");
+        if cfg!(target_os = "linux") {
+            report.suggest(
+                "Install gstreamer-vaapi (gstreamer1.0-vaapi on Debian/Ubuntu) and your GPU's VA-API driver for hardware decoding"
+                    .to_owned(),
+            );
+        }
+    }
+    if !broken.is_empty() {
+        report.suggest(format!(
+            "Remove {} to retry the disabled hardware decoders",
+            decoder::state_path().display()
+        ));
+    }
+
+    println!("Display");
+    display_backend(&mut report);
+
+    println!("Config");
+    let path = Config::path();
+    match Config::load() {
+        Ok(config) => {
+            if path.exists() {
+                report.ok(path.display());
+            } else {
+                report.ok(format!("{} doesn't exist, using defaults", path.display()));
+            }
+
+            for problem in config.validate() {
+                report.fail(problem);
+            }
+        }
+        Err(e) => {
+            report.fail(format!("{:#}", e));
+            report.suggest(format!("Fix or remove {}", path.display()));
+        }
+    }
+
+    finish(report)
+}
+
+fn display_backend(report: &mut Report) {
+    if cfg!(target_os = "linux") {
+        let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = std::env::var_os("DISPLAY").is_some();
+
+        match (wayland, x11) {
+            (true, true) => report.ok("Wayland (XWayland available)"),
+            (true, false) => report.ok("Wayland"),
+            (false, true) => report.ok("X11"),
+            (false, false) => {
+                report.fail("No display found, neither WAYLAND_DISPLAY nor DISPLAY is set");
+                report.suggest("Run awa from inside a graphical session".to_owned());
+            }
+        }
+    } else {
+        report.ok(std::env::consts::OS);
+    }
+}
+
+fn finish(report: Report) -> Result<(), anyhow::Error> {
+    if !report.suggestions.is_empty() {
+        println!("Suggestions");
+        for suggestion in &report.suggestions {
+            println!("  - {}", suggestion);
+        }
+    }
+
+    match report.failures {
+        0 => {
+            println!("Everything looks fine");
+            Ok(())
+        }
+        n => Err(anyhow::anyhow!("{} problem(s) found", n)),
+    }
+}
@@ -1,10 +1,18 @@
-use crate::app::App;
+use crate::{
+    activity::{Throttle, PAUSED_FRAMERATE},
+    app::App,
+};
+
+/// Frames the updates may lag behind before the rest is dropped, e.g. after a suspend
+const MAX_FRAMES_BEHIND: u32 = 2;
 
 pub(crate) struct FrameManager {
     current_instant: std::time::Instant,
     previous_instant: std::time::Instant,
     accumulated: std::time::Duration,
     target: std::time::Duration,
+    /// Configured framerate, before throttling
+    framerate: f64,
 }
 
 impl FrameManager {
@@ -14,6 +22,7 @@ impl FrameManager {
             previous_instant: std::time::Instant::now(),
             accumulated: std::time::Duration::from_secs(0),
             target: std::time::Duration::from_secs_f64(1. / framerate),
+            framerate,
         }
     }
 
@@ -22,6 +31,8 @@ impl FrameManager {
         let time_delta = self.current_instant - self.previous_instant;
 
         self.accumulated += time_delta;
+        // Not catching up on the hours spent suspended, or on a long pause
+        self.accumulated = self.accumulated.min(self.target * MAX_FRAMES_BEHIND);
 
         if self.accumulated >= self.target {
             app.update().await;
@@ -35,6 +46,19 @@ impl FrameManager {
 
         self.previous_instant = self.current_instant;
 
+        let throttle = app.throttle().await;
+        let framerate = match throttle {
+            None => self.framerate,
+            Some(Throttle::Framerate(framerate)) => framerate.min(self.framerate),
+            Some(Throttle::Pause) => PAUSED_FRAMERATE,
+        };
+        self.target = std::time::Duration::from_secs_f64(1. / framerate);
+
+        if throttle.is_some() {
+            // Nothing worth spinning for until the next update
+            tokio::time::sleep(self.target.saturating_sub(self.accumulated)).await;
+        }
+
         true
     }
 }
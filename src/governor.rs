@@ -0,0 +1,349 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CpuBudgetConfig;
+
+/// Usage below this fraction of the budget relaxes the throttling one level
+const RELAX_BELOW: f64 = 0.5;
+
+/// Relaxing windows in a row before leaving `Freeze`, as a frozen wallpaper always looks idle.
+/// Doubled every time it freezes again, up to `MAX_FREEZE_HOLD`, until it is back to `Full`.
+const FREEZE_HOLD: u32 = 4;
+const MAX_FREEZE_HOLD: u32 = 64;
+
+/// How hard the governor holds the wallpaper back, each level adding a lever to the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Level {
+    #[default]
+    Full,
+    /// Half the framerate
+    Framerate,
+    /// Decoded at half the resolution
+    Resolution,
+    /// Hardware decoders picked over everything else
+    Hardware,
+    /// Paused on a still frame
+    Freeze,
+}
+
+impl Level {
+    /// Factor of the window size the wallpaper is decoded at
+    pub(crate) fn decode_scale(self) -> f64 {
+        if self >= Self::Resolution {
+            0.5
+        } else {
+            1.
+        }
+    }
+
+    #[inline]
+    pub(crate) fn favor_hardware(self) -> bool {
+        self >= Self::Hardware
+    }
+
+    fn up(self) -> Self {
+        match self {
+            Self::Full => Self::Framerate,
+            Self::Framerate => Self::Resolution,
+            Self::Resolution => Self::Hardware,
+            Self::Hardware | Self::Freeze => Self::Freeze,
+        }
+    }
+
+    fn down(self) -> Self {
+        match self {
+            Self::Full | Self::Framerate => Self::Full,
+            Self::Resolution => Self::Framerate,
+            Self::Hardware => Self::Resolution,
+            Self::Freeze => Self::Hardware,
+        }
+    }
+}
+
+/// Keeps the process' CPU usage under a budget, moving one level per measurement window.
+///
+/// The usage is the whole process', so awa's own background work (library scans, thumbnails)
+/// counts against the budget too. Optimizing waits until the wallpaper is back to `Full`.
+#[derive(Debug)]
+pub(crate) struct Governor {
+    /// Fraction of one core
+    budget: f64,
+    window: Duration,
+
+    level: Level,
+    /// Start of the current window, with the CPU time used so far then
+    window_start: Option<(Instant, Duration)>,
+    /// Share of one core used in the last complete window
+    usage: Option<f64>,
+
+    /// Windows in a row below the relaxing threshold
+    calm_windows: u32,
+    /// Times it froze since it was last at `Full`
+    freezes: u32,
+}
+
+impl Governor {
+    pub(crate) fn new(config: &CpuBudgetConfig) -> Self {
+        Self {
+            budget: config.percent / 100.,
+            window: config.window(),
+            level: Level::Full,
+            window_start: None,
+            usage: None,
+            calm_windows: 0,
+            freezes: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Share of one core used in the last complete window, in percent
+    #[inline]
+    pub(crate) fn usage_percent(&self) -> Option<f64> {
+        self.usage.map(|usage| usage * 100.)
+    }
+
+    /// Feeds the CPU time the process used so far, see `process_cpu_time`. Returns the new level
+    /// if it changed.
+    pub(crate) fn update(&mut self, now: Instant, cpu_time: Duration) -> Option<Level> {
+        let Some((start, start_cpu_time)) = self.window_start else {
+            self.window_start = Some((now, cpu_time));
+            return None;
+        };
+
+        let elapsed = now.duration_since(start);
+        if elapsed < self.window {
+            return None;
+        }
+        self.window_start = Some((now, cpu_time));
+
+        let usage = cpu_time.saturating_sub(start_cpu_time).as_secs_f64() / elapsed.as_secs_f64();
+        self.usage = Some(usage);
+
+        let level = if usage < self.budget * RELAX_BELOW {
+            self.calm_windows += 1;
+            if self.level == Level::Freeze && self.calm_windows < self.freeze_hold() {
+                self.level
+            } else {
+                self.level.down()
+            }
+        } else {
+            self.calm_windows = 0;
+            if usage > self.budget {
+                self.level.up()
+            } else {
+                self.level
+            }
+        };
+
+        if level != self.level {
+            self.calm_windows = 0;
+            match level {
+                Level::Freeze => self.freezes += 1,
+                Level::Full => self.freezes = 0,
+                _ => {}
+            }
+            self.level = level;
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    /// Relaxing windows in a row needed to leave `Freeze`
+    fn freeze_hold(&self) -> u32 {
+        let doublings = self.freezes.saturating_sub(1).min(MAX_FREEZE_HOLD.ilog2());
+        (FREEZE_HOLD << doublings).min(MAX_FREEZE_HOLD)
+    }
+}
+
+/// User and system CPU time of the whole process
+#[cfg(unix)]
+pub(crate) fn process_cpu_time() -> Result<Duration, anyhow::Error> {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let time = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    Ok(time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+/// User and kernel CPU time of the whole process
+#[cfg(windows)]
+pub(crate) fn process_cpu_time() -> Result<Duration, anyhow::Error> {
+    use winapi::{
+        shared::minwindef::FILETIME,
+        um::processthreadsapi::{GetCurrentProcess, GetProcessTimes},
+    };
+
+    unsafe {
+        let mut creation: FILETIME = std::mem::zeroed();
+        let mut exit: FILETIME = std::mem::zeroed();
+        let mut kernel: FILETIME = std::mem::zeroed();
+        let mut user: FILETIME = std::mem::zeroed();
+
+        if GetProcessTimes(
+            GetCurrentProcess(),
+            &mut creation,
+            &mut exit,
+            &mut kernel,
+            &mut user,
+        ) == 0
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        // In 100 ns intervals
+        let time = |t: FILETIME| {
+            Duration::from_nanos((((t.dwHighDateTime as u64) << 32) | t.dwLowDateTime as u64) * 100)
+        };
+        Ok(time(kernel) + time(user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds one window at `percent` of a core, returning the level after it
+    fn window(governor: &mut Governor, clock: &mut (Instant, Duration), percent: f64) -> Level {
+        let window = governor.window;
+        clock.0 += window;
+        clock.1 += window.mul_f64(percent / 100.);
+        governor.update(clock.0, clock.1);
+        governor.level()
+    }
+
+    fn started() -> (Governor, (Instant, Duration)) {
+        let mut governor = Governor::new(&CpuBudgetConfig::default());
+        let clock = (Instant::now(), Duration::ZERO);
+        governor.update(clock.0, clock.1);
+        (governor, clock)
+    }
+
+    #[test]
+    fn waits_for_a_whole_window() {
+        let (mut governor, mut clock) = started();
+
+        clock.0 += governor.window / 2;
+        clock.1 += governor.window / 2;
+        assert_eq!(governor.update(clock.0, clock.1), None);
+        assert_eq!(governor.usage_percent(), None);
+
+        clock.0 += governor.window / 2;
+        assert_eq!(governor.update(clock.0, clock.1), Some(Level::Framerate));
+        assert_eq!(governor.usage_percent(), Some(50.));
+    }
+
+    #[test]
+    fn moves_one_level_per_window() {
+        let (mut governor, mut clock) = started();
+
+        for level in [
+            Level::Framerate,
+            Level::Resolution,
+            Level::Hardware,
+            Level::Freeze,
+        ] {
+            assert_eq!(window(&mut governor, &mut clock, 50.), level);
+        }
+        assert_eq!(window(&mut governor, &mut clock, 50.), Level::Freeze);
+
+        for _ in 0..FREEZE_HOLD {
+            window(&mut governor, &mut clock, 0.);
+        }
+        for level in [
+            Level::Resolution,
+            Level::Framerate,
+            Level::Full,
+            Level::Full,
+        ] {
+            assert_eq!(window(&mut governor, &mut clock, 0.), level);
+        }
+    }
+
+    #[test]
+    fn holds_between_the_thresholds() {
+        let budget = CpuBudgetConfig::default().percent;
+        let (mut governor, mut clock) = started();
+
+        window(&mut governor, &mut clock, budget * 2.);
+        window(&mut governor, &mut clock, budget * 2.);
+        assert_eq!(governor.level(), Level::Resolution);
+
+        // Under budget but above the relaxing threshold
+        for _ in 0..8 {
+            assert_eq!(
+                window(&mut governor, &mut clock, budget * 0.75),
+                Level::Resolution
+            );
+        }
+        assert_eq!(
+            window(&mut governor, &mut clock, budget * 0.25),
+            Level::Framerate
+        );
+    }
+
+    #[test]
+    fn busy_windows_restart_the_freeze_hold() {
+        let (mut governor, mut clock) = started();
+        for _ in 0..4 {
+            window(&mut governor, &mut clock, 50.);
+        }
+
+        for _ in 1..FREEZE_HOLD {
+            window(&mut governor, &mut clock, 0.);
+        }
+        assert_eq!(window(&mut governor, &mut clock, 50.), Level::Freeze);
+
+        for _ in 1..FREEZE_HOLD {
+            assert_eq!(window(&mut governor, &mut clock, 0.), Level::Freeze);
+        }
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Hardware);
+    }
+
+    #[test]
+    fn caps_the_freeze_hold() {
+        let mut governor = Governor::new(&CpuBudgetConfig::default());
+        governor.freezes = u32::MAX;
+        assert_eq!(governor.freeze_hold(), MAX_FREEZE_HOLD);
+    }
+
+    #[test]
+    fn holds_the_freeze() {
+        let mut governor = Governor::new(&CpuBudgetConfig::default());
+        let mut clock = (Instant::now(), Duration::ZERO);
+        governor.update(clock.0, clock.1);
+
+        for _ in 0..4 {
+            window(&mut governor, &mut clock, 50.);
+        }
+        assert_eq!(governor.level(), Level::Freeze);
+
+        // Idle while frozen for a while before trying again
+        for _ in 1..FREEZE_HOLD {
+            assert_eq!(window(&mut governor, &mut clock, 0.), Level::Freeze);
+        }
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Hardware);
+
+        // Longer after freezing again
+        assert_eq!(window(&mut governor, &mut clock, 50.), Level::Freeze);
+        for _ in 1..FREEZE_HOLD * 2 {
+            assert_eq!(window(&mut governor, &mut clock, 0.), Level::Freeze);
+        }
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Hardware);
+
+        // Other levels relax one per window
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Resolution);
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Framerate);
+        assert_eq!(window(&mut governor, &mut clock, 0.), Level::Full);
+    }
+}
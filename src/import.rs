@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::library::{Index, Settings};
+
+mod komorebi;
+mod lively;
+mod wallpaper_engine;
+
+/// A wallpaper of another program, as awa plays it. The importers only deserialize the parts of
+/// the program's files that end up here.
+#[derive(Debug, Clone)]
+pub(crate) struct Imported {
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    /// The media awa plays
+    pub(crate) file: PathBuf,
+    /// Only shown, see `preview`
+    pub(crate) preview: Option<PathBuf>,
+    /// Set in the program, `Kind::tag` is added when importing
+    pub(crate) tags: BTreeSet<String>,
+    pub(crate) settings: Settings,
+    /// Names of the properties awa has no equivalent for
+    pub(crate) unmapped: Vec<String>,
+}
+
+/// Programs whose wallpapers can be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    WallpaperEngine,
+    Lively,
+    Komorebi,
+}
+
+impl Kind {
+    /// Program the wallpaper at `path` belongs to, `None` if it isn't one
+    fn detect(path: &Path) -> Option<Self> {
+        if path.join(wallpaper_engine::PROJECT_FILE).is_file() {
+            Some(Self::WallpaperEngine)
+        } else if lively::is_wallpaper(path) {
+            Some(Self::Lively)
+        } else if komorebi::is_wallpaper(path) {
+            Some(Self::Komorebi)
+        } else {
+            None
+        }
+    }
+
+    /// Tag added to every wallpaper imported from this program, to find them in the library
+    fn tag(self) -> &'static str {
+        match self {
+            Self::WallpaperEngine => "wallpaper-engine",
+            Self::Lively => "lively",
+            Self::Komorebi => "komorebi",
+        }
+    }
+
+    fn read(self, path: &Path) -> Result<Imported, anyhow::Error> {
+        match self {
+            Self::WallpaperEngine => wallpaper_engine::read(path),
+            Self::Lively => lively::read(path),
+            Self::Komorebi => komorebi::read(path),
+        }
+    }
+}
+
+/// `path` joined with `relative`, which must stay inside `dir`
+pub(crate) fn inside(dir: &Path, relative: &str) -> Result<PathBuf, anyhow::Error> {
+    let path = dir.join(relative);
+    let resolved = std::fs::canonicalize(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+
+    if !resolved.starts_with(std::fs::canonicalize(dir)?) {
+        return Err(anyhow::anyhow!(
+            "{} points outside of {}",
+            relative,
+            dir.display()
+        ));
+    }
+    Ok(resolved)
+}
+
+/// The first of `names` inside `dir` which exists. Previews are only shown, so a missing or
+/// invalid one isn't an error.
+pub(crate) fn preview<'a>(dir: &Path, names: impl IntoIterator<Item = &'a str>) -> Option<PathBuf> {
+    names.into_iter().find_map(|name| inside(dir, name).ok())
+}
+
+/// `awa import`
+pub(crate) fn run(paths: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let mut index = Index::load()?;
+    let mut imported = 0;
+
+    for path in paths {
+        // A wallpaper, or a folder of them like Steam's workshop folder
+        let wallpapers = match Kind::detect(path) {
+            Some(kind) => vec![(path.clone(), kind)],
+            None => std::fs::read_dir(path)
+                .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter_map(|path| Some((path.clone(), Kind::detect(&path)?)))
+                .collect(),
+        };
+        if wallpapers.is_empty() {
+            eprintln!("Error importing {}: no wallpapers found", path.display());
+        }
+
+        for (path, kind) in wallpapers {
+            match import(&mut index, &path, kind) {
+                Ok(()) => imported += 1,
+                Err(e) => eprintln!("Error importing {}: {:#}", path.display(), e),
+            }
+        }
+    }
+
+    index.save()?;
+    println!("Imported {} wallpaper(s)", imported);
+    Ok(())
+}
+
+fn import(index: &mut Index, path: &Path, kind: Kind) -> Result<(), anyhow::Error> {
+    let imported = kind.read(path)?;
+
+    index.register(&imported.file, |entry| {
+        entry.title = imported.title.clone();
+        entry.description = imported.description.clone();
+        entry.preview = imported.preview.clone();
+        entry.tags.extend(imported.tags.iter().cloned());
+        entry.tags.insert(kind.tag().to_owned());
+        entry.settings = imported.settings;
+    })?;
+
+    println!(
+        "{} ({})",
+        imported.title.as_deref().unwrap_or("Untitled"),
+        imported.file.display()
+    );
+    if !imported.unmapped.is_empty() {
+        println!("    Not mapped: {}", imported.unmapped.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::{collections::BTreeSet, path::Path};
+
+use serde::Deserialize;
+
+use super::{inside, preview, Imported};
+use crate::library::Settings;
+
+const CONFIG_FILE: &str = "config.json";
+/// Thumbnail of video wallpapers, and the image of image ones
+const IMAGE_FILE: &str = "wallpaper.jpg";
+
+/// `config.json` of a wallpaper folder
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Config {
+    /// `image`, `video` or `web_page`
+    wallpaper_type: String,
+    video_file_name: Option<String>,
+    #[serde(default)]
+    wallpaper_parallax: bool,
+    #[serde(default)]
+    date_time_visible: bool,
+    #[serde(default)]
+    date_time_parallax: bool,
+    /// Image drawn over the wallpaper
+    #[serde(default)]
+    asset_visible: bool,
+    /// `noanimation`, `light` or `clouds`
+    asset_animation_mode: Option<String>,
+}
+
+/// Whether `dir` is a Komorebi wallpaper folder, rather than any folder with a `config.json`
+pub(crate) fn is_wallpaper(dir: &Path) -> bool {
+    read_config(dir).is_ok()
+}
+
+fn read_config(dir: &Path) -> Result<Config, anyhow::Error> {
+    let path = dir.join(CONFIG_FILE);
+    let data = std::fs::read(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))
+}
+
+/// Reads the image or video wallpaper in the folder `dir`. Komorebi doesn't title them, the
+/// folder's name is used.
+pub(crate) fn read(dir: &Path) -> Result<Imported, anyhow::Error> {
+    let config = read_config(dir)?;
+
+    // Images are shown as they are by the library, see `Entry::image`
+    let file = match config.wallpaper_type.as_str() {
+        "image" => inside(dir, IMAGE_FILE)?,
+        "video" => {
+            let file = config
+                .video_file_name
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("{} has no VideoFileName", CONFIG_FILE))?;
+            inside(dir, file)?
+        }
+        kind => {
+            return Err(anyhow::anyhow!(
+                "Komorebi {} wallpapers aren't supported, only images and videos",
+                kind
+            ))
+        }
+    };
+    let preview = preview(dir, [IMAGE_FILE]);
+
+    // Drawn by Komorebi over the wallpaper, awa has nothing like them
+    let mut unmapped = Vec::new();
+    if config.wallpaper_parallax {
+        unmapped.push("WallpaperParallax".to_owned());
+    }
+    if config.date_time_visible {
+        unmapped.push(if config.date_time_parallax {
+            "DateTimeVisible (with parallax)".to_owned()
+        } else {
+            "DateTimeVisible".to_owned()
+        });
+    }
+    if config.asset_visible {
+        unmapped.push(match &config.asset_animation_mode {
+            Some(mode) => format!("AssetVisible ({})", mode),
+            None => "AssetVisible".to_owned(),
+        });
+    }
+
+    let title = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+
+    Ok(Imported {
+        title,
+        description: None,
+        file,
+        preview,
+        tags: BTreeSet::new(),
+        settings: Settings::default(),
+        unmapped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A wallpaper folder named `name` with `config` and the given files
+    fn wallpaper(name: &str, config: &str, files: &[&str]) -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join(name);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join(CONFIG_FILE), config).unwrap();
+        for file in files {
+            std::fs::write(dir.join(file), b"").unwrap();
+        }
+        (root, dir)
+    }
+
+    #[test]
+    fn imports_images() {
+        let (_root, dir) = wallpaper(
+            "Mountains",
+            r#"{ "WallpaperType": "image", "WallpaperParallax": false }"#,
+            &[IMAGE_FILE],
+        );
+        assert!(is_wallpaper(&dir));
+
+        let imported = read(&dir).unwrap();
+        let image = std::fs::canonicalize(dir.join(IMAGE_FILE)).unwrap();
+        assert_eq!(imported.file, image);
+        assert_eq!(imported.preview, Some(image));
+        assert_eq!(imported.title.as_deref(), Some("Mountains"));
+        assert!(imported.unmapped.is_empty());
+    }
+
+    #[test]
+    fn imports_videos() {
+        let (_root, dir) = wallpaper(
+            "Rain",
+            r#"{
+                "WallpaperType": "video",
+                "VideoFileName": "rain.mp4",
+                "WallpaperParallax": true,
+                "DateTimeVisible": true,
+                "DateTimeParallax": true,
+                "AssetVisible": true,
+                "AssetAnimationMode": "clouds"
+            }"#,
+            &["rain.mp4"],
+        );
+
+        let imported = read(&dir).unwrap();
+        assert_eq!(
+            imported.file,
+            std::fs::canonicalize(dir.join("rain.mp4")).unwrap()
+        );
+        // No thumbnail
+        assert_eq!(imported.preview, None);
+        assert_eq!(
+            imported.unmapped,
+            [
+                "WallpaperParallax",
+                "DateTimeVisible (with parallax)",
+                "AssetVisible (clouds)"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_wallpapers() {
+        let (_root, dir) = wallpaper("Page", r#"{ "WallpaperType": "web_page" }"#, &[]);
+        assert!(read(&dir).is_err());
+
+        let (_root, dir) = wallpaper("Video", r#"{ "WallpaperType": "video" }"#, &[]);
+        assert!(read(&dir).is_err());
+
+        // Only inside the folder
+        let (root, dir) = wallpaper(
+            "Outside",
+            r#"{ "WallpaperType": "video", "VideoFileName": "../outside.mp4" }"#,
+            &[],
+        );
+        std::fs::write(root.path().join("outside.mp4"), b"").unwrap();
+        assert!(read(&dir).is_err());
+
+        // Any `config.json`
+        let (_root, dir) = wallpaper("Other", r#"{ "name": "other" }"#, &[]);
+        assert!(!is_wallpaper(&dir));
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use super::{inside, preview, Imported};
+use crate::{
+    cache,
+    library::Settings,
+    paths,
+    pkg::{self, Limits},
+};
+
+const INFO_FILE: &str = "LivelyInfo.json";
+
+/// Lively's `WallpaperType`, by value
+const TYPES: &[&str] = &[
+    "app",
+    "web",
+    "webaudio",
+    "url",
+    "bizhawk",
+    "unity",
+    "godot",
+    "video",
+    "gif",
+    "unityaudio",
+    "videostream",
+    "picture",
+];
+/// The types awa plays
+const SUPPORTED_TYPES: &[&str] = &["video", "gif"];
+
+/// `LivelyInfo.json`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Info {
+    title: Option<String>,
+    desc: Option<String>,
+    /// Animated, preferred over the thumbnail
+    preview: Option<String>,
+    thumbnail: Option<String>,
+    #[serde(rename = "Type")]
+    kind: Option<usize>,
+    /// Relative to the folder, unless `is_absolute_path`
+    file_name: Option<String>,
+    #[serde(default)]
+    is_absolute_path: bool,
+    /// Passed to Lively's player
+    arguments: Option<String>,
+}
+
+/// Whether `path` is a Lively wallpaper folder or package, rather than any other `.zip` archive
+pub(crate) fn is_wallpaper(path: &Path) -> bool {
+    if path.join(INFO_FILE).is_file() {
+        return true;
+    }
+
+    let is_zip = path
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("zip"));
+    is_zip
+        && File::open(path)
+            .ok()
+            .and_then(|file| zip::ZipArchive::new(file).ok())
+            .map_or(false, |mut archive| {
+                // Not the closure's value directly: the borrowed entry would outlive `archive`
+                let found = archive.by_name(INFO_FILE).is_ok();
+                found
+            })
+}
+
+/// Reads the video or GIF wallpaper in the folder or `.zip` package at `path`. Packages are
+/// unpacked to `$XDG_DATA_HOME/awa/imported/lively`, since the library points at files.
+pub(crate) fn read(path: &Path) -> Result<Imported, anyhow::Error> {
+    let dir = if path.is_dir() {
+        path.to_owned()
+    } else {
+        unpack(path)?
+    };
+
+    let info_path = dir.join(INFO_FILE);
+    let data = std::fs::read(&info_path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", info_path.display(), e))?;
+    // Written by .NET, often with a byte order mark
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+    let info: Info = serde_json::from_slice(data)
+        .map_err(|e| anyhow::anyhow!("Invalid {}: {}", info_path.display(), e))?;
+
+    let kind = info.kind.and_then(|kind| TYPES.get(kind).copied());
+    match kind {
+        Some(kind) if SUPPORTED_TYPES.contains(&kind) => {}
+        Some(kind) => {
+            return Err(anyhow::anyhow!(
+                "Lively {} wallpapers aren't supported, only videos and GIFs",
+                kind
+            ))
+        }
+        None => {
+            return Err(anyhow::anyhow!(
+                "Lively wallpaper of unknown type {:?}, only videos and GIFs are supported",
+                info.kind
+            ))
+        }
+    }
+
+    let file_name = info
+        .file_name
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("{} has no file", info_path.display()))?;
+    let file = if info.is_absolute_path {
+        // Added from elsewhere on the disk rather than packaged
+        std::fs::canonicalize(file_name)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", file_name, e))?
+    } else {
+        inside(&dir, file_name)?
+    };
+    let preview = preview(
+        &dir,
+        [&info.preview, &info.thumbnail]
+            .into_iter()
+            .flatten()
+            .map(String::as_str),
+    );
+
+    let mut unmapped = Vec::new();
+    if let Some(arguments) = info
+        .arguments
+        .filter(|arguments| !arguments.trim().is_empty())
+    {
+        unmapped.push(format!("arguments ({})", arguments));
+    }
+
+    Ok(Imported {
+        title: info.title,
+        description: info.desc.filter(|description| !description.is_empty()),
+        file,
+        preview,
+        tags: BTreeSet::new(),
+        settings: Settings::default(),
+        unmapped,
+    })
+}
+
+/// Unpacks the package at `path`, once per content, returns the folder.
+fn unpack(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let dir = paths::data_dir()
+        .join("imported")
+        .join("lively")
+        .join(format!("{:016x}", cache::hash_file(path)?));
+    if dir.join(INFO_FILE).is_file() {
+        return Ok(dir);
+    }
+
+    // Renamed once complete, so that an interrupted import is redone
+    let mut tmp = dir.clone().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    for stale in [&tmp, &dir] {
+        if stale.exists() {
+            std::fs::remove_dir_all(stale)?;
+        }
+    }
+
+    let result = extract(path, &tmp, Limits::default());
+    match result {
+        Ok(()) => {
+            std::fs::rename(&tmp, &dir)?;
+            Ok(dir)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Writes the files of the zip archive at `path` under `dir`, within `limits` like packages.
+fn extract(path: &Path, dir: &Path, limits: Limits) -> Result<(), anyhow::Error> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .map_err(|e| anyhow::anyhow!("Invalid package {}: {}", path.display(), e))?;
+    if archive.len() > limits.max_entries as usize {
+        return Err(anyhow::anyhow!(
+            "{} entries, more than the limit of {}",
+            archive.len(),
+            limits.max_entries
+        ));
+    }
+
+    let mut total = 0u64;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_owned();
+        let target = dir.join(pkg::safe_path(&name)?);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // The sizes in the archive may lie, so the reads are bounded
+        let max = limits
+            .max_entry_size
+            .min(limits.max_total_size.saturating_sub(total));
+        let mut output = File::create(&target)
+            .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", target.display(), e))?;
+        let written = std::io::copy(&mut (&mut entry).take(max + 1), &mut output)?;
+        if written > max {
+            return Err(anyhow::anyhow!(
+                "{} is larger than the limit of {} bytes",
+                name,
+                max
+            ));
+        }
+        total += written;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+
+    use super::*;
+
+    /// A wallpaper folder with a `LivelyInfo.json` of `kind` playing `clip.mp4`
+    fn wallpaper(kind: usize) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let info = format!(
+            r#"{{ "Title": "Rain", "Desc": "", "Type": {}, "FileName": "clip.mp4",
+                "Thumbnail": "thumb.jpg", "Arguments": "--loop" }}"#,
+            kind
+        );
+        // With a byte order mark, like Lively writes it
+        std::fs::write(
+            dir.path().join(INFO_FILE),
+            [b"\xef\xbb\xbf", info.as_bytes()].concat(),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("clip.mp4"), b"").unwrap();
+        std::fs::write(dir.path().join("thumb.jpg"), b"").unwrap();
+        dir
+    }
+
+    /// A zip archive of `entries` in `dir`
+    fn archive(dir: &Path, entries: &[(&str, &[u8])]) -> PathBuf {
+        let path = dir.join("wallpaper.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, content) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn imports_videos_and_gifs() {
+        // `video` and `gif`
+        for kind in [7, 8] {
+            let dir = wallpaper(kind);
+            assert!(is_wallpaper(dir.path()));
+
+            let imported = read(dir.path()).unwrap();
+            assert_eq!(imported.title.as_deref(), Some("Rain"));
+            assert_eq!(imported.description, None);
+            assert_eq!(
+                imported.file,
+                std::fs::canonicalize(dir.path().join("clip.mp4")).unwrap()
+            );
+            assert_eq!(
+                imported.preview,
+                Some(std::fs::canonicalize(dir.path().join("thumb.jpg")).unwrap())
+            );
+            assert_eq!(imported.unmapped, ["arguments (--loop)"]);
+        }
+    }
+
+    #[test]
+    fn rejects_other_types() {
+        // `web`, `picture` and out of range
+        for kind in [1, 11, TYPES.len()] {
+            assert!(read(wallpaper(kind).path()).is_err(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn detects_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_wallpaper(dir.path()));
+
+        let package = archive(dir.path(), &[(INFO_FILE, b"{}"), ("clip.mp4", b"")]);
+        assert!(is_wallpaper(&package));
+
+        let other = archive(dir.path(), &[("readme.txt", b"")]);
+        assert!(!is_wallpaper(&other));
+
+        std::fs::write(dir.path().join("broken.zip"), b"PK").unwrap();
+        assert!(!is_wallpaper(&dir.path().join("broken.zip")));
+    }
+
+    #[test]
+    fn extracts_within_the_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = archive(
+            dir.path(),
+            &[(INFO_FILE, b"{}"), ("media/clip.mp4", &[0; 100])],
+        );
+
+        let target = dir.path().join("unpacked");
+        extract(&package, &target, Limits::default()).unwrap();
+        assert_eq!(
+            std::fs::read(target.join("media/clip.mp4")).unwrap(),
+            [0; 100]
+        );
+
+        let small = Limits {
+            max_entry_size: 99,
+            ..Limits::default()
+        };
+        assert!(extract(&package, &dir.path().join("entry"), small).is_err());
+        let small = Limits {
+            max_total_size: 101,
+            ..Limits::default()
+        };
+        assert!(extract(&package, &dir.path().join("total"), small).is_err());
+        let small = Limits {
+            max_entries: 1,
+            ..Limits::default()
+        };
+        assert!(extract(&package, &dir.path().join("entries"), small).is_err());
+
+        let escaping = archive(dir.path(), &[("../escaped.txt", b"")]);
+        assert!(extract(&escaping, &dir.path().join("escaping"), Limits::default()).is_err());
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Deserialize;
+
+use super::{inside, preview, Imported};
+use crate::library::{Position, Settings};
+
+pub(crate) const PROJECT_FILE: &str = "project.json";
+
+/// Properties of Wallpaper Engine's own UI, which don't change how the wallpaper plays
+const IGNORED_PROPERTIES: &[&str] = &["schemecolor"];
+
+/// `project.json` of a workshop folder
+#[derive(Debug, Deserialize)]
+struct Project {
+    /// `video`, `scene`, `web` or `application`, in any case
+    #[serde(rename = "type", default)]
+    kind: String,
+    title: Option<String>,
+    description: Option<String>,
+    /// Relative to the folder
+    file: Option<String>,
+    preview: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    general: General,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct General {
+    /// The ones exposed to the user, by name
+    #[serde(default)]
+    properties: BTreeMap<String, Property>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Property {
+    /// Label, sometimes a translation key like `ui_browse_properties_scheme_color`
+    text: Option<String>,
+    #[serde(default)]
+    value: serde_json::Value,
+    /// Of a slider
+    max: Option<f64>,
+    /// Of a combo box
+    #[serde(default)]
+    options: Vec<Choice>,
+}
+
+/// An option of a combo box
+#[derive(Debug, Deserialize)]
+struct Choice {
+    label: Option<String>,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+impl Property {
+    /// Where the `alignment` property keeps the clip, by the label of the option picked or the
+    /// value itself. `None` for a corner or anything else awa can't place.
+    fn position(&self) -> Option<Position> {
+        let label = self
+            .options
+            .iter()
+            .find(|choice| same_value(&choice.value, &self.value))
+            .and_then(|choice| choice.label.clone());
+        let name = label
+            .or_else(|| self.value.as_str().map(str::to_owned))?
+            .to_ascii_lowercase();
+
+        let positions = [
+            ("center", Position::Center),
+            ("centre", Position::Center),
+            ("top", Position::Top),
+            ("bottom", Position::Bottom),
+            ("left", Position::Left),
+            ("right", Position::Right),
+        ];
+        let mut found = positions
+            .into_iter()
+            .filter(|(word, _)| name.contains(word))
+            .map(|(_, position)| position);
+        match (found.next(), found.next()) {
+            (Some(position), None) => Some(position),
+            _ => None,
+        }
+    }
+}
+
+/// Whether two property values are the same, combo values being numbers or strings of them
+fn same_value(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    let text = |value: &serde_json::Value| match value {
+        serde_json::Value::String(string) => string.clone(),
+        value => value.to_string(),
+    };
+    a == b || text(a) == text(b)
+}
+
+/// Reads the video wallpaper in the workshop folder `dir`.
+pub(crate) fn read(dir: &Path) -> Result<Imported, anyhow::Error> {
+    let path = dir.join(PROJECT_FILE);
+    let data = std::fs::read(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let project: Project = serde_json::from_slice(&data)
+        .map_err(|e| anyhow::anyhow!("Invalid project {}: {}", path.display(), e))?;
+
+    if !project.kind.eq_ignore_ascii_case("video") {
+        return Err(anyhow::anyhow!(
+            "{} wallpapers aren't supported, only videos",
+            if project.kind.is_empty() {
+                "Untyped"
+            } else {
+                &project.kind
+            }
+        ));
+    }
+
+    let file = project
+        .file
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("{} has no file", path.display()))?;
+    let file = inside(dir, file)?;
+    let preview = preview(dir, project.preview.as_deref());
+
+    let mut settings = Settings::default();
+    let mut unmapped = Vec::new();
+    for (name, property) in &project.general.properties {
+        let number = property.value.as_f64();
+
+        match name.as_str() {
+            "rate" => settings.rate = number.filter(|rate| *rate > 0.),
+            // Out of the slider's maximum, 100 unless the project says otherwise
+            "volume" => {
+                let max = property.max.filter(|max| *max > 0.).unwrap_or(100.);
+                settings.volume = number.map(|volume| (volume / max).clamp(0., 1.) as f32)
+            }
+            "alignment" => match property.position() {
+                Some(position) => settings.position = Some(position),
+                None => unmapped.push(format!("alignment {}", property.value)),
+            },
+            name if IGNORED_PROPERTIES.contains(&name) => {}
+            name => unmapped.push(match &property.text {
+                Some(text) if !text.starts_with("ui_") => format!("{} ({})", name, text),
+                _ => name.to_owned(),
+            }),
+        }
+    }
+
+    Ok(Imported {
+        title: project.title,
+        description: project
+            .description
+            .filter(|description| !description.is_empty()),
+        file,
+        preview,
+        tags: project.tags.into_iter().collect(),
+        settings,
+        unmapped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// A workshop folder with `project` and a `clip.mp4`
+    fn workshop(project: serde_json::Value) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(PROJECT_FILE), project.to_string()).unwrap();
+        std::fs::write(dir.path().join("clip.mp4"), b"").unwrap();
+        dir
+    }
+
+    fn property(value: serde_json::Value) -> Property {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn imports_video_projects() {
+        let dir = workshop(json!({
+            "type": "Video",
+            "title": "Rain",
+            "description": "",
+            "file": "clip.mp4",
+            "preview": "preview.gif",
+            "tags": ["Nature"],
+            "general": {
+                "properties": {
+                    "rate": { "type": "slider", "value": 0.5, "max": 2 },
+                    "volume": { "type": "slider", "value": 40, "max": 100 },
+                    "alignment": {
+                        "type": "combo",
+                        "value": "2",
+                        "options": [
+                            { "label": "ui_alignment_center", "value": "0" },
+                            { "label": "ui_alignment_top", "value": "1" },
+                            { "label": "ui_alignment_bottom", "value": "2" }
+                        ]
+                    },
+                    "schemecolor": {
+                        "text": "ui_browse_properties_scheme_color",
+                        "value": "0 0 0"
+                    },
+                    "thunder": { "text": "Thunder", "type": "bool", "value": true }
+                }
+            }
+        }));
+
+        let imported = read(dir.path()).unwrap();
+        assert_eq!(
+            imported.file,
+            std::fs::canonicalize(dir.path().join("clip.mp4")).unwrap()
+        );
+        assert_eq!(imported.title.as_deref(), Some("Rain"));
+        assert_eq!(imported.description, None);
+        // Missing
+        assert_eq!(imported.preview, None);
+        assert_eq!(imported.tags, BTreeSet::from(["Nature".to_owned()]));
+        assert_eq!(
+            imported.settings,
+            Settings {
+                rate: Some(0.5),
+                volume: Some(0.4),
+                position: Some(Position::Bottom),
+            }
+        );
+        assert_eq!(imported.unmapped, ["thunder (Thunder)"]);
+    }
+
+    #[test]
+    fn rejects_other_projects() {
+        let dir = workshop(json!({ "type": "scene", "file": "scene.json" }));
+        assert!(read(dir.path()).is_err());
+
+        let dir = workshop(json!({ "type": "video" }));
+        assert!(read(dir.path()).is_err());
+
+        let dir = workshop(json!({ "type": "video", "file": "../clip.mp4" }));
+        assert!(read(dir.path()).is_err());
+    }
+
+    #[test]
+    fn reads_the_volume_by_its_range() {
+        let volume = |property: serde_json::Value| {
+            let dir = workshop(json!({
+                "type": "video",
+                "file": "clip.mp4",
+                "general": { "properties": { "volume": property } }
+            }));
+            read(dir.path()).unwrap().settings.volume
+        };
+
+        // Percentages unless the slider says otherwise
+        assert_eq!(volume(json!({ "value": 1 })), Some(0.01));
+        assert_eq!(volume(json!({ "value": 100 })), Some(1.));
+        assert_eq!(volume(json!({ "value": 1, "max": 1 })), Some(1.));
+        assert_eq!(volume(json!({ "value": 0.25, "max": 1 })), Some(0.25));
+        assert_eq!(volume(json!({ "value": 150, "max": 100 })), Some(1.));
+        assert_eq!(volume(json!({ "value": "loud" })), None);
+    }
+
+    #[test]
+    fn places_by_alignment() {
+        assert_eq!(
+            property(json!({ "value": "left" })).position(),
+            Some(Position::Left)
+        );
+        assert_eq!(
+            property(json!({
+                "value": 1,
+                "options": [
+                    { "label": "Centered", "value": 0 },
+                    { "label": "Right", "value": 1 }
+                ]
+            }))
+            .position(),
+            Some(Position::Right)
+        );
+        // Corners and unknown values aren't mapped
+        assert_eq!(property(json!({ "value": "top left" })).position(), None);
+        assert_eq!(property(json!({ "value": 3 })).position(), None);
+    }
+}
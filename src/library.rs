@@ -0,0 +1,725 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::LibraryCommand,
+    config::LibraryConfig,
+    paths::{self, Stamp},
+    probe,
+};
+
+/// Extensions of the files picked up by scans, lowercase
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "ogv", "mpg", "mpeg", "ts", "wmv", "flv", "gif",
+];
+
+pub(crate) const MAX_RATING: u8 = 5;
+
+/// Held from loading the index to saving it by awa's threads, so that they don't undo each other
+static SAVING: Mutex<()> = Mutex::new(());
+/// The index `find` looks entries up in, with the stamp of the file it was read from
+static LOADED: Mutex<Option<(Stamp, Index)>> = Mutex::new(None);
+
+/// A wallpaper of the library, with what the Discoverer found and what the user set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Entry {
+    pub(crate) uri: String,
+    pub(crate) duration_ms: Option<u64>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    /// Caps name of the video codec, e.g. `video/x-h264`
+    pub(crate) codec: Option<String>,
+    /// A still image, shown as is rather than played, e.g. an imported Komorebi wallpaper
+    #[serde(default)]
+    pub(crate) image: bool,
+
+    #[serde(default)]
+    pub(crate) tags: BTreeSet<String>,
+    #[serde(default)]
+    pub(crate) favorite: bool,
+    /// From 1 to `MAX_RATING`, unrated if `None`
+    #[serde(default)]
+    pub(crate) rating: Option<u8>,
+    #[serde(default)]
+    pub(crate) play_count: u32,
+
+    /// Set when imported from another program
+    #[serde(default)]
+    pub(crate) title: Option<String>,
+    #[serde(default)]
+    pub(crate) description: Option<String>,
+    /// Image shown by the program it was imported from
+    #[serde(default)]
+    pub(crate) preview: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) settings: Settings,
+
+    /// Size and modification time of the file when it was probed, to notice changes
+    len: u64,
+    modified_s: u64,
+}
+
+impl Entry {
+    /// Probes the file at `path`, keeping what the user set on `previous`.
+    fn probe(
+        path: &Path,
+        (len, modified): Stamp,
+        previous: Option<&Entry>,
+    ) -> Result<Self, anyhow::Error> {
+        let uri = paths::path_to_uri(path)?;
+        let report = probe::probe(&uri)?;
+        let video = report.video.as_ref();
+
+        let mut entry = Self {
+            uri,
+            duration_ms: report.duration_ms,
+            width: video.map(|video| video.width),
+            height: video.map(|video| video.height),
+            codec: video.map(|video| video.codec.clone()),
+            image: video.map_or(false, |video| video.image),
+            tags: BTreeSet::new(),
+            favorite: false,
+            rating: None,
+            play_count: 0,
+            title: None,
+            description: None,
+            preview: None,
+            settings: Settings::default(),
+            len,
+            modified_s: modified.as_secs(),
+        };
+        if let Some(previous) = previous {
+            entry.keep_user_fields(previous);
+        }
+        Ok(entry)
+    }
+
+    /// Takes what the user set on `other`, and what an import filled in.
+    fn keep_user_fields(&mut self, other: &Entry) {
+        self.tags = other.tags.clone();
+        self.favorite = other.favorite;
+        self.rating = other.rating;
+        self.play_count = other.play_count;
+        self.title = other.title.clone();
+        self.description = other.description.clone();
+        self.preview = other.preview.clone();
+        self.settings = other.settings;
+    }
+}
+
+/// How one wallpaper plays, over the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub(crate) struct Settings {
+    /// Playback speed, 1 for normal
+    pub(crate) rate: Option<f64>,
+    /// From 0 to 1, `audio.volume` if `None`
+    pub(crate) volume: Option<f32>,
+    /// Part of the clip kept when cropping it to fill the screen, the middle if `None`
+    pub(crate) position: Option<Position>,
+}
+
+/// Which side of a clip is kept when it is cropped to the screen's aspect ratio
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Position {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// Which entries `awa library ls` lists, everything by default
+#[derive(Debug, Clone, Default)]
+struct Query {
+    tag: Option<String>,
+    min_rating: Option<u8>,
+    favorites: bool,
+}
+
+impl Query {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.tag
+            .as_ref()
+            .map_or(true, |tag| entry.tags.contains(tag))
+            && self.min_rating.map_or(true, |min| {
+                entry.rating.map_or(false, |rating| rating >= min)
+            })
+            && (!self.favorites || entry.favorite)
+    }
+}
+
+/// What a scan changed, in number of entries
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Changes {
+    pub(crate) added: usize,
+    pub(crate) updated: usize,
+    pub(crate) removed: usize,
+}
+
+impl Changes {
+    #[inline]
+    pub(crate) fn any(&self) -> bool {
+        self.added + self.updated + self.removed > 0
+    }
+
+    fn add(&mut self, other: Changes) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.removed += other.removed;
+    }
+}
+
+/// The library, persisted to `$XDG_STATE_HOME/awa/library.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Index {
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+impl Index {
+    #[inline]
+    pub(crate) fn path() -> PathBuf {
+        paths::state_dir().join("library.json")
+    }
+
+    /// Loads the index, or an empty one if there is none yet.
+    pub(crate) fn load() -> Result<Self, anyhow::Error> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = std::fs::read(&path)?;
+        serde_json::from_slice(&data)
+            .map_err(|e| anyhow::anyhow!("Invalid library {}: {}", path.display(), e))
+    }
+
+    pub(crate) fn save(&self) -> Result<(), anyhow::Error> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // The watcher and the CLI both write it
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Saves the entries probed since the index was loaded with `loaded`, over what was saved in
+    /// the meantime: probing can take long, and the CLI may have tagged or rated something since.
+    fn save_merged(self, loaded: &BTreeSet<PathBuf>) -> Result<(), anyhow::Error> {
+        let _saving = SAVING.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut latest = Self::load()?;
+
+        // Entries added in the meantime are kept, those gone while probing aren't
+        latest
+            .entries
+            .retain(|path, _| !loaded.contains(path) || self.entries.contains_key(path));
+        for (path, mut entry) in self.entries {
+            if let Some(saved) = latest.entries.get(&path) {
+                entry.keep_user_fields(saved);
+            }
+            latest.entries.insert(path, entry);
+        }
+        latest.save()
+    }
+
+    #[inline]
+    fn paths(&self) -> BTreeSet<PathBuf> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Entries matching `query`, sorted by path
+    fn query<'a>(&'a self, query: &'a Query) -> impl Iterator<Item = (&'a Path, &'a Entry)> + 'a {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| query.matches(entry))
+            .map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    /// Entry of the file at `path`, which must be in the library
+    pub(crate) fn entry_mut(&mut self, path: &Path) -> Result<&mut Entry, anyhow::Error> {
+        let path = std::fs::canonicalize(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        self.entries.get_mut(&path).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not in the library, run `awa library scan`",
+                path.display()
+            )
+        })
+    }
+
+    /// Picks up the media under `dirs` and drops the entries whose file is gone.
+    pub(crate) fn scan(&mut self, dirs: &[PathBuf]) -> Changes {
+        let mut changes = Changes::default();
+
+        for dir in dirs {
+            match std::fs::canonicalize(dir) {
+                Ok(dir) => changes.add(self.refresh(&dir)),
+                Err(e) => eprintln!("Error scanning {}: {}", dir.display(), e),
+            }
+        }
+
+        let gone = self
+            .entries
+            .keys()
+            .filter(|path| !path.is_file())
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in gone {
+            self.entries.remove(&path);
+            changes.removed += 1;
+        }
+
+        changes
+    }
+
+    /// Brings the entries at or under `path` up to date, after it was created, changed or removed.
+    pub(crate) fn refresh(&mut self, path: &Path) -> Changes {
+        self.refresh_under(path, &mut BTreeSet::new())
+    }
+
+    /// `refresh`, skipping the directories in `visited`, which symbolic links can lead back to
+    fn refresh_under(&mut self, path: &Path, visited: &mut BTreeSet<PathBuf>) -> Changes {
+        let mut changes = Changes::default();
+
+        if path.is_dir() {
+            let first_visit = std::fs::canonicalize(path).map_or(false, |dir| visited.insert(dir));
+            if !first_visit {
+                return changes;
+            }
+            let Ok(children) = std::fs::read_dir(path) else {
+                return changes;
+            };
+            for child in children.filter_map(|child| child.ok()) {
+                changes.add(self.refresh_under(&child.path(), visited));
+            }
+        } else if path.is_file() && is_media(path) {
+            changes.add(self.refresh_file(path));
+        } else {
+            // Removed, with everything under it if it was a directory
+            let before = self.entries.len();
+            self.entries.retain(|entry, _| !entry.starts_with(path));
+            changes.removed += before - self.entries.len();
+        }
+
+        changes
+    }
+
+    fn refresh_file(&mut self, path: &Path) -> Changes {
+        self.probe_file(path).unwrap_or_else(|e| {
+            // Possibly still being written, probed again on the next change
+            eprintln!("Error adding {} to the library: {:#}", path.display(), e);
+            Changes::default()
+        })
+    }
+
+    /// Probes the file at `path` if it is new or changed.
+    fn probe_file(&mut self, path: &Path) -> Result<Changes, anyhow::Error> {
+        let mut changes = Changes::default();
+        let stamp = paths::stamp(path)
+            .ok_or_else(|| anyhow::anyhow!("Failed to read the metadata of {}", path.display()))?;
+
+        let previous = self.entries.get(path);
+        let (len, modified) = stamp;
+        let unchanged = |entry: &Entry| (entry.len, entry.modified_s) == (len, modified.as_secs());
+        if previous.map_or(false, unchanged) {
+            return Ok(changes);
+        }
+
+        let entry = Entry::probe(path, stamp, previous)?;
+        if self.entries.insert(path.to_owned(), entry).is_some() {
+            changes.updated += 1;
+        } else {
+            changes.added += 1;
+        }
+        Ok(changes)
+    }
+
+    /// Adds the file at `path` wherever it is, then lets `import` fill in what its program knew.
+    pub(crate) fn register(
+        &mut self,
+        path: &Path,
+        import: impl FnOnce(&mut Entry),
+    ) -> Result<Changes, anyhow::Error> {
+        let path = std::fs::canonicalize(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        let changes = self.probe_file(&path)?;
+
+        if let Some(entry) = self.entries.get_mut(&path) {
+            import(entry);
+        }
+        Ok(changes)
+    }
+
+    /// Counts a play of `uri`, if it is in the library.
+    fn record_play(&mut self, uri: &str) -> bool {
+        match self.entries.values_mut().find(|entry| entry.uri == uri) {
+            Some(entry) => {
+                entry.play_count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn is_media(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            MEDIA_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+        })
+}
+
+/// Entry of `uri` in the library, if it is there. The index is only read again once it changed.
+pub(crate) fn find(uri: &str) -> Option<Entry> {
+    let stamp = paths::stamp(&Index::path())?;
+    let mut loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if loaded.as_ref().map_or(true, |(loaded, _)| *loaded != stamp) {
+        *loaded = Some((stamp, Index::load().ok()?));
+    }
+    let (_, index) = loaded.as_ref()?;
+    index
+        .entries
+        .values()
+        .find(|entry| entry.uri == uri)
+        .cloned()
+}
+
+/// Counts a play of `uri` in the library, if it is there.
+pub(crate) fn record_play(uri: &str) {
+    let _saving = SAVING.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = Index::load().and_then(|mut index| {
+        if index.record_play(uri) {
+            index.save()?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("Error counting the play in the library: {:#}", e);
+    }
+}
+
+/// Scans the library's directories, then keeps the index up to date on a thread of its own for
+/// as long as awa runs.
+pub(crate) fn spawn_watcher(config: LibraryConfig) {
+    if config.dirs.is_empty() {
+        return;
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("awa-library".to_owned())
+        .spawn(move || {
+            if let Err(e) = watch(&config) {
+                eprintln!("Error watching the library: {:#}", e);
+            }
+        });
+    if let Err(e) = spawned {
+        eprintln!("Error watching the library: {:#}", e);
+    }
+}
+
+fn watch(config: &LibraryConfig) -> Result<(), anyhow::Error> {
+    // Picks up what changed while awa wasn't running
+    let mut index = Index::load()?;
+    let loaded = index.paths();
+    if index.scan(&config.dirs).any() {
+        index.save_merged(&loaded)?;
+    }
+    if !config.watch {
+        return Ok(());
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    for dir in &config.dirs {
+        // Events come under the watched path, the entries under the canonical one
+        let watched = std::fs::canonicalize(dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|dir| Ok(watcher.watch(&dir, RecursiveMode::Recursive)?));
+        if let Err(e) = watched {
+            eprintln!("Error watching {}: {:#}", dir.display(), e);
+        }
+    }
+
+    for event in rx {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Error watching the library: {:#}", e);
+                continue;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            continue;
+        }
+
+        let mut index = match Index::load() {
+            Ok(index) => index,
+            Err(e) => {
+                eprintln!("Error loading the library: {:#}", e);
+                continue;
+            }
+        };
+        let loaded = index.paths();
+        let mut changes = Changes::default();
+        for path in &event.paths {
+            changes.add(index.refresh(path));
+        }
+        if changes.any() {
+            if let Err(e) = index.save_merged(&loaded) {
+                eprintln!("Error saving the library: {:#}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `awa library`
+pub(crate) fn run(command: LibraryCommand, config: &LibraryConfig) -> Result<(), anyhow::Error> {
+    let mut index = Index::load()?;
+
+    match command {
+        LibraryCommand::Scan => {
+            if config.dirs.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "No library directories, set library.dirs in {}",
+                    crate::config::Config::path().display()
+                ));
+            }
+
+            let changes = index.scan(&config.dirs);
+            index.save()?;
+            println!(
+                "{} added, {} updated, {} removed",
+                changes.added, changes.updated, changes.removed
+            );
+        }
+        LibraryCommand::Ls {
+            tag,
+            min_rating,
+            favorites,
+            json,
+        } => {
+            let query = Query {
+                tag,
+                min_rating,
+                favorites,
+            };
+            let entries = index.query(&query).collect::<BTreeMap<_, _>>();
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for (path, entry) in entries {
+                    print_entry(path, entry);
+                }
+            }
+        }
+        LibraryCommand::Tag { file, tags, remove } => {
+            let entry = index.entry_mut(&file)?;
+            for tag in tags {
+                if remove {
+                    entry.tags.remove(&tag);
+                } else {
+                    entry.tags.insert(tag);
+                }
+            }
+            index.save()?;
+        }
+        LibraryCommand::Rate { file, rating } => {
+            index.entry_mut(&file)?.rating = (rating > 0).then_some(rating);
+            index.save()?;
+        }
+        LibraryCommand::Favorite { file, off } => {
+            index.entry_mut(&file)?.favorite = !off;
+            index.save()?;
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(path: &Path, entry: &Entry) {
+    let mut details = Vec::new();
+    if let (Some(width), Some(height)) = (entry.width, entry.height) {
+        details.push(format!("{}x{}", width, height));
+    }
+    if let Some(ms) = entry.duration_ms {
+        details.push(format!("{:.1}s", ms as f64 / 1000.));
+    }
+    if let Some(rating) = entry.rating {
+        details.push(format!("{}/{}", rating, MAX_RATING));
+    }
+    if entry.favorite {
+        details.push("favorite".to_owned());
+    }
+    details.push(format!("played {}", entry.play_count));
+    if !entry.tags.is_empty() {
+        details.push(
+            entry
+                .tags
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+
+    match &entry.title {
+        Some(title) => println!("{} ({})", title, path.display()),
+        None => println!("{}", path.display()),
+    }
+    println!("    {}", details.join(" | "));
+    if let Some(description) = &entry.description {
+        println!("    {}", description);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uri: &str) -> Entry {
+        Entry {
+            uri: uri.to_owned(),
+            duration_ms: Some(10_000),
+            width: Some(1920),
+            height: Some(1080),
+            codec: Some("video/x-h264".to_owned()),
+            image: false,
+            tags: BTreeSet::new(),
+            favorite: false,
+            rating: None,
+            play_count: 0,
+            title: None,
+            description: None,
+            preview: None,
+            settings: Settings::default(),
+            len: 1,
+            modified_s: 1,
+        }
+    }
+
+    #[test]
+    fn queries_entries() {
+        let mut rain = entry("file:///rain.mp4");
+        rain.tags.insert("nature".to_owned());
+        rain.rating = Some(4);
+        let mut city = entry("file:///city.mp4");
+        city.favorite = true;
+        city.rating = Some(2);
+
+        let matching = |query: &Query| {
+            [&rain, &city]
+                .into_iter()
+                .filter(|entry| query.matches(entry))
+                .map(|entry| entry.uri.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matching(&Query::default()).len(), 2);
+        let nature = Query {
+            tag: Some("nature".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(matching(&nature), ["file:///rain.mp4"]);
+        let rated = Query {
+            min_rating: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(matching(&rated), ["file:///rain.mp4"]);
+        let favorites = Query {
+            favorites: true,
+            ..Default::default()
+        };
+        assert_eq!(matching(&favorites), ["file:///city.mp4"]);
+    }
+
+    #[test]
+    fn keeps_what_the_user_set() {
+        let mut saved = entry("file:///rain.mp4");
+        saved.tags.insert("nature".to_owned());
+        saved.favorite = true;
+        saved.rating = Some(5);
+        saved.play_count = 3;
+        saved.title = Some("Rain".to_owned());
+        saved.settings.rate = Some(0.5);
+
+        let mut probed = entry("file:///rain.mp4");
+        probed.width = Some(3840);
+        probed.keep_user_fields(&saved);
+        assert_eq!(probed.width, Some(3840));
+        assert_eq!(probed.tags, saved.tags);
+        assert!(probed.favorite);
+        assert_eq!(probed.rating, Some(5));
+        assert_eq!(probed.play_count, 3);
+        assert_eq!(probed.title.as_deref(), Some("Rain"));
+        assert_eq!(probed.settings, saved.settings);
+    }
+
+    #[test]
+    fn counts_plays() {
+        let mut index = Index::default();
+        index
+            .entries
+            .insert(PathBuf::from("/rain.mp4"), entry("file:///rain.mp4"));
+
+        assert!(index.record_play("file:///rain.mp4"));
+        assert!(index.record_play("file:///rain.mp4"));
+        assert!(!index.record_play("file:///city.mp4"));
+        assert_eq!(index.entries[Path::new("/rain.mp4")].play_count, 2);
+    }
+
+    #[test]
+    fn drops_removed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(dir.path()).unwrap();
+        let kept = dir.join("kept.mp4");
+        std::fs::write(&kept, b"").unwrap();
+
+        let mut index = Index::default();
+        for path in [&kept, &dir.join("gone/a.mp4"), &dir.join("gone/b.mp4")] {
+            index
+                .entries
+                .insert(path.clone(), entry("file:///clip.mp4"));
+        }
+
+        let changes = index.refresh(&dir.join("gone"));
+        assert_eq!(changes.removed, 2);
+        assert_eq!(index.paths(), BTreeSet::from([kept]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn survives_symlink_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::os::unix::fs::symlink(dir.path(), sub.join("parent")).unwrap();
+        std::fs::write(sub.join("notes.txt"), b"").unwrap();
+
+        let mut index = Index::default();
+        assert!(!index.refresh(dir.path()).any());
+        assert!(index.entries.is_empty());
+    }
+
+    #[test]
+    fn picks_media_by_extension() {
+        assert!(is_media(Path::new("clip.MP4")));
+        assert!(is_media(Path::new("loop.webm")));
+        assert!(!is_media(Path::new("notes.txt")));
+        assert!(!is_media(Path::new("mp4")));
+    }
+}
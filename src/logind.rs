@@ -0,0 +1,49 @@
+use futures_util::StreamExt;
+
+use crate::{activity::Reason, app::App};
+
+const DESTINATION: &str = "org.freedesktop.login1";
+const MANAGER_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+/// Pauses `app` while the session is locked or the system sleeps, following logind's
+/// `Lock`/`Unlock` and `PrepareForSleep` signals on the system bus.
+pub(crate) async fn watch(app: App) {
+    if let Err(e) = watch_signals(&app).await {
+        eprintln!("Error watching logind, not pausing when locked: {:#}", e);
+    }
+}
+
+async fn watch_signals(app: &App) -> Result<(), anyhow::Error> {
+    let connection = zbus::Connection::system().await?;
+
+    let manager =
+        zbus::Proxy::new(&connection, DESTINATION, MANAGER_PATH, MANAGER_INTERFACE).await?;
+    // Signals are sent from the real path, not from `session/auto`
+    let session_path: zbus::zvariant::OwnedObjectPath =
+        manager.call("GetSession", &("auto",)).await?;
+    let session =
+        zbus::Proxy::new(&connection, DESTINATION, session_path, SESSION_INTERFACE).await?;
+
+    let mut sleep = manager.receive_signal("PrepareForSleep").await?;
+    let mut lock = session.receive_signal("Lock").await?;
+    let mut unlock = session.receive_signal("Unlock").await?;
+
+    // Locked before awa started
+    if let Ok(locked) = session.get_property::<bool>("LockedHint").await {
+        app.pause(Reason::Locked, locked).await;
+    }
+
+    loop {
+        tokio::select! {
+            Some(msg) = sleep.next() => {
+                let sleeping: bool = msg.body()?;
+                app.pause(Reason::Sleeping, sleeping).await;
+            }
+            Some(_) = lock.next() => app.pause(Reason::Locked, true).await,
+            Some(_) = unlock.next() => app.pause(Reason::Locked, false).await,
+            else => return Err(anyhow::anyhow!("Lost the connection to the system bus")),
+        }
+    }
+}
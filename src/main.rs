@@ -1,12 +1,81 @@
+use clap::Parser;
+
+use cli::{Cli, Command};
+use config::Config;
 use main_loop::MainLoop;
 
+mod activity;
 mod app;
 mod audio;
+mod cache;
+mod cli;
+mod config;
+mod decoder;
+mod doctor;
 mod frame_mgr;
+mod governor;
+mod import;
+mod library;
+#[cfg(target_os = "linux")]
+mod logind;
 mod main_loop;
+mod paths;
+mod pkg;
 mod platform_specific;
+mod power;
+mod probe;
+mod reactive;
+mod recovery;
+mod scene;
+mod slideshow;
+mod source;
+mod status;
+mod still;
+mod tex;
+mod thumbnail;
 mod video;
+mod visualizer;
 
 fn main() {
-    MainLoop::new(60.).run();
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        None => run(),
+        Some(Command::Devices) => audio::print_devices(),
+        Some(Command::Doctor) => doctor::run(),
+        Some(Command::Probe { file, json }) => probe::print(&file, json),
+        Some(Command::Cache {
+            file,
+            width,
+            height,
+        }) => cache::print_generate(&file, width, height),
+        Some(Command::Optimize {
+            file,
+            width,
+            height,
+            no_audio,
+        }) => cache::optimize::print(&file, width, height, no_audio),
+        Some(Command::Thumbnail { files, force }) => thumbnail::print(&files, force),
+        Some(Command::Import { paths }) => import::run(&paths),
+        Some(Command::Pkg { command }) => pkg::run(command),
+        Some(Command::Tex { file, output }) => tex::convert(&file, &output),
+        Some(Command::Status { json }) => status::print(json),
+        Some(Command::Library { command }) => {
+            Config::load().and_then(|config| library::run(command, &config.library))
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> ! {
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Error loading config: {:#}", e);
+        Config::default()
+    });
+
+    MainLoop::new(config).run();
 }
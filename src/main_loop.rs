@@ -5,7 +5,12 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
-use crate::{app::App, frame_mgr::FrameManager, platform_specific};
+use crate::{
+    app::App,
+    config::{Config, Wallpaper},
+    frame_mgr::FrameManager,
+    library, platform_specific,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum Message {
@@ -25,7 +30,7 @@ pub(crate) struct MainLoop {
 }
 
 impl MainLoop {
-    pub(crate) fn new(framerate: f64) -> Self {
+    pub(crate) fn new(config: Config) -> Self {
         let event_loop = EventLoopBuilder::<Message>::with_user_event().build();
 
         //TODO: Multiple monitor support
@@ -46,13 +51,19 @@ impl MainLoop {
 
         platform_specific::set_desktop_window(&window);
 
-        let app = App::new(&window);
+        if let Wallpaper::Video { uri } = &config.wallpaper {
+            library::record_play(uri);
+        }
+        library::spawn_watcher(config.library.clone());
+
+        let frame_mgr = FrameManager::new(config.framerate);
+        let app = App::new(&window, config);
         let input_helper = winit_input_helper::WinitInputHelper::new();
 
         Self {
             event_loop,
             window,
-            frame_mgr: FrameManager::new(framerate),
+            frame_mgr,
             app,
             input_helper,
         }
@@ -85,7 +96,11 @@ impl MainLoop {
             }
         });
 
+        #[cfg(target_os = "linux")]
+        runtime.spawn(crate::logind::watch(app.clone()));
+
         let app_ref2 = app.clone();
+        let app_ref3 = app.clone();
         let event_loop_proxy = event_loop.create_proxy();
 
         runtime.spawn(async move {
@@ -98,6 +113,10 @@ impl MainLoop {
                                 app_ref2.update_scale_factor(*scale_factor).await;
                             }
 
+                            WindowEvent::Occluded(occluded) => {
+                                app_ref2.set_occluded(*occluded).await;
+                            }
+
                             WindowEvent::Resized(size) => {
                                 if let Err(e) = app_ref2.update_surface_size(*size).await {
                                     eprintln!("Error resizing: {}", e);
@@ -119,6 +138,11 @@ impl MainLoop {
         });
 
         event_loop.run(move |event, _, control_flow| {
+            if let Event::LoopDestroyed = event {
+                runtime.block_on(app_ref3.shutdown());
+                return;
+            }
+
             if input.update(&event) {
                 if input.close_requested() || input.key_pressed(VirtualKeyCode::Escape) {
                     *control_flow = ControlFlow::Exit;
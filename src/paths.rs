@@ -0,0 +1,71 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Size and modification time of a file, which change whenever it is written
+pub(crate) type Stamp = (u64, Duration);
+
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(fallback))
+        })
+        .unwrap_or_else(std::env::temp_dir)
+        .join("awa")
+}
+
+/// `$XDG_CONFIG_HOME/awa`
+pub(crate) fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_STATE_HOME/awa`
+pub(crate) fn state_dir() -> PathBuf {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_DATA_HOME/awa`
+pub(crate) fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// `$XDG_CACHE_HOME/awa`
+pub(crate) fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_RUNTIME_DIR/awa`, or a temporary directory without it
+pub(crate) fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(std::env::temp_dir)
+        .join("awa")
+}
+
+/// Turns a command line argument into a URI, e.g. `clip.mp4` into `file:///home/me/clip.mp4`
+pub(crate) fn to_uri(arg: &str) -> Result<String, anyhow::Error> {
+    if arg.contains("://") {
+        return Ok(arg.to_owned());
+    }
+
+    path_to_uri(Path::new(arg))
+}
+
+pub(crate) fn path_to_uri(path: &Path) -> Result<String, anyhow::Error> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    Ok(gst::glib::filename_to_uri(path, None)?.to_string())
+}
+
+/// `Stamp` of the file at `path`
+pub(crate) fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified))
+}
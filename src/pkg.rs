@@ -0,0 +1,414 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::cli::PkgCommand;
+
+/// Start of the version string, e.g. `PKGV0001`
+const MAGIC: &str = "PKGV";
+
+/// Bounds on what an archive may claim, so that a corrupt or hostile one can't exhaust memory
+/// or disk space
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) max_entries: u32,
+    pub(crate) max_name_len: u32,
+    pub(crate) max_entry_size: u64,
+    /// Of all entries together
+    pub(crate) max_total_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_entries: 65_536,
+            max_name_len: 4096,
+            max_entry_size: 1 << 30,
+            max_total_size: 4 << 30,
+        }
+    }
+}
+
+/// A file in the archive
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Entry {
+    /// Relative path with `/` separators, e.g. `materials/background.tex`
+    pub(crate) name: String,
+    /// From the start of the archive
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+/// A Wallpaper Engine `.pkg` archive: a length-prefixed version string, the number of entries,
+/// each entry's length-prefixed name with its offset and size, then the entries' data. Offsets
+/// are relative to the end of that header. Integers are little-endian `u32`.
+pub(crate) struct Pkg<R> {
+    reader: R,
+    pub(crate) version: String,
+    entries: Vec<Entry>,
+}
+
+impl Pkg<BufReader<File>> {
+    pub(crate) fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        Self::read(BufReader::new(file), Limits::default())
+            .map_err(|e| e.context(format!("Invalid package {}", path.display())))
+    }
+}
+
+impl<R: Read + Seek> Pkg<R> {
+    /// Reads the header and checks every entry against `limits` and the archive's length.
+    pub(crate) fn read(mut reader: R, limits: Limits) -> Result<Self, anyhow::Error> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let version = read_string(&mut reader, 16)?;
+        if !version.starts_with(MAGIC) {
+            return Err(anyhow::anyhow!("Not a package, starts with {:?}", version));
+        }
+
+        let count = read_u32(&mut reader)?;
+        if count > limits.max_entries {
+            return Err(anyhow::anyhow!(
+                "{} entries, more than the limit of {}",
+                count,
+                limits.max_entries
+            ));
+        }
+
+        let mut entries = (0..count)
+            .map(|_| {
+                let name = read_string(&mut reader, limits.max_name_len)?;
+                let offset = read_u32(&mut reader)? as u64;
+                let size = read_u32(&mut reader)? as u64;
+                Ok(Entry { name, offset, size })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        let data_start = reader.stream_position()?;
+        let mut total = 0u64;
+        for entry in &mut entries {
+            entry.offset += data_start;
+
+            if entry.size > limits.max_entry_size {
+                return Err(anyhow::anyhow!(
+                    "{} is {} bytes, more than the limit of {}",
+                    entry.name,
+                    entry.size,
+                    limits.max_entry_size
+                ));
+            }
+            total += entry.size;
+            if total > limits.max_total_size {
+                return Err(anyhow::anyhow!(
+                    "Entries take more than the limit of {} bytes",
+                    limits.max_total_size
+                ));
+            }
+            if entry.offset + entry.size > len {
+                return Err(anyhow::anyhow!(
+                    "{} ends past the end of the package",
+                    entry.name
+                ));
+            }
+        }
+
+        Ok(Self {
+            reader,
+            version,
+            entries,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Entry named `name`, e.g. `scene.json`
+    pub(crate) fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Content of the entry named `name`
+    pub(crate) fn read_entry(&mut self, name: &str) -> Result<Vec<u8>, anyhow::Error> {
+        let entry = self
+            .find(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No {} in the package", name))?;
+
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = vec![0; entry.size as usize];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Writes the entries, all of them if `names` is empty, under `dir`. Never overwrites a file
+    /// and never writes outside of `dir`.
+    pub(crate) fn extract(&mut self, dir: &Path, names: &[String]) -> Result<usize, anyhow::Error> {
+        let entries = if names.is_empty() {
+            self.entries.clone()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    self.find(name)
+                        .cloned()
+                        .ok_or_else(|| anyhow::anyhow!("No {} in the package", name))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // Checked before writing anything
+        let paths = entries
+            .iter()
+            .map(|entry| Ok(dir.join(safe_path(&entry.name)?)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        for (entry, path) in entries.iter().zip(&paths) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // Fails on existing files and symlinks alike
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path.display(), e))?;
+
+            self.reader.seek(SeekFrom::Start(entry.offset))?;
+            let written = std::io::copy(&mut (&mut self.reader).take(entry.size), &mut file)?;
+            if written != entry.size {
+                return Err(anyhow::anyhow!("{} is truncated", entry.name));
+            }
+        }
+        Ok(entries.len())
+    }
+}
+
+/// Relative path of an entry, refusing names which could escape the extraction directory
+pub(crate) fn safe_path(name: &str) -> Result<PathBuf, anyhow::Error> {
+    let unsafe_name = || anyhow::anyhow!("Unsafe entry name {:?}", name);
+
+    // `\` and `:` would be separators or drive letters on Windows
+    if name.is_empty() || name.contains(['\0', ':', '\\']) {
+        return Err(unsafe_name());
+    }
+
+    let mut path = PathBuf::new();
+    for part in name.split('/') {
+        match Path::new(part).components().next() {
+            // Doubled separators
+            None => continue,
+            Some(Component::Normal(_)) => path.push(part),
+            _ => return Err(unsafe_name()),
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        return Err(unsafe_name());
+    }
+    Ok(path)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, anyhow::Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads a `u32` length followed by that many bytes of UTF-8, at most `max_len`.
+fn read_string(reader: &mut impl Read, max_len: u32) -> Result<String, anyhow::Error> {
+    let len = read_u32(reader)?;
+    if len > max_len {
+        return Err(anyhow::anyhow!(
+            "String of {} bytes, more than the limit of {}",
+            len,
+            max_len
+        ));
+    }
+
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// `awa pkg`
+pub(crate) fn run(command: PkgCommand) -> Result<(), anyhow::Error> {
+    match command {
+        PkgCommand::Ls { file, json } => {
+            let pkg = Pkg::open(&file)?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(pkg.entries())?);
+            } else {
+                println!("{} ({} entries)", pkg.version, pkg.entries().len());
+                for entry in pkg.entries() {
+                    println!("{:>12}  {}", entry.size, entry.name);
+                }
+            }
+        }
+        PkgCommand::Extract { file, dir, entries } => {
+            let mut pkg = Pkg::open(&file)?;
+            let extracted = pkg.extract(&dir, &entries)?;
+            println!("Extracted {} entries to {}", extracted, dir.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A package of `entries`, with the given version string
+    fn package(version: &str, entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let string = |data: &mut Vec<u8>, string: &str| {
+            data.extend((string.len() as u32).to_le_bytes());
+            data.extend(string.as_bytes());
+        };
+
+        string(&mut data, version);
+        data.extend((entries.len() as u32).to_le_bytes());
+        let mut offset = 0;
+        for (name, content) in entries {
+            string(&mut data, name);
+            data.extend((offset as u32).to_le_bytes());
+            data.extend((content.len() as u32).to_le_bytes());
+            offset += content.len();
+        }
+        for (_, content) in entries {
+            data.extend(*content);
+        }
+        data
+    }
+
+    fn read(data: Vec<u8>, limits: Limits) -> Result<Pkg<Cursor<Vec<u8>>>, anyhow::Error> {
+        Pkg::read(Cursor::new(data), limits)
+    }
+
+    const ENTRIES: &[(&str, &[u8])] = &[
+        ("scene.json", b"{}"),
+        ("materials/background.tex", b"TEXV0005"),
+        ("empty", b""),
+    ];
+
+    #[test]
+    fn reads_entries() {
+        let mut pkg = read(package("PKGV0001", ENTRIES), Limits::default()).unwrap();
+
+        assert_eq!(pkg.version, "PKGV0001");
+        let names = pkg.entries().iter().map(|entry| entry.name.as_str());
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            ["scene.json", "materials/background.tex", "empty"]
+        );
+        assert_eq!(pkg.find("scene.json").unwrap().size, 2);
+
+        for (name, content) in ENTRIES {
+            assert_eq!(pkg.read_entry(name).unwrap(), *content);
+        }
+        assert!(pkg.read_entry("missing").is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read(package("GIF89a", ENTRIES), Limits::default()).is_err());
+        assert!(read(Vec::new(), Limits::default()).is_err());
+    }
+
+    #[test]
+    fn rejects_entries_past_the_end() {
+        let mut data = package("PKGV0001", ENTRIES);
+        data.pop();
+        assert!(read(data, Limits::default()).is_err());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let data = || package("PKGV0001", ENTRIES);
+        assert!(read(data(), Limits::default()).is_ok());
+
+        let limits = [
+            Limits {
+                max_entries: 2,
+                ..Limits::default()
+            },
+            Limits {
+                max_name_len: 10,
+                ..Limits::default()
+            },
+            Limits {
+                max_entry_size: 7,
+                ..Limits::default()
+            },
+            Limits {
+                max_total_size: 9,
+                ..Limits::default()
+            },
+        ];
+        for limits in limits {
+            assert!(read(data(), limits).is_err(), "{:?}", limits);
+        }
+    }
+
+    #[test]
+    fn safe_paths() {
+        assert_eq!(
+            safe_path("materials//background.tex").unwrap(),
+            Path::new("materials").join("background.tex")
+        );
+
+        for name in [
+            "",
+            "/",
+            "../escape",
+            "materials/../../escape",
+            "/etc/passwd",
+            "..\\escape",
+            "materials\\background.tex",
+            "C:escape",
+            "nul\0byte",
+        ] {
+            assert!(safe_path(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn extracts_without_overwriting() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pkg = read(package("PKGV0001", ENTRIES), Limits::default()).unwrap();
+
+        assert_eq!(pkg.extract(dir.path(), &[]).unwrap(), ENTRIES.len());
+        for (name, content) in ENTRIES {
+            assert_eq!(std::fs::read(dir.path().join(name)).unwrap(), *content);
+        }
+
+        std::fs::write(dir.path().join("scene.json"), "kept").unwrap();
+        assert!(pkg.extract(dir.path(), &["scene.json".to_owned()]).is_err());
+        assert_eq!(
+            std::fs::read(dir.path().join("scene.json")).unwrap(),
+            b"kept"
+        );
+    }
+
+    #[test]
+    fn refuses_unsafe_names_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries: &[(&str, &[u8])] = &[("first", b"1"), ("../escape", b"2")];
+        let mut pkg = read(package("PKGV0001", entries), Limits::default()).unwrap();
+
+        assert!(pkg.extract(&dir.path().join("out"), &[]).is_err());
+        assert!(!dir.path().join("out").exists());
+        assert!(!dir.path().join("escape").exists());
+    }
+}
@@ -1,5 +1,18 @@
 #![allow(unused_imports)]
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
 use cfg_if::cfg_if;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    window::Window,
+};
 
 cfg_if! {
     if #[cfg(target_os = "macos")] {
@@ -8,6 +21,8 @@ cfg_if! {
     }
     else if #[cfg(target_os = "linux")] {
         mod linux;
+        mod wayland;
+        mod x11;
         pub(crate) use linux::*;
 
     } else if #[cfg(target_os = "windows")] {
@@ -18,3 +33,185 @@ cfg_if! {
         compile_error!("Unsupported platform!");
     }
 }
+
+/// Resolution of the primary monitor, for commands which run without a window
+pub(crate) fn primary_monitor_size() -> Result<PhysicalSize<u32>, anyhow::Error> {
+    let event_loop = winit::event_loop::EventLoop::new();
+
+    event_loop
+        .primary_monitor()
+        .or_else(|| event_loop.available_monitors().next())
+        .map(|monitor| monitor.size())
+        .ok_or_else(|| anyhow::anyhow!("No monitor found"))
+}
+
+/// Monitor the wallpaper is shown on, in physical pixels
+#[derive(Debug, Clone)]
+pub(crate) struct MonitorArea {
+    pub(crate) name: Option<String>,
+    pub(crate) position: PhysicalPosition<i32>,
+    pub(crate) size: PhysicalSize<u32>,
+}
+
+impl MonitorArea {
+    pub(crate) fn of(window: &Window) -> Option<Self> {
+        let monitor = window.current_monitor()?;
+
+        Some(Self {
+            name: monitor.name(),
+            position: monitor.position(),
+            size: monitor.size(),
+        })
+    }
+
+    pub(crate) fn contains(&self, x: i32, y: i32) -> bool {
+        let (left, top) = (self.position.x as i64, self.position.y as i64);
+        let (x, y) = (x as i64, y as i64);
+
+        x >= left
+            && y >= top
+            && x < left + self.size.width as i64
+            && y < top + self.size.height as i64
+    }
+}
+
+/// Watches whether a fullscreen or maximized window covers the monitor, on a thread of its own
+/// since the platform connections aren't `Send`.
+pub(crate) struct Coverage {
+    covered: Arc<AtomicBool>,
+
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Coverage {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Coverage {
+    pub(crate) fn spawn(area: MonitorArea, poll: Duration) -> Result<Self, anyhow::Error> {
+        let covered = Arc::new(AtomicBool::new(false));
+        let covered_ref = covered.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_ref = stop.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("awa-coverage".to_owned())
+            .spawn(move || {
+                let mut probe = match CoverProbe::open(&area) {
+                    Ok(Some(probe)) => probe,
+                    // Only occlusion events from winit then
+                    Ok(None) => return,
+                    Err(e) => {
+                        eprintln!("Error watching windows, not pausing when covered: {:#}", e);
+                        return;
+                    }
+                };
+
+                while !stop_ref.load(Ordering::Acquire) {
+                    match probe.covered() {
+                        Ok(covered) => covered_ref.store(covered, Ordering::Release),
+                        Err(e) => {
+                            eprintln!("Error watching windows, not pausing when covered: {:#}", e);
+                            covered_ref.store(false, Ordering::Release);
+                            break;
+                        }
+                    }
+
+                    std::thread::park_timeout(poll);
+                }
+            })?;
+
+        Ok(Self {
+            covered,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn covered(&self) -> bool {
+        self.covered.load(Ordering::Acquire)
+    }
+}
+
+/// How present the user is, as seen by the window system
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct PresenceState {
+    /// Time since the last input
+    pub(crate) idle: Duration,
+    /// The monitors are powered down (DPMS)
+    pub(crate) display_off: bool,
+}
+
+/// Polls the user's idle time and the monitors' power state on a thread of its own.
+pub(crate) struct Presence {
+    state: Arc<std::sync::Mutex<PresenceState>>,
+
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Presence {
+    pub(crate) fn spawn(poll: Duration) -> Result<Self, anyhow::Error> {
+        let state = Arc::new(std::sync::Mutex::new(PresenceState::default()));
+        let state_ref = state.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_ref = stop.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("awa-presence".to_owned())
+            .spawn(move || {
+                let mut probe = match PresenceProbe::open() {
+                    Ok(Some(probe)) => probe,
+                    Ok(None) => return,
+                    Err(e) => {
+                        eprintln!("Error watching idle time, not pausing when idle: {:#}", e);
+                        return;
+                    }
+                };
+
+                while !stop_ref.load(Ordering::Acquire) {
+                    match probe.state() {
+                        Ok(state) => *state_ref.lock().unwrap() = state,
+                        Err(e) => {
+                            eprintln!("Error watching idle time, not pausing when idle: {:#}", e);
+                            *state_ref.lock().unwrap() = PresenceState::default();
+                            break;
+                        }
+                    }
+
+                    std::thread::park_timeout(poll);
+                }
+            })?;
+
+        Ok(Self {
+            state,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn state(&self) -> PresenceState {
+        *self.state.lock().unwrap()
+    }
+}
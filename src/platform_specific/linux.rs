@@ -2,6 +2,51 @@
 
 use winit::window::Window;
 
+use super::{wayland, x11, MonitorArea, PresenceState};
+
 pub(crate) fn set_desktop_window(window: &Window) {
     todo!();
 }
+
+/// Covering windows of the session's display server
+pub(crate) enum CoverProbe {
+    Wayland(wayland::CoverProbe),
+    X11(x11::CoverProbe),
+}
+
+impl CoverProbe {
+    pub(crate) fn open(area: &MonitorArea) -> Result<Option<Self>, anyhow::Error> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Ok(Some(Self::Wayland(wayland::CoverProbe::open(area)?)))
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Ok(Some(Self::X11(x11::CoverProbe::open(area)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub(crate) fn covered(&mut self) -> Result<bool, anyhow::Error> {
+        match self {
+            Self::Wayland(probe) => probe.covered(),
+            Self::X11(probe) => probe.covered(),
+        }
+    }
+}
+
+/// Idle time and DPMS state, only known on X11
+pub(crate) struct PresenceProbe(x11::PresenceProbe);
+
+impl PresenceProbe {
+    pub(crate) fn open() -> Result<Option<Self>, anyhow::Error> {
+        if std::env::var_os("DISPLAY").is_some() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            Ok(Some(Self(x11::PresenceProbe::open()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[inline]
+    pub(crate) fn state(&mut self) -> Result<PresenceState, anyhow::Error> {
+        self.0.state()
+    }
+}
@@ -49,3 +49,29 @@ pub(crate) fn set_desktop_window(window: &Window) {
         set_collection_behavior(obj, true, 1 << 8);
     }
 }
+
+/// Covering windows are only noticed through winit's occlusion events on macOS.
+pub(crate) struct CoverProbe;
+
+impl CoverProbe {
+    pub(crate) fn open(_area: &super::MonitorArea) -> Result<Option<Self>, anyhow::Error> {
+        Ok(None)
+    }
+
+    pub(crate) fn covered(&mut self) -> Result<bool, anyhow::Error> {
+        Ok(false)
+    }
+}
+
+/// Idle time isn't watched on macOS.
+pub(crate) struct PresenceProbe;
+
+impl PresenceProbe {
+    pub(crate) fn open() -> Result<Option<Self>, anyhow::Error> {
+        Ok(None)
+    }
+
+    pub(crate) fn state(&mut self) -> Result<super::PresenceState, anyhow::Error> {
+        Ok(Default::default())
+    }
+}
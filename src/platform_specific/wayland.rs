@@ -0,0 +1,201 @@
+use wayland_client::{
+    event_created_child,
+    protocol::{
+        wl_output::{self, WlOutput},
+        wl_registry::{self, WlRegistry},
+    },
+    Connection, Dispatch, EventQueue, QueueHandle,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use super::MonitorArea;
+
+// Values of `zwlr_foreign_toplevel_handle_v1.state`
+const STATE_MAXIMIZED: u32 = 0;
+const STATE_MINIMIZED: u32 = 1;
+const STATE_FULLSCREEN: u32 = 3;
+
+#[derive(Debug)]
+struct Toplevel {
+    handle: ZwlrForeignToplevelHandleV1,
+    outputs: Vec<WlOutput>,
+    states: Vec<u32>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// With their names, from `wl_output` version 4
+    outputs: Vec<(WlOutput, Option<String>)>,
+    manager: Option<ZwlrForeignToplevelManagerV1>,
+    toplevels: Vec<Toplevel>,
+}
+
+/// Tracks the toplevels of wlroots-based compositors with wlr-foreign-toplevel-management.
+pub(crate) struct CoverProbe {
+    // Keeps the connection alive
+    _connection: Connection,
+    queue: EventQueue<State>,
+    state: State,
+    /// Name of the wallpaper's monitor, matched against the outputs
+    name: Option<String>,
+}
+
+impl CoverProbe {
+    pub(crate) fn open(area: &MonitorArea) -> Result<Self, anyhow::Error> {
+        let connection = Connection::connect_to_env()?;
+        let mut queue = connection.new_event_queue();
+        let qh = queue.handle();
+        connection.display().get_registry(&qh, ());
+
+        let mut state = State::default();
+        // Globals, then the outputs' names and the existing toplevels
+        queue.roundtrip(&mut state)?;
+        if state.manager.is_none() {
+            return Err(anyhow::anyhow!(
+                "The compositor doesn't support wlr-foreign-toplevel-management"
+            ));
+        }
+        queue.roundtrip(&mut state)?;
+
+        Ok(Self {
+            _connection: connection,
+            queue,
+            state,
+            name: area.name.clone(),
+        })
+    }
+
+    pub(crate) fn covered(&mut self) -> Result<bool, anyhow::Error> {
+        self.queue.roundtrip(&mut self.state)?;
+
+        // Any output if the monitor's name is unknown to the compositor
+        let named = self
+            .state
+            .outputs
+            .iter()
+            .find(|(_, name)| name.is_some() && *name == self.name)
+            .map(|(output, _)| output);
+
+        Ok(self.state.toplevels.iter().any(|toplevel| {
+            let has = |state| toplevel.states.contains(&state);
+            let on_monitor = named.map_or(!toplevel.outputs.is_empty(), |output| {
+                toplevel.outputs.contains(output)
+            });
+
+            on_monitor && !has(STATE_MINIMIZED) && (has(STATE_FULLSCREEN) || has(STATE_MAXIMIZED))
+        }))
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for State {
+    fn event(
+        state: &mut Self,
+        registry: &WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        {
+            match interface.as_str() {
+                "wl_output" => {
+                    let output = registry.bind::<WlOutput, _, _>(name, version.min(4), qh, ());
+                    state.outputs.push((output, None));
+                }
+                "zwlr_foreign_toplevel_manager_v1" => {
+                    state.manager = Some(registry.bind(name, version.min(3), qh, ()));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Dispatch<WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            if let Some((_, n)) = state.outputs.iter_mut().find(|(o, _)| o == output) {
+                *n = Some(name);
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } = event {
+            state.toplevels.push(Toplevel {
+                handle: toplevel,
+                outputs: Vec::new(),
+                states: Vec::new(),
+            });
+        }
+    }
+
+    event_created_child!(State, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        use zwlr_foreign_toplevel_handle_v1::Event;
+
+        if let Event::Closed = event {
+            state
+                .toplevels
+                .retain(|toplevel| toplevel.handle != *handle);
+            handle.destroy();
+            return;
+        }
+
+        let Some(toplevel) = state
+            .toplevels
+            .iter_mut()
+            .find(|toplevel| toplevel.handle == *handle)
+        else {
+            return;
+        };
+
+        match event {
+            Event::OutputEnter { output } => toplevel.outputs.push(output),
+            Event::OutputLeave { output } => toplevel.outputs.retain(|o| *o != output),
+            Event::State { state: states } => {
+                toplevel.states = states
+                    .chunks_exact(4)
+                    .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,12 +1,16 @@
 #![allow(dead_code)]
 
-use std::mem;
+use std::{mem, time::Duration};
 
 use winapi::{
-    shared::windef::HWND,
-    um::winuser::{
-        CloseWindow, EnumWindows, FindWindowExA, FindWindowExW, GetDesktopWindow,
-        SendMessageTimeoutW, SetParent, SMTO_NORMAL,
+    shared::windef::{HWND, RECT},
+    um::{
+        sysinfoapi::GetTickCount,
+        winuser::{
+            CloseWindow, EnumWindows, FindWindowExA, FindWindowExW, GetClassNameA,
+            GetDesktopWindow, GetForegroundWindow, GetLastInputInfo, GetWindowRect, IsIconic,
+            IsZoomed, SendMessageTimeoutW, SetParent, LASTINPUTINFO, SMTO_NORMAL,
+        },
     },
 };
 use winit::{platform::windows::WindowExtWindows, window::Window};
@@ -64,3 +68,76 @@ pub(crate) fn set_desktop_window(window: &Window) {
         SetParent(window_hwnd, workerw);
     }
 }
+
+/// Checks the foreground window, which covers the monitor if it is maximized or as large as it.
+pub(crate) struct CoverProbe {
+    area: super::MonitorArea,
+}
+
+impl CoverProbe {
+    pub(crate) fn open(area: &super::MonitorArea) -> Result<Option<Self>, anyhow::Error> {
+        Ok(Some(Self { area: area.clone() }))
+    }
+
+    pub(crate) fn covered(&mut self) -> Result<bool, anyhow::Error> {
+        unsafe {
+            let hwnd = GetForegroundWindow();
+            if hwnd.is_null() || IsIconic(hwnd) != 0 {
+                return Ok(false);
+            }
+
+            // The desktop itself, which the wallpaper is part of
+            let mut class = [0u8; 64];
+            let len = GetClassNameA(hwnd, class.as_mut_ptr() as _, class.len() as _);
+            if matches!(&class[..len.max(0) as usize], b"Progman" | b"WorkerW") {
+                return Ok(false);
+            }
+
+            let mut rect: RECT = mem::zeroed();
+            if GetWindowRect(hwnd, &mut rect) == 0 {
+                return Ok(false);
+            }
+
+            let center = ((rect.left + rect.right) / 2, (rect.top + rect.bottom) / 2);
+            if !self.area.contains(center.0, center.1) {
+                return Ok(false);
+            }
+
+            let area = &self.area;
+            let fills = rect.left <= area.position.x
+                && rect.top <= area.position.y
+                && rect.right >= area.position.x + area.size.width as i32
+                && rect.bottom >= area.position.y + area.size.height as i32;
+
+            Ok(fills || IsZoomed(hwnd) != 0)
+        }
+    }
+}
+
+/// Idle time from `GetLastInputInfo`. The monitors' power state isn't known.
+pub(crate) struct PresenceProbe;
+
+impl PresenceProbe {
+    pub(crate) fn open() -> Result<Option<Self>, anyhow::Error> {
+        Ok(Some(Self))
+    }
+
+    pub(crate) fn state(&mut self) -> Result<super::PresenceState, anyhow::Error> {
+        unsafe {
+            let mut info = LASTINPUTINFO {
+                cbSize: mem::size_of::<LASTINPUTINFO>() as _,
+                dwTime: 0,
+            };
+            if GetLastInputInfo(&mut info) == 0 {
+                return Err(anyhow::anyhow!("GetLastInputInfo failed"));
+            }
+
+            // Both wrap around after 49.7 days
+            let idle = GetTickCount().wrapping_sub(info.dwTime);
+            Ok(super::PresenceState {
+                idle: Duration::from_millis(idle as u64),
+                display_off: false,
+            })
+        }
+    }
+}
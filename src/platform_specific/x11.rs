@@ -0,0 +1,291 @@
+use std::{
+    ffi::CString,
+    mem,
+    os::raw::{c_int, c_uchar, c_ulong},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use ::x11::{dpms, xlib, xss};
+
+use super::{MonitorArea, PresenceState};
+
+type ErrorHandler = unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int;
+
+/// Xlib's error handler is process-wide, so traps are taken one at a time
+static TRAP: Mutex<()> = Mutex::new(());
+static TRAPPED_DISPLAY: AtomicPtr<xlib::Display> = AtomicPtr::new(ptr::null_mut());
+static TRAPPED_ERROR: AtomicBool = AtomicBool::new(false);
+static PREVIOUS_HANDLER: Mutex<Option<ErrorHandler>> = Mutex::new(None);
+
+unsafe extern "C" fn trap_error(
+    display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    if display == TRAPPED_DISPLAY.load(Ordering::SeqCst) {
+        TRAPPED_ERROR.store(true, Ordering::SeqCst);
+        return 0;
+    }
+
+    // Other connections, e.g. winit's, keep their handling
+    let previous = *PREVIOUS_HANDLER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    match previous {
+        Some(handler) => handler(display, event),
+        None => 0,
+    }
+}
+
+/// Runs `f`, turning the X errors it causes on `display` into an `Err` instead of letting Xlib's
+/// default handler exit the process, e.g. for a window destroyed in the meantime.
+unsafe fn trapping_errors<T>(
+    display: *mut xlib::Display,
+    f: impl FnOnce() -> T,
+) -> Result<T, anyhow::Error> {
+    let _trap = TRAP.lock().unwrap_or_else(PoisonError::into_inner);
+
+    // Errors of earlier requests aren't ours to trap
+    xlib::XSync(display, xlib::False);
+    TRAPPED_ERROR.store(false, Ordering::SeqCst);
+    TRAPPED_DISPLAY.store(display, Ordering::SeqCst);
+    let previous = xlib::XSetErrorHandler(Some(trap_error));
+    *PREVIOUS_HANDLER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = previous;
+
+    let result = f();
+
+    // Delivers the errors of asynchronous requests
+    xlib::XSync(display, xlib::False);
+    xlib::XSetErrorHandler(previous);
+    TRAPPED_DISPLAY.store(ptr::null_mut(), Ordering::SeqCst);
+
+    if TRAPPED_ERROR.swap(false, Ordering::SeqCst) {
+        Err(anyhow::anyhow!("X request failed"))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Checks `_NET_ACTIVE_WINDOW` for `_NET_WM_STATE_FULLSCREEN` or both maximized states, through a
+/// connection of its own.
+pub(crate) struct CoverProbe {
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    area: MonitorArea,
+
+    active_window: xlib::Atom,
+    wm_state: xlib::Atom,
+    fullscreen: xlib::Atom,
+    maximized_vert: xlib::Atom,
+    maximized_horz: xlib::Atom,
+    hidden: xlib::Atom,
+}
+
+impl Drop for CoverProbe {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+impl CoverProbe {
+    pub(crate) fn open(area: &MonitorArea) -> Result<Self, anyhow::Error> {
+        unsafe {
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return Err(anyhow::anyhow!("Failed to open the X display"));
+            }
+
+            let atom = |name: &str| {
+                let name = CString::new(name).unwrap();
+                xlib::XInternAtom(display, name.as_ptr(), xlib::False)
+            };
+
+            Ok(Self {
+                display,
+                root: xlib::XDefaultRootWindow(display),
+                area: area.clone(),
+                active_window: atom("_NET_ACTIVE_WINDOW"),
+                wm_state: atom("_NET_WM_STATE"),
+                fullscreen: atom("_NET_WM_STATE_FULLSCREEN"),
+                maximized_vert: atom("_NET_WM_STATE_MAXIMIZED_VERT"),
+                maximized_horz: atom("_NET_WM_STATE_MAXIMIZED_HORZ"),
+                hidden: atom("_NET_WM_STATE_HIDDEN"),
+            })
+        }
+    }
+
+    /// Values of a 32-bit `property` of `window`, empty if it isn't set.
+    unsafe fn property(
+        &self,
+        window: xlib::Window,
+        property: xlib::Atom,
+        kind: xlib::Atom,
+    ) -> Vec<c_ulong> {
+        let mut actual_type = 0;
+        let mut actual_format: c_int = 0;
+        let mut count = 0;
+        let mut bytes_after = 0;
+        let mut data: *mut c_uchar = ptr::null_mut();
+
+        let status = xlib::XGetWindowProperty(
+            self.display,
+            window,
+            property,
+            0,
+            1024,
+            xlib::False,
+            kind,
+            &mut actual_type,
+            &mut actual_format,
+            &mut count,
+            &mut bytes_after,
+            &mut data,
+        );
+        // 0 : Success
+        if status != 0 || data.is_null() {
+            return Vec::new();
+        }
+
+        // Format 32 properties are returned as longs
+        let values = if actual_format == 32 {
+            std::slice::from_raw_parts(data as *const c_ulong, count as usize).to_vec()
+        } else {
+            Vec::new()
+        };
+        xlib::XFree(data as _);
+        values
+    }
+
+    pub(crate) fn covered(&mut self) -> Result<bool, anyhow::Error> {
+        // The active window can be destroyed at any time, then it covers nothing
+        unsafe {
+            Ok(trapping_errors(self.display, || self.active_window_covers()).unwrap_or(false))
+        }
+    }
+
+    unsafe fn active_window_covers(&self) -> bool {
+        let window = match self
+            .property(self.root, self.active_window, xlib::XA_WINDOW)
+            .first()
+        {
+            Some(&window) if window != 0 => window,
+            _ => return false,
+        };
+
+        let states = self.property(window, self.wm_state, xlib::XA_ATOM);
+        let has = |atom| states.contains(&atom);
+        if has(self.hidden) {
+            return false;
+        }
+        let fills = has(self.fullscreen) || (has(self.maximized_vert) && has(self.maximized_horz));
+        if !fills {
+            return false;
+        }
+
+        // Only counts if it is on the wallpaper's monitor
+        let mut attributes: xlib::XWindowAttributes = mem::zeroed();
+        if xlib::XGetWindowAttributes(self.display, window, &mut attributes) == 0 {
+            return false;
+        }
+        let (mut x, mut y, mut child) = (0, 0, 0);
+        let translated = xlib::XTranslateCoordinates(
+            self.display,
+            window,
+            self.root,
+            0,
+            0,
+            &mut x,
+            &mut y,
+            &mut child,
+        );
+        if translated == 0 {
+            return false;
+        }
+
+        self.area
+            .contains(x + attributes.width / 2, y + attributes.height / 2)
+    }
+}
+
+/// Idle time from the MIT-SCREEN-SAVER extension and the monitors' state from DPMS, through a
+/// connection of its own.
+pub(crate) struct PresenceProbe {
+    display: *mut xlib::Display,
+    root: xlib::Window,
+    /// Null if it couldn't be allocated, the session never counts as idle then
+    info: *mut xss::XScreenSaverInfo,
+    dpms: bool,
+}
+
+impl Drop for PresenceProbe {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.info.is_null() {
+                xlib::XFree(self.info as _);
+            }
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+impl PresenceProbe {
+    pub(crate) fn open() -> Result<Self, anyhow::Error> {
+        unsafe {
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return Err(anyhow::anyhow!("Failed to open the X display"));
+            }
+
+            let (mut event_base, mut error_base) = (0, 0);
+            if xss::XScreenSaverQueryExtension(display, &mut event_base, &mut error_base) == 0 {
+                xlib::XCloseDisplay(display);
+                return Err(anyhow::anyhow!(
+                    "The X server doesn't support the MIT-SCREEN-SAVER extension"
+                ));
+            }
+
+            let info = xss::XScreenSaverAllocInfo();
+            if info.is_null() {
+                eprintln!("Error allocating the screen saver info, idle time won't be read");
+            }
+
+            Ok(Self {
+                display,
+                root: xlib::XDefaultRootWindow(display),
+                info,
+                dpms: dpms::DPMSCapable(display) != 0,
+            })
+        }
+    }
+
+    pub(crate) fn state(&mut self) -> Result<PresenceState, anyhow::Error> {
+        unsafe {
+            let idle = if self.info.is_null() {
+                Duration::ZERO
+            } else {
+                if xss::XScreenSaverQueryInfo(self.display, self.root, self.info) == 0 {
+                    return Err(anyhow::anyhow!("XScreenSaverQueryInfo failed"));
+                }
+                Duration::from_millis((*self.info).idle as u64)
+            };
+
+            let display_off = self.dpms && {
+                let mut level = 0;
+                let mut enabled = 0;
+                dpms::DPMSInfo(self.display, &mut level, &mut enabled);
+                // 0 : DPMSModeOn
+                enabled != 0 && level != 0
+            };
+
+            Ok(PresenceState { idle, display_off })
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::Path;
+
+use crate::config::{PowerAction, PowerConfig};
+
+/// Where the kernel lists batteries and chargers
+pub(crate) const SYSFS_POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct PowerState {
+    /// No charger is online and a battery is discharging
+    pub(crate) on_battery: bool,
+    /// Charge of the batteries from 0 to 100, `None` without any
+    pub(crate) charge: Option<f32>,
+}
+
+/// Reads the power supplies listed in `root`, which is `SYSFS_POWER_SUPPLY` outside of tests.
+pub(crate) fn read(root: &Path) -> Result<PowerState, anyhow::Error> {
+    let attribute = |supply: &Path, name: &str| {
+        std::fs::read_to_string(supply.join(name))
+            .map(|value| value.trim().to_owned())
+            .ok()
+    };
+
+    let mut charger_online = false;
+    let mut discharging = false;
+    // Energy-weighted when the batteries report it, averaged otherwise
+    let mut capacities = Vec::new();
+
+    for entry in std::fs::read_dir(root)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", root.display(), e))?
+    {
+        let supply = entry?.path();
+
+        match attribute(&supply, "type").as_deref() {
+            Some("Mains" | "USB") => {
+                charger_online |= attribute(&supply, "online").as_deref() == Some("1");
+            }
+            Some("Battery") => {
+                // Peripherals such as mice report their batteries too
+                if attribute(&supply, "scope").as_deref() == Some("Device")
+                    || attribute(&supply, "present").as_deref() == Some("0")
+                {
+                    continue;
+                }
+
+                discharging |= attribute(&supply, "status").as_deref() == Some("Discharging");
+                if let Some(capacity) =
+                    attribute(&supply, "capacity").and_then(|c| c.parse::<f32>().ok())
+                {
+                    let weight = attribute(&supply, "energy_full")
+                        .and_then(|e| e.parse::<f32>().ok())
+                        .unwrap_or(1.);
+                    capacities.push((capacity, weight));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let total_weight = capacities.iter().map(|(_, weight)| weight).sum::<f32>();
+    let charge = (total_weight > 0.).then(|| {
+        capacities
+            .iter()
+            .map(|(capacity, weight)| capacity * weight)
+            .sum::<f32>()
+            / total_weight
+    });
+
+    Ok(PowerState {
+        on_battery: !charger_online && discharging,
+        charge,
+    })
+}
+
+/// What the matching profiles of `config` ask for
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) struct Restrictions {
+    /// Lowest framerate asked for
+    pub(crate) framerate: Option<f64>,
+    pub(crate) mute: bool,
+    /// Pause on the current frame
+    pub(crate) still: bool,
+    /// Stop the wallpaper and show the fallback
+    pub(crate) stop: bool,
+}
+
+impl Restrictions {
+    pub(crate) fn of(config: &PowerConfig, state: PowerState) -> Self {
+        let mut restrictions = Self::default();
+        if !state.on_battery {
+            return restrictions;
+        }
+
+        let matching = config.profiles.iter().filter(|profile| {
+            match (profile.below, state.charge) {
+                (None, _) => true,
+                (Some(below), Some(charge)) => charge < below,
+                // Unknown charge, not low as far as we know
+                (Some(_), None) => false,
+            }
+        });
+
+        for profile in matching {
+            match profile.action {
+                PowerAction::Framerate { framerate } => {
+                    restrictions.framerate = Some(
+                        restrictions
+                            .framerate
+                            .map_or(framerate, |current| current.min(framerate)),
+                    );
+                }
+                PowerAction::Mute => restrictions.mute = true,
+                PowerAction::Still => restrictions.still = true,
+                PowerAction::Stop => restrictions.stop = true,
+            }
+        }
+
+        restrictions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::PowerProfile;
+
+    use super::*;
+
+    /// Adds a power supply with `attributes` under the fake sysfs `root`.
+    fn supply(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let dir = root.join(name);
+        std::fs::create_dir(&dir).unwrap();
+        for (attribute, value) in attributes {
+            // With the kernel's trailing newline
+            std::fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    /// A charger, online or not, and a battery discharging unless the charger is online
+    fn laptop(online: bool, capacity: &str) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        supply(
+            root.path(),
+            "AC",
+            &[
+                ("type", "Mains"),
+                ("online", if online { "1" } else { "0" }),
+            ],
+        );
+        supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("present", "1"),
+                ("status", if online { "Charging" } else { "Discharging" }),
+                ("capacity", capacity),
+            ],
+        );
+        root
+    }
+
+    fn config() -> PowerConfig {
+        PowerConfig {
+            profiles: vec![
+                PowerProfile {
+                    below: None,
+                    action: PowerAction::Framerate { framerate: 15. },
+                },
+                PowerProfile {
+                    below: Some(20.),
+                    action: PowerAction::Stop,
+                },
+            ],
+            ..PowerConfig::default()
+        }
+    }
+
+    #[test]
+    fn on_ac() {
+        let root = laptop(true, "15");
+        let state = read(root.path()).unwrap();
+
+        assert_eq!(
+            state,
+            PowerState {
+                on_battery: false,
+                charge: Some(15.),
+            }
+        );
+        assert_eq!(Restrictions::of(&config(), state), Restrictions::default());
+    }
+
+    #[test]
+    fn on_battery_below_the_threshold() {
+        let root = laptop(false, "15");
+        let state = read(root.path()).unwrap();
+
+        assert!(state.on_battery);
+        let restrictions = Restrictions::of(&config(), state);
+        assert_eq!(restrictions.framerate, Some(15.));
+        assert!(restrictions.stop);
+    }
+
+    #[test]
+    fn on_battery_above_the_threshold() {
+        let root = laptop(false, "80");
+        let state = read(root.path()).unwrap();
+
+        assert!(state.on_battery);
+        let restrictions = Restrictions::of(&config(), state);
+        assert_eq!(restrictions.framerate, Some(15.));
+        assert!(!restrictions.stop);
+    }
+
+    #[test]
+    fn weighs_batteries_and_skips_peripherals() {
+        let root = laptop(false, "20");
+        std::fs::write(root.path().join("BAT0/energy_full"), "10000000\n").unwrap();
+        supply(
+            root.path(),
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "80"),
+                ("energy_full", "30000000"),
+            ],
+        );
+        supply(
+            root.path(),
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+
+        let state = read(root.path()).unwrap();
+        assert_eq!(state.charge, Some(65.));
+    }
+
+    #[test]
+    fn without_supplies() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(read(root.path()).unwrap(), PowerState::default());
+        assert!(read(&root.path().join("missing")).is_err());
+    }
+}
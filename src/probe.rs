@@ -0,0 +1,247 @@
+use gst::ElementFactory;
+use gst_pbutils::{prelude::*, Discoverer, DiscovererResult};
+use serde::Serialize;
+
+use crate::decoder;
+
+const TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(15);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ProbeReport {
+    pub(crate) uri: String,
+    /// Caps name of the container, e.g. `video/quicktime`
+    pub(crate) container: Option<String>,
+    pub(crate) duration_ms: Option<u64>,
+    pub(crate) seekable: bool,
+    pub(crate) video: Option<VideoStream>,
+    pub(crate) audio: Option<AudioStream>,
+    /// Hardware decoders of this platform which accept the video stream
+    pub(crate) hardware_decoders: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct VideoStream {
+    /// Caps name of the codec, e.g. `video/x-h264`
+    pub(crate) codec: String,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) framerate: Option<f64>,
+    pub(crate) alpha: bool,
+    /// A still image rather than a video
+    pub(crate) image: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct AudioStream {
+    pub(crate) codec: String,
+    pub(crate) channels: u32,
+    pub(crate) sample_rate: u32,
+}
+
+/// Inspects the media at `uri` without playing it.
+pub(crate) fn probe(uri: &str) -> Result<ProbeReport, anyhow::Error> {
+    gst::init()?;
+
+    let discoverer = Discoverer::new(TIMEOUT)?;
+    let info = discoverer.discover_uri(uri)?;
+
+    match info.result() {
+        DiscovererResult::Ok => {}
+        DiscovererResult::MissingPlugins => {
+            return Err(anyhow::anyhow!(
+                "Missing plugins to play {}, run `awa doctor`",
+                uri
+            ))
+        }
+        result => return Err(anyhow::anyhow!("Failed to probe {}: {:?}", uri, result)),
+    }
+
+    let container = info
+        .container_streams()
+        .first()
+        .and_then(|stream| stream.caps())
+        .and_then(|caps| caps_name(&caps));
+
+    let video_info = info.video_streams().into_iter().next();
+    let video_caps = video_info.as_ref().and_then(|video| video.caps());
+
+    let video = video_info.as_ref().map(|video| {
+        let framerate = video.framerate();
+        VideoStream {
+            codec: video_caps.as_ref().and_then(caps_name).unwrap_or_default(),
+            width: video.width(),
+            height: video.height(),
+            framerate: (framerate.numer() > 0 && framerate.denom() > 0)
+                .then(|| framerate.numer() as f64 / framerate.denom() as f64),
+            alpha: video_caps.as_ref().map_or(false, has_alpha),
+            image: video.is_image(),
+        }
+    });
+
+    let audio = info
+        .audio_streams()
+        .into_iter()
+        .next()
+        .map(|audio| AudioStream {
+            codec: audio
+                .caps()
+                .as_ref()
+                .and_then(caps_name)
+                .unwrap_or_default(),
+            channels: audio.channels(),
+            sample_rate: audio.sample_rate(),
+        });
+
+    let hardware_decoders = video_caps
+        .as_ref()
+        .map(hardware_decoders_for)
+        .unwrap_or_default();
+
+    Ok(ProbeReport {
+        uri: uri.to_owned(),
+        container,
+        duration_ms: info.duration().map(|d| d.mseconds()),
+        seekable: info.is_seekable(),
+        video,
+        audio,
+        hardware_decoders,
+    })
+}
+
+/// Hardware decoders which accept `caps`
+pub(crate) fn hardware_decoders_for(caps: &gst::Caps) -> Vec<String> {
+    ElementFactory::factories_with_type(gst::ElementFactoryType::DECODER, gst::Rank::None)
+        .into_iter()
+        .filter(|factory| decoder::is_hardware_factory(factory) && factory.can_sink_any_caps(caps))
+        .map(|factory| factory.name().to_string())
+        .collect()
+}
+
+fn caps_name(caps: &gst::Caps) -> Option<String> {
+    caps.structure(0).map(|s| s.name().to_string())
+}
+
+/// Whether the stream of `caps` may have transparent pixels, from the codec's fields since only
+/// raw caps have a pixel format
+fn has_alpha(caps: &gst::Caps) -> bool {
+    let Some(s) = caps.structure(0) else {
+        return false;
+    };
+
+    match s.name().as_str() {
+        "video/x-raw" => gst_video::VideoInfo::from_caps(caps)
+            .map_or(false, |info| info.format_info().has_alpha()),
+        // Set by matroskademux from WebM's `AlphaMode`
+        "video/x-vp8" | "video/x-vp9" => {
+            s.get::<bool>("codec-alpha").unwrap_or(false)
+                || s.get::<i32>("alpha-mode").map_or(false, |mode| mode != 0)
+        }
+        // Only the 4444 profiles carry an alpha channel
+        "video/x-prores" => s
+            .get::<&str>("variant")
+            .map_or(false, |variant| variant.starts_with("4444")),
+        // QuickTime Animation, ARGB at 32 bits
+        "video/x-rle" => s.get::<i32>("depth").map_or(false, |depth| depth == 32),
+        _ => false,
+    }
+}
+
+impl std::fmt::Display for ProbeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "URI:        {}", self.uri)?;
+        writeln!(
+            f,
+            "Container:  {}",
+            self.container.as_deref().unwrap_or("(none)")
+        )?;
+        match self.duration_ms {
+            Some(ms) => writeln!(f, "Duration:   {:.2}s", ms as f64 / 1000.)?,
+            None => writeln!(f, "Duration:   (unknown)")?,
+        }
+        writeln!(f, "Seekable:   {}", self.seekable)?;
+
+        match &self.video {
+            Some(video) => {
+                writeln!(f, "Video:      {}", video.codec)?;
+                writeln!(f, "Resolution: {}x{}", video.width, video.height)?;
+                match (video.image, video.framerate) {
+                    (true, _) => writeln!(f, "Framerate:  (still image)")?,
+                    (false, Some(fps)) => writeln!(f, "Framerate:  {:.3}", fps)?,
+                    (false, None) => writeln!(f, "Framerate:  (variable)")?,
+                }
+                writeln!(f, "Alpha:      {}", video.alpha)?;
+            }
+            None => writeln!(f, "Video:      (none)")?,
+        }
+
+        match &self.audio {
+            Some(audio) => writeln!(
+                f,
+                "Audio:      {}, {} channel(s), {} Hz",
+                audio.codec, audio.channels, audio.sample_rate
+            )?,
+            None => writeln!(f, "Audio:      (none)")?,
+        }
+
+        if self.hardware_decoders.is_empty() {
+            write!(f, "Hardware:   (software decoding)")
+        } else {
+            write!(f, "Hardware:   {}", self.hardware_decoders.join(", "))
+        }
+    }
+}
+
+/// `awa probe`
+pub(crate) fn print(arg: &str, json: bool) -> Result<(), anyhow::Error> {
+    let report = probe(&crate::paths::to_uri(arg)?)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(caps: &str) -> gst::Caps {
+        gst::init().unwrap();
+        caps.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_alpha_in_raw_formats() {
+        assert!(has_alpha(&caps(
+            "video/x-raw, format=RGBA, width=4, height=4"
+        )));
+        assert!(!has_alpha(&caps(
+            "video/x-raw, format=NV12, width=4, height=4"
+        )));
+        // Without a size, which `VideoInfo` needs
+        assert!(!has_alpha(&caps("video/x-raw, format=RGBA")));
+    }
+
+    #[test]
+    fn finds_alpha_in_codecs() {
+        assert!(has_alpha(&caps("video/x-vp9, codec-alpha=true")));
+        assert!(has_alpha(&caps("video/x-vp8, alpha-mode=1")));
+        assert!(!has_alpha(&caps("video/x-vp9")));
+        assert!(!has_alpha(&caps("video/x-vp9, codec-alpha=false")));
+
+        assert!(has_alpha(&caps("video/x-prores, variant=4444")));
+        assert!(has_alpha(&caps("video/x-prores, variant=4444xq")));
+        assert!(!has_alpha(&caps("video/x-prores, variant=hq")));
+
+        assert!(has_alpha(&caps("video/x-rle, layout=quicktime, depth=32")));
+        assert!(!has_alpha(&caps("video/x-rle, layout=quicktime, depth=24")));
+
+        assert!(!has_alpha(&caps("video/x-h264, stream-format=avc")));
+        assert!(!has_alpha(&gst::Caps::new_empty()));
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use gst::{prelude::*, ElementFactory, GhostPad};
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    config::{Modulation, ReactiveConfig, ReactiveInput},
+    visualizer::{
+        input::Capture,
+        spectrum::{self, Spectrum},
+    },
+};
+
+mod effects;
+
+use effects::Params;
+
+/// Rate the audio is analyzed at
+const ANALYSIS_RATE: u32 = 44100;
+/// Bands of the `spectrum` element, linear up to half of `ANALYSIS_RATE`
+const SPECTRUM_BANDS: u32 = 128;
+/// How often `level` and `spectrum` post their messages
+const ANALYSIS_INTERVAL: Duration = Duration::from_millis(20);
+
+const BASS: (f32, f32) = (20., 250.);
+const MID: (f32, f32) = (250., 4000.);
+const TREBLE: (f32, f32) = (4000., 16000.);
+
+/// Bass energy compared to its average over `BEAT_HISTORY` to detect beats
+const BEAT_HISTORY: Duration = Duration::from_secs(1);
+const BEAT_THRESHOLD: f32 = 1.3;
+const BEAT_MIN_ENERGY: f32 = 0.3;
+const BEAT_COOLDOWN: Duration = Duration::from_millis(250);
+/// Time constant of the beat pulse decay
+const BEAT_DECAY: Duration = Duration::from_millis(150);
+
+/// Samples captured when the wallpaper has no audio of its own
+const CAPTURE_WINDOW: usize = 2048;
+
+/// Normalized band energies, from 0 to 1
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Energies {
+    pub(crate) bass: f32,
+    pub(crate) mid: f32,
+    pub(crate) treble: f32,
+    pub(crate) loudness: f32,
+    /// 1 on a beat, decaying to 0 until the next one
+    pub(crate) beat: f32,
+}
+
+impl Energies {
+    #[inline]
+    fn get(&self, input: ReactiveInput) -> f32 {
+        match input {
+            ReactiveInput::Bass => self.bass,
+            ReactiveInput::Mid => self.mid,
+            ReactiveInput::Treble => self.treble,
+            ReactiveInput::Loudness => self.loudness,
+            ReactiveInput::Beat => self.beat,
+        }
+    }
+}
+
+/// Turns band levels into `Energies`, detecting beats along the way.
+#[derive(Debug, Default)]
+pub(crate) struct Analysis {
+    energies: Energies,
+    /// `None` until the first levels arrive
+    last_update: Option<Instant>,
+
+    bass_history: VecDeque<(Instant, f32)>,
+    last_beat: Option<Instant>,
+}
+
+impl Analysis {
+    /// `None` if no audio was analyzed yet.
+    #[inline]
+    pub(crate) fn energies(&self) -> Option<Energies> {
+        self.last_update.map(|_| self.energies)
+    }
+
+    pub(crate) fn feed(&mut self, bass: f32, mid: f32, treble: f32, loudness: f32, now: Instant) {
+        if let Some(last_update) = self.last_update {
+            let elapsed = now.duration_since(last_update).as_secs_f32();
+            self.energies.beat *= (-elapsed / BEAT_DECAY.as_secs_f32()).exp();
+        }
+        self.last_update = Some(now);
+
+        while let Some((time, _)) = self.bass_history.front() {
+            if now.duration_since(*time) <= BEAT_HISTORY {
+                break;
+            }
+            self.bass_history.pop_front();
+        }
+        let average = self.bass_history.iter().map(|(_, e)| e).sum::<f32>()
+            / self.bass_history.len().max(1) as f32;
+        self.bass_history.push_back((now, bass));
+
+        let cooled_down = self
+            .last_beat
+            .map_or(true, |last| now.duration_since(last) >= BEAT_COOLDOWN);
+        if cooled_down && bass >= BEAT_MIN_ENERGY && bass > average * BEAT_THRESHOLD {
+            self.last_beat = Some(now);
+            self.energies.beat = 1.;
+        }
+
+        self.energies.bass = bass;
+        self.energies.mid = mid;
+        self.energies.treble = treble;
+        self.energies.loudness = loudness;
+    }
+
+    /// Feeds the `spectrum` and `level` messages of `analysis_sink`, returns whether `msg` was
+    /// one of them.
+    pub(crate) fn handle_message(&mut self, msg: &gst::message::Element) -> bool {
+        let Some(s) = msg.structure() else {
+            return false;
+        };
+
+        match s.name().as_str() {
+            "spectrum" => {
+                let Ok(magnitudes) = s.get::<gst::List>("magnitude") else {
+                    return false;
+                };
+                let magnitudes = magnitudes
+                    .iter()
+                    .filter_map(|value| value.get::<f32>().ok())
+                    .collect::<Vec<_>>();
+
+                let loudness = self.energies.loudness;
+                self.feed(
+                    band_level(&magnitudes, BASS),
+                    band_level(&magnitudes, MID),
+                    band_level(&magnitudes, TREBLE),
+                    loudness,
+                    Instant::now(),
+                );
+                true
+            }
+            "level" => {
+                let Ok(rms) = s.get::<gst::glib::ValueArray>("rms") else {
+                    return false;
+                };
+                // Of the loudest channel
+                let loudest_rms = rms
+                    .iter()
+                    .filter_map(|value| value.get::<f64>().ok())
+                    .fold(f64::NEG_INFINITY, f64::max);
+
+                self.energies.loudness = spectrum::normalize_db(loudest_rms as f32);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Level of the loudest of the `spectrum` bands, in decibels, whose frequency is in `band`
+fn band_level(magnitudes: &[f32], (low, high): (f32, f32)) -> f32 {
+    let band_width = ANALYSIS_RATE as f32 / 2. / magnitudes.len().max(1) as f32;
+
+    magnitudes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            let frequency = (*i as f32 + 0.5) * band_width;
+            (low..high).contains(&frequency)
+        })
+        .map(|(_, db)| spectrum::normalize_db(*db))
+        .fold(0., f32::max)
+}
+
+/// Wraps `sink` so that the audio passes through `level` and `spectrum` first, whose messages
+/// are fed to `Analysis::handle_message`.
+pub(crate) fn analysis_sink(sink: gst::Element) -> Result<gst::Element, anyhow::Error> {
+    // audioconvert ! audioresample ! capsfilter ! level ! spectrum ! {sink}
+    let bin = gst::Bin::builder().name("analysisbin").build();
+
+    let audioconvert = ElementFactory::make("audioconvert").build()?;
+    let audioresample = ElementFactory::make("audioresample").build()?;
+    let capsfilter = ElementFactory::make("capsfilter")
+        .property(
+            "caps",
+            gst_audio::AudioCapsBuilder::new()
+                .rate(ANALYSIS_RATE as i32)
+                .build(),
+        )
+        .build()?;
+
+    let interval = ANALYSIS_INTERVAL.as_nanos() as u64;
+    let level = ElementFactory::make("level")
+        .property("post-messages", true)
+        .property("interval", interval)
+        .build()?;
+    let spectrum = ElementFactory::make("spectrum")
+        .property("post-messages", true)
+        .property("interval", interval)
+        .property("bands", SPECTRUM_BANDS)
+        .property("threshold", -80i32)
+        .build()?;
+
+    let elements = [
+        &audioconvert,
+        &audioresample,
+        &capsfilter,
+        &level,
+        &spectrum,
+        &sink,
+    ];
+    bin.add_many(elements)?;
+    gst::Element::link_many(elements)?;
+
+    let pad = audioconvert.static_pad("sink").unwrap();
+    let ghost_pad = GhostPad::builder_with_target(&pad)?.build();
+    ghost_pad.set_active(true)?;
+    bin.add_pad(&ghost_pad)?;
+
+    Ok(bin.upcast())
+}
+
+/// Analysis of the system audio, for wallpapers without audio of their own
+struct CaptureAnalysis {
+    capture: Capture,
+    spectrum: Spectrum,
+    samples: Vec<f32>,
+    analysis: Analysis,
+}
+
+impl CaptureAnalysis {
+    fn open(device: Option<&str>) -> Result<Self, anyhow::Error> {
+        let capture = Capture::open(device, CAPTURE_WINDOW * 4)?;
+
+        Ok(Self {
+            spectrum: Spectrum::new(CAPTURE_WINDOW, capture.rate()),
+            capture,
+            samples: vec![0.; CAPTURE_WINDOW],
+            analysis: Analysis::default(),
+        })
+    }
+
+    fn update(&mut self, now: Instant) -> Result<Energies, anyhow::Error> {
+        self.capture.read(&mut self.samples)?;
+
+        let levels = self.spectrum.levels(&self.samples, &[BASS, MID, TREBLE]);
+        let rms =
+            (self.samples.iter().map(|s| s * s).sum::<f32>() / self.samples.len() as f32).sqrt();
+        let loudness = spectrum::normalize_db(20. * (rms + f32::EPSILON).log10());
+
+        self.analysis
+            .feed(levels[0], levels[1], levels[2], loudness, now);
+        Ok(self.analysis.energies)
+    }
+}
+
+/// Maps the energies of the wallpaper's audio, or of the system audio, to effects on its frames.
+pub(crate) struct Modulator {
+    config: ReactiveConfig,
+    size: PhysicalSize<u32>,
+
+    /// Started the first time the wallpaper has no energies of its own
+    capture: Option<CaptureAnalysis>,
+    capture_failed: bool,
+
+    /// Last frame of the wallpaper, before the effects
+    pristine: Vec<u8>,
+    params: Params,
+    /// Whether `params` changed since the last render
+    dirty: bool,
+}
+
+impl Modulator {
+    pub(crate) fn new(config: ReactiveConfig, size: PhysicalSize<u32>) -> Self {
+        Self {
+            config,
+            size,
+            capture: None,
+            capture_failed: false,
+            pristine: Vec::new(),
+            params: Params::default(),
+            dirty: false,
+        }
+    }
+
+    /// Updates the effect parameters from the wallpaper's `energies`.
+    pub(crate) fn update(&mut self, energies: Option<Energies>, now: Instant) {
+        let energies = match energies {
+            Some(energies) => Some(energies),
+            None => self.capture_energies(now),
+        }
+        .unwrap_or_default();
+
+        let value = |modulation: &Option<Modulation>| {
+            modulation
+                .as_ref()
+                .map_or(0., |m| energies.get(m.input) * m.amount)
+        };
+
+        let params = Params {
+            brightness: (1. + value(&self.config.brightness)).max(0.),
+            zoom: 1. + value(&self.config.zoom).max(0.),
+            hue: value(&self.config.hue).to_radians(),
+        };
+
+        if params != self.params {
+            self.params = params;
+            self.dirty = true;
+        }
+    }
+
+    fn capture_energies(&mut self, now: Instant) -> Option<Energies> {
+        if !self.config.capture || self.capture_failed {
+            return None;
+        }
+
+        if self.capture.is_none() {
+            match CaptureAnalysis::open(self.config.device.as_deref()) {
+                Ok(capture) => self.capture = Some(capture),
+                Err(e) => {
+                    eprintln!("Error capturing audio, not reacting to it: {:#}", e);
+                    self.capture_failed = true;
+                    return None;
+                }
+            }
+        }
+
+        match self.capture.as_mut()?.update(now) {
+            Ok(energies) => Some(energies),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                // Reopened on the next update
+                self.capture = None;
+                None
+            }
+        }
+    }
+
+    /// Applies the effects to `frame`, in which the wallpaper just drew if `rendered`. Returns
+    /// whether `frame` changed.
+    pub(crate) fn render(&mut self, frame: &mut [u8], rendered: bool) -> bool {
+        if rendered {
+            self.pristine.clear();
+            self.pristine.extend_from_slice(frame);
+        } else if !self.dirty || self.pristine.len() != frame.len() {
+            return false;
+        }
+        self.dirty = false;
+
+        effects::apply(&self.pristine, frame, self.size, self.params);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn measures_the_bands() {
+        // 128 bands of about 172 Hz
+        let mut magnitudes = vec![-80.; SPECTRUM_BANDS as usize];
+        assert_eq!(band_level(&magnitudes, BASS), 0.);
+
+        magnitudes[0] = 0.;
+        magnitudes[10] = -30.;
+        assert_eq!(band_level(&magnitudes, BASS), 1.);
+        assert_eq!(band_level(&magnitudes, MID), 0.5);
+        assert_eq!(band_level(&magnitudes, TREBLE), 0.);
+
+        assert_eq!(band_level(&[], BASS), 0.);
+    }
+
+    #[test]
+    fn detects_beats() {
+        let start = Instant::now();
+        let mut analysis = Analysis::default();
+        assert_eq!(analysis.energies(), None);
+
+        // Too quiet, however sudden
+        analysis.feed(0.2, 0., 0., 0., start);
+        assert_eq!(analysis.energies().unwrap().beat, 0.);
+
+        analysis.feed(0.8, 0.5, 0.25, 0.6, after(start, 20));
+        let energies = analysis.energies().unwrap();
+        assert_eq!(energies.beat, 1.);
+        assert_eq!(
+            (energies.bass, energies.mid, energies.treble),
+            (0.8, 0.5, 0.25)
+        );
+        assert_eq!(energies.loudness, 0.6);
+
+        // Decays, one time constant later
+        analysis.feed(0.2, 0., 0., 0., after(start, 170));
+        let beat = analysis.energies().unwrap().beat;
+        assert!((beat - (-1f32).exp()).abs() < 1e-3, "{}", beat);
+    }
+
+    #[test]
+    fn waits_between_beats() {
+        let start = Instant::now();
+        let mut analysis = Analysis::default();
+        analysis.feed(1., 0., 0., 0., start);
+        analysis.feed(0.1, 0., 0., 0., after(start, 100));
+
+        // Loud enough, but within the cooldown
+        analysis.feed(1., 0., 0., 0., after(start, 200));
+        assert!(analysis.energies().unwrap().beat < 1.);
+
+        analysis.feed(1., 0., 0., 0., after(start, 300));
+        assert_eq!(analysis.energies().unwrap().beat, 1.);
+    }
+
+    #[test]
+    fn compares_to_the_recent_bass() {
+        let start = Instant::now();
+        let mut analysis = Analysis::default();
+
+        // A steady bass line isn't a beat, past the first hit
+        for i in 0..50 {
+            analysis.feed(0.8, 0., 0., 0., after(start, i * 20));
+        }
+        assert!(analysis.energies().unwrap().beat < 0.01);
+
+        // Forgotten after `BEAT_HISTORY`
+        let mut analysis = Analysis::default();
+        analysis.feed(0.9, 0., 0., 0., start);
+        analysis.feed(0.6, 0., 0., 0., after(start, 1500));
+        assert_eq!(analysis.energies().unwrap().beat, 1.);
+    }
+
+    #[test]
+    fn modulates_the_effects() {
+        let config = ReactiveConfig {
+            brightness: Some(Modulation {
+                input: ReactiveInput::Bass,
+                amount: 0.5,
+            }),
+            zoom: Some(Modulation {
+                input: ReactiveInput::Loudness,
+                amount: 0.1,
+            }),
+            hue: Some(Modulation {
+                input: ReactiveInput::Treble,
+                amount: 180.,
+            }),
+            ..ReactiveConfig::default()
+        };
+        let mut modulator = Modulator::new(config, PhysicalSize::new(1, 1));
+        let energies = Energies {
+            bass: 1.,
+            treble: 0.5,
+            loudness: 0.5,
+            ..Energies::default()
+        };
+
+        modulator.update(Some(energies), Instant::now());
+        assert_eq!(modulator.params.brightness, 1.5);
+        assert!((modulator.params.zoom - 1.05).abs() < 1e-6);
+        assert!((modulator.params.hue - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+        assert!(modulator.dirty);
+
+        // Without energies nor capture, the effects stop
+        modulator.update(None, Instant::now());
+        assert_eq!(modulator.params, Params::default());
+    }
+
+    #[test]
+    fn renders_from_the_last_frame() {
+        let config = ReactiveConfig {
+            brightness: Some(Modulation {
+                input: ReactiveInput::Bass,
+                amount: -0.5,
+            }),
+            ..ReactiveConfig::default()
+        };
+        let mut modulator = Modulator::new(config, PhysicalSize::new(1, 1));
+        let mut frame = [200, 100, 50, 255];
+
+        // Nothing to redo before the wallpaper drew
+        modulator.update(Some(Energies::default()), Instant::now());
+        assert!(!modulator.render(&mut frame, false));
+
+        assert!(modulator.render(&mut frame, true));
+        assert_eq!(frame, [200, 100, 50, 255]);
+
+        // Applied to the pristine frame rather than to the previous result
+        for _ in 0..2 {
+            let energies = Energies {
+                bass: 1.,
+                ..Energies::default()
+            };
+            modulator.update(Some(energies), Instant::now());
+            modulator.dirty = true;
+            assert!(modulator.render(&mut frame, false));
+            // Within the rounding of the color matrix
+            let expected = [100, 50, 25, 255];
+            assert!(
+                frame.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 1),
+                "{:?}",
+                frame
+            );
+        }
+        assert!(!modulator.render(&mut frame, false));
+    }
+}
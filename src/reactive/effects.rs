@@ -0,0 +1,192 @@
+use winit::dpi::PhysicalSize;
+
+/// Effect parameters of a frame, identity by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Params {
+    /// Multiplier of the colors
+    pub(crate) brightness: f32,
+    /// Scale around the center, 1 or more
+    pub(crate) zoom: f32,
+    /// Hue rotation in radians
+    pub(crate) hue: f32,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            brightness: 1.,
+            zoom: 1.,
+            hue: 0.,
+        }
+    }
+}
+
+impl Params {
+    /// Color matrix for the brightness and the hue rotation, on RGB rows
+    fn color_matrix(&self) -> [[f32; 3]; 3] {
+        let (sin, cos) = self.hue.sin_cos();
+        let b = self.brightness;
+
+        // Rotation around the luma axis
+        [
+            [
+                b * (0.299 + 0.701 * cos + 0.168 * sin),
+                b * (0.587 - 0.587 * cos + 0.330 * sin),
+                b * (0.114 - 0.114 * cos - 0.497 * sin),
+            ],
+            [
+                b * (0.299 - 0.299 * cos - 0.328 * sin),
+                b * (0.587 + 0.413 * cos + 0.035 * sin),
+                b * (0.114 - 0.114 * cos + 0.292 * sin),
+            ],
+            [
+                b * (0.299 - 0.300 * cos + 1.250 * sin),
+                b * (0.587 - 0.588 * cos - 1.050 * sin),
+                b * (0.114 + 0.886 * cos - 0.203 * sin),
+            ],
+        ]
+    }
+}
+
+/// Writes `src` with `params` applied into `dst`, both RGBA frames of `size`.
+pub(crate) fn apply(src: &[u8], dst: &mut [u8], size: PhysicalSize<u32>, params: Params) {
+    if params == Params::default() {
+        dst.copy_from_slice(src);
+        return;
+    }
+
+    let (width, height) = (size.width as usize, size.height as usize);
+    let (cx, cy) = (width as f32 / 2., height as f32 / 2.);
+    let zoom = params.zoom.max(1.);
+    let m = params.color_matrix();
+
+    // Source column of every destination column, nearest neighbour
+    let columns = (0..width)
+        .map(|x| ((cx + (x as f32 + 0.5 - cx) / zoom) as usize).min(width - 1))
+        .collect::<Vec<_>>();
+
+    for (y, row) in dst.chunks_exact_mut(width * 4).enumerate() {
+        let sy = ((cy + (y as f32 + 0.5 - cy) / zoom) as usize).min(height - 1);
+        let src_row = &src[sy * width * 4..(sy + 1) * width * 4];
+
+        for (pixel, sx) in row.chunks_exact_mut(4).zip(&columns) {
+            let s = &src_row[sx * 4..sx * 4 + 4];
+            let (r, g, b) = (s[0] as f32, s[1] as f32, s[2] as f32);
+
+            pixel[0] = (m[0][0] * r + m[0][1] * g + m[0][2] * b).clamp(0., 255.) as u8;
+            pixel[1] = (m[1][0] * r + m[1][1] * g + m[1][2] * b).clamp(0., 255.) as u8;
+            pixel[2] = (m[2][0] * r + m[2][1] * g + m[2][2] * b).clamp(0., 255.) as u8;
+            pixel[3] = s[3];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(4, 4);
+
+    /// A 4x4 frame, pixel `i` of color `[i, 2i, 3i]` and alpha `255 - i`
+    fn frame() -> Vec<u8> {
+        (0..16u8).flat_map(|i| [i, 2 * i, 3 * i, 255 - i]).collect()
+    }
+
+    fn pixel(frame: &[u8], x: usize, y: usize) -> &[u8] {
+        let i = (y * SIZE.width as usize + x) * 4;
+        &frame[i..i + 4]
+    }
+
+    fn apply_to_frame(params: Params) -> Vec<u8> {
+        let mut dst = vec![0; 64];
+        apply(&frame(), &mut dst, SIZE, params);
+        dst
+    }
+
+    /// Within rounding, the matrix is only accurate to its 3 decimals
+    fn assert_close(a: &[u8], b: &[u8]) {
+        assert_eq!(a.len(), b.len());
+        assert!(
+            a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 1),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn copies_without_effects() {
+        assert_eq!(apply_to_frame(Params::default()), frame());
+
+        // A full turn, through the matrix
+        let params = Params {
+            hue: std::f32::consts::TAU,
+            ..Params::default()
+        };
+        assert_close(&apply_to_frame(params), &frame());
+    }
+
+    #[test]
+    fn scales_the_brightness() {
+        let brighter = apply_to_frame(Params {
+            brightness: 2.,
+            ..Params::default()
+        });
+        assert_close(pixel(&brighter, 1, 0), &[2, 4, 6, 254]);
+        assert_close(pixel(&brighter, 3, 3), &[30, 60, 90, 240]);
+
+        // Clamped, alpha untouched
+        let clipped = apply_to_frame(Params {
+            brightness: 20.,
+            ..Params::default()
+        });
+        assert_eq!(pixel(&clipped, 3, 3), [255, 255, 255, 240]);
+    }
+
+    #[test]
+    fn rotates_the_hue_around_gray() {
+        let mut dst = vec![0; 64];
+        let params = Params {
+            hue: 2.,
+            ..Params::default()
+        };
+        let gray = [128, 128, 128, 255].repeat(16);
+        apply(&gray, &mut dst, SIZE, params);
+        assert_close(&dst, &gray);
+
+        // A third of the way around, red is no longer red
+        let params = Params {
+            hue: 120f32.to_radians(),
+            ..Params::default()
+        };
+        apply(&[255, 0, 0, 255].repeat(16), &mut dst, SIZE, params);
+        let [r, g, b] = [dst[0], dst[1], dst[2]];
+        assert!(r < g.max(b), "{:?}", [r, g, b]);
+    }
+
+    #[test]
+    fn zooms_into_the_center() {
+        let zoomed = apply_to_frame(Params {
+            zoom: 2.,
+            ..Params::default()
+        });
+        // The 2x2 middle of the source, each pixel doubled
+        let source = frame();
+        assert_close(pixel(&zoomed, 0, 0), pixel(&source, 1, 1));
+        assert_close(pixel(&zoomed, 1, 1), pixel(&source, 1, 1));
+        assert_close(pixel(&zoomed, 2, 0), pixel(&source, 2, 1));
+        assert_close(pixel(&zoomed, 3, 3), pixel(&source, 2, 2));
+
+        // Never zooms out
+        let params = Params {
+            zoom: 0.5,
+            brightness: 1.5,
+            hue: 0.,
+        };
+        let brighter = Params {
+            brightness: 1.5,
+            ..Params::default()
+        };
+        assert_eq!(apply_to_frame(params), apply_to_frame(brighter));
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+use crate::config::RecoveryConfig;
+
+/// How long a wallpaper has to play without errors before its failures are forgotten
+const STABLE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Retry policy for a failing wallpaper: exponential backoff, then fallback after
+/// `max_retries` consecutive failures.
+pub(crate) struct Recovery {
+    config: RecoveryConfig,
+
+    failures: u32,
+    next_retry: Option<Instant>,
+    started: Option<Instant>,
+}
+
+impl Recovery {
+    pub(crate) fn new(config: RecoveryConfig) -> Self {
+        Self {
+            config,
+            failures: 0,
+            next_retry: None,
+            started: None,
+        }
+    }
+
+    /// Records a failure and schedules the next retry. Returns the backoff until then.
+    pub(crate) fn record_failure(&mut self, now: Instant) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.started = None;
+
+        let backoff = self.backoff();
+        // Never, if the backoff is too long for the clock
+        self.next_retry = now.checked_add(backoff);
+
+        backoff
+    }
+
+    #[inline]
+    pub(crate) fn record_start(&mut self, now: Instant) {
+        self.next_retry = None;
+        self.started = Some(now);
+    }
+
+    /// Forgets previous failures once the wallpaper has been playing for a while.
+    pub(crate) fn record_success(&mut self, now: Instant) {
+        if let Some(started) = self.started {
+            if self.failures > 0 && now.duration_since(started) >= STABLE_PERIOD {
+                self.failures = 0;
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn should_retry(&self, now: Instant) -> bool {
+        self.next_retry.map_or(false, |next| now >= next)
+    }
+
+    #[inline]
+    pub(crate) fn exhausted(&self) -> bool {
+        self.failures >= self.config.max_retries
+    }
+
+    #[inline]
+    pub(crate) fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    fn backoff(&self) -> Duration {
+        let exponent = self.failures.saturating_sub(1).min(16);
+        self.config
+            .initial_backoff()
+            .saturating_mul(1 << exponent)
+            .min(self.config.max_backoff())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recovery(max_retries: u32) -> Recovery {
+        Recovery::new(RecoveryConfig {
+            max_retries,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 5000,
+        })
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn spaces_the_retries() {
+        let start = Instant::now();
+        let mut recovery = recovery(10);
+        assert!(!recovery.should_retry(start));
+
+        // Doubling up to the maximum
+        let mut now = start;
+        for expected in [1, 2, 4, 5, 5] {
+            let backoff = recovery.record_failure(now);
+            assert_eq!(backoff, secs(expected));
+
+            assert!(!recovery.should_retry(now + backoff - Duration::from_millis(1)));
+            now += backoff;
+            assert!(recovery.should_retry(now));
+
+            recovery.record_start(now);
+            assert!(!recovery.should_retry(now));
+        }
+        assert_eq!(recovery.failures(), 5);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let now = Instant::now();
+        let mut recovery = recovery(3);
+
+        for _ in 0..2 {
+            recovery.record_failure(now);
+            assert!(!recovery.exhausted());
+        }
+        recovery.record_failure(now);
+        assert!(recovery.exhausted());
+    }
+
+    #[test]
+    fn forgets_failures_once_stable() {
+        let start = Instant::now();
+        let mut recovery = recovery(3);
+        recovery.record_failure(start);
+        recovery.record_failure(start);
+        recovery.record_start(start + secs(2));
+
+        // Not long enough
+        recovery.record_success(start + secs(2) + STABLE_PERIOD - secs(1));
+        assert_eq!(recovery.failures(), 2);
+
+        recovery.record_success(start + secs(2) + STABLE_PERIOD);
+        assert_eq!(recovery.failures(), 0);
+        assert_eq!(recovery.record_failure(start + secs(60)), secs(1));
+    }
+
+    #[test]
+    fn success_needs_a_start() {
+        let start = Instant::now();
+        let mut recovery = recovery(3);
+        recovery.record_start(start);
+        recovery.record_failure(start + secs(1));
+
+        // Failed since it started
+        recovery.record_success(start + secs(100));
+        assert_eq!(recovery.failures(), 1);
+    }
+
+    #[test]
+    fn caps_huge_backoffs() {
+        let now = Instant::now();
+        let mut recovery = Recovery::new(RecoveryConfig {
+            max_retries: u32::MAX,
+            initial_backoff_ms: u64::MAX,
+            max_backoff_ms: u64::MAX,
+        });
+        for _ in 0..40 {
+            assert_eq!(
+                recovery.record_failure(now),
+                Duration::from_millis(u64::MAX)
+            );
+        }
+        assert!(!recovery.should_retry(now + secs(3600)));
+    }
+}
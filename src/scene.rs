@@ -0,0 +1,537 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use winit::dpi::PhysicalSize;
+
+use crate::{source::Source, tex::Tex};
+
+mod assets;
+mod particles;
+mod raster;
+mod values;
+
+use assets::Assets;
+use particles::Particles;
+use raster::{Blend, Sprite};
+
+/// Size of the scene when its `orthogonalprojection` is `auto` or missing
+const DEFAULT_PROJECTION: [f32; 2] = [1920., 1080.];
+/// Fraction of the projection a layer with a parallax depth of 1 moves by, with the cursor at
+/// the edge of the screen and an amount of 1
+const PARALLAX_RANGE: f32 = 0.05;
+/// Longest step of the simulation, so that particles don't jump after a stall
+const MAX_STEP: Duration = Duration::from_millis(100);
+
+/// Maps the scene's coordinates, in pixels of its projection with y going up, to the frame's,
+/// scaled to fill it like `Still::cover`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct View {
+    projection: [f32; 2],
+    frame: [f32; 2],
+    scale: f32,
+}
+
+impl View {
+    fn new(projection: [f32; 2], size: PhysicalSize<u32>) -> Self {
+        let frame = [size.width as f32, size.height as f32];
+        Self {
+            projection,
+            frame,
+            scale: (frame[0] / projection[0]).max(frame[1] / projection[1]),
+        }
+    }
+
+    pub(crate) fn to_frame(&self, [x, y]: [f32; 2]) -> [f32; 2] {
+        [
+            (x - self.projection[0] / 2.) * self.scale + self.frame[0] / 2.,
+            (self.projection[1] / 2. - y) * self.scale + self.frame[1] / 2.,
+        ]
+    }
+
+    /// Frame pixels per scene unit
+    #[inline]
+    pub(crate) fn scale(&self) -> f32 {
+        self.scale
+    }
+}
+
+/// Where an object is in the scene
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Placement {
+    pub(crate) origin: [f32; 2],
+    pub(crate) scale: [f32; 2],
+    /// Radians, counterclockwise
+    pub(crate) angle: f32,
+    /// How much the object follows the camera's parallax, 0 for not at all
+    pub(crate) parallax_depth: [f32; 2],
+}
+
+impl Placement {
+    fn read(object: &Value) -> Self {
+        let [x, y, _] = values::vector::<3>(object.get("origin")).unwrap_or_default();
+        let [scale_x, scale_y, _] = values::vector::<3>(object.get("scale")).unwrap_or([1.; 3]);
+        let [_, _, angle] = values::vector::<3>(object.get("angles")).unwrap_or_default();
+
+        Self {
+            origin: [x, y],
+            scale: [scale_x, scale_y],
+            angle,
+            parallax_depth: values::vector(object.get("parallaxDepth")).unwrap_or([1.; 2]),
+        }
+    }
+}
+
+/// An image object, e.g. the background
+struct Image {
+    texture: Tex,
+    blend: Blend,
+    placement: Placement,
+    /// In scene units, before scaling
+    size: [f32; 2],
+    /// Color from 0 to 1, and alpha
+    tint: [f32; 4],
+}
+
+impl Image {
+    fn load(
+        object: &Value,
+        assets: &mut Assets,
+        projection: [f32; 2],
+    ) -> Result<Self, anyhow::Error> {
+        let file =
+            values::string(object.get("image")).ok_or_else(|| anyhow::anyhow!("No image"))?;
+        let model = assets.json(file)?;
+        let material = values::string(model.get("material"))
+            .ok_or_else(|| anyhow::anyhow!("{} has no material", file))?;
+        let material = assets.material(material)?;
+
+        let mut placement = Placement::read(object);
+        let (width, height) = material.texture.first_frame().dimensions();
+        let size = if values::boolean(model.get("fullscreen")).unwrap_or(false) {
+            placement.origin = [projection[0] / 2., projection[1] / 2.];
+            projection
+        } else {
+            let positive = |[width, height]: &[f32; 2]| *width > 0. && *height > 0.;
+            values::vector(object.get("size"))
+                .filter(positive)
+                .or_else(|| {
+                    Some([
+                        values::number(model.get("width"))?,
+                        values::number(model.get("height"))?,
+                    ])
+                    .filter(positive)
+                })
+                .unwrap_or([width as f32, height as f32])
+        };
+
+        let [r, g, b] = values::vector(object.get("color")).unwrap_or([1.; 3]);
+        let brightness = values::number(object.get("brightness")).unwrap_or(1.);
+        let alpha = values::number(object.get("alpha")).unwrap_or(1.);
+
+        Ok(Self {
+            texture: material.texture,
+            blend: material.blend,
+            placement,
+            size,
+            tint: [
+                r * brightness,
+                g * brightness,
+                b * brightness,
+                alpha.clamp(0., 1.),
+            ],
+        })
+    }
+
+    fn draw(
+        &self,
+        frame: &mut [u8],
+        size: PhysicalSize<u32>,
+        view: &View,
+        offset: [f32; 2],
+        time: Duration,
+    ) {
+        let placement = &self.placement;
+        let position = [0, 1].map(|axis| placement.origin[axis] + offset[axis]);
+
+        raster::draw(
+            frame,
+            size,
+            &Sprite {
+                image: self.texture.frame_at(time),
+                center: view.to_frame(position),
+                size: [0, 1].map(|axis| self.size[axis] * placement.scale[axis] * view.scale()),
+                angle: placement.angle,
+                tint: self.tint,
+                blend: self.blend,
+            },
+        );
+    }
+}
+
+enum Layer {
+    Image(Image),
+    Particles(Particles),
+}
+
+impl Layer {
+    fn parallax_depth(&self) -> [f32; 2] {
+        match self {
+            Self::Image(image) => image.placement.parallax_depth,
+            Self::Particles(particles) => particles.parallax_depth(),
+        }
+    }
+}
+
+/// The scene's camera parallax, moving layers with the cursor
+#[derive(Debug, Clone, Copy)]
+struct Parallax {
+    amount: f32,
+    /// Seconds the camera takes to follow the cursor
+    delay: f32,
+    mouse_influence: f32,
+}
+
+impl Parallax {
+    fn read(general: &Value) -> Option<Self> {
+        values::boolean(general.get("cameraparallax"))
+            .filter(|enabled| *enabled)
+            .map(|_| Self {
+                amount: values::number(general.get("cameraparallaxamount")).unwrap_or(0.5),
+                delay: values::number(general.get("cameraparallaxdelay")).unwrap_or(0.1),
+                mouse_influence: values::number(general.get("cameraparallaxmouseinfluence"))
+                    .unwrap_or(0.5),
+            })
+    }
+}
+
+/// A Wallpaper Engine 2D scene: image layers and particle systems composited on the CPU, so it
+/// runs without a GPU. Objects and effects which can't be drawn this way are skipped.
+pub(crate) struct Scene {
+    size: PhysicalSize<u32>,
+    view: View,
+    clear_color: [u8; 3],
+    layers: Vec<Layer>,
+    parallax: Option<Parallax>,
+
+    /// Cursor from -1 to 1, y going up
+    pointer: [f32; 2],
+    /// Follows `pointer` after the parallax delay
+    camera: [f32; 2],
+    /// Played, without the paused time
+    time: Duration,
+    last_update: Instant,
+    paused: bool,
+
+    need_render: bool,
+}
+
+impl Scene {
+    /// Opens a `scene.pkg`, or a Wallpaper Engine project folder of a scene.
+    pub(crate) fn open<S>(size: S, path: &Path) -> Result<Self, anyhow::Error>
+    where
+        S: Into<PhysicalSize<u32>>,
+    {
+        let size: PhysicalSize<u32> = size.into();
+        let mut assets = Assets::open(path)?;
+        let scene_file = assets.scene.clone();
+        let scene = assets.json(&scene_file)?;
+
+        let general = scene.get("general").unwrap_or(&Value::Null);
+        let projection = general
+            .get("orthogonalprojection")
+            .filter(|projection| !values::boolean(projection.get("auto")).unwrap_or(false))
+            .and_then(|projection| {
+                Some([
+                    values::number(projection.get("width"))?,
+                    values::number(projection.get("height"))?,
+                ])
+            })
+            .filter(|[width, height]| *width > 0. && *height > 0.)
+            .unwrap_or(DEFAULT_PROJECTION);
+        let clear_color = values::vector(general.get("clearcolor"))
+            .unwrap_or([0.; 3])
+            .map(|channel: f32| (channel.clamp(0., 1.) * 255.) as u8);
+
+        let mut layers = Vec::new();
+        let objects = scene
+            .get("objects")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow::anyhow!("{} has no objects", scene_file))?;
+        for object in objects {
+            let name = values::string(object.get("name")).unwrap_or("unnamed object");
+            if !values::boolean(object.get("visible")).unwrap_or(true) {
+                continue;
+            }
+
+            let layer = if object.get("image").is_some() {
+                Image::load(object, &mut assets, projection).map(Layer::Image)
+            } else if object.get("particle").is_some() {
+                Particles::load(object, name, &mut assets).map(Layer::Particles)
+            } else {
+                Err(anyhow::anyhow!("{} objects aren't supported", kind(object)))
+            };
+            match layer {
+                Ok(layer) => layers.push(layer),
+                Err(e) => {
+                    eprintln!("Skipping {} of the scene: {:#}", name, e);
+                    continue;
+                }
+            }
+
+            if object.get("parent").is_some() {
+                eprintln!("Placing {} without its parent, which isn't supported", name);
+            }
+            let effects = object.get("effects").and_then(Value::as_array);
+            for effect in effects.into_iter().flatten() {
+                let file = values::string(effect.get("file")).unwrap_or("unnamed effect");
+                eprintln!(
+                    "Skipping effect {} of {}, effects aren't supported",
+                    file, name
+                );
+            }
+        }
+        if layers.is_empty() {
+            return Err(anyhow::anyhow!("Nothing in the scene can be drawn"));
+        }
+
+        let now = Instant::now();
+        Ok(Self {
+            size,
+            view: View::new(projection, size),
+            clear_color,
+            layers,
+            parallax: Parallax::read(general),
+            pointer: [0.; 2],
+            camera: [0.; 2],
+            time: Duration::ZERO,
+            last_update: now,
+            paused: false,
+            need_render: true,
+        })
+    }
+
+    /// Where a layer with `depth` is moved by the parallax, in scene units
+    fn parallax_offset(&self, depth: [f32; 2]) -> [f32; 2] {
+        let Some(parallax) = self.parallax else {
+            return [0.; 2];
+        };
+        // Towards the opposite side, like looking past the layer
+        [0, 1].map(|axis| {
+            -self.camera[axis]
+                * parallax.amount
+                * parallax.mouse_influence
+                * depth[axis]
+                * self.view.projection[axis]
+                * PARALLAX_RANGE
+        })
+    }
+}
+
+/// What an object which is neither an image nor particles is, for the warning
+fn kind(object: &Value) -> &'static str {
+    ["sound", "text", "model", "light"]
+        .into_iter()
+        .find(|kind| object.get(*kind).is_some())
+        .unwrap_or("unknown")
+}
+
+impl Source for Scene {
+    fn update(&mut self) -> Result<(), anyhow::Error> {
+        let now = Instant::now();
+        let step = now.duration_since(self.last_update).min(MAX_STEP);
+        self.last_update = now;
+        if self.paused {
+            return Ok(());
+        }
+
+        self.time += step;
+        let dt = step.as_secs_f32();
+
+        if let Some(parallax) = self.parallax {
+            let previous = self.camera;
+            let follow = if parallax.delay > 0. {
+                1. - (-dt / parallax.delay).exp()
+            } else {
+                1.
+            };
+            for axis in 0..2 {
+                self.camera[axis] += (self.pointer[axis] - self.camera[axis]) * follow;
+            }
+            if (0..2).any(|axis| (self.camera[axis] - previous[axis]).abs() > 1e-4) {
+                self.need_render = true;
+            }
+        }
+
+        for layer in &mut self.layers {
+            match layer {
+                Layer::Image(image) => self.need_render |= image.texture.is_animated(),
+                Layer::Particles(particles) => {
+                    particles.update(dt);
+                    self.need_render = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut [u8]) -> bool {
+        let len = self.size.width as usize * self.size.height as usize * 4;
+        if !self.need_render || frame.len() != len {
+            return false;
+        }
+        self.need_render = false;
+
+        raster::clear(frame, self.clear_color);
+        for layer in &self.layers {
+            let offset = self.parallax_offset(layer.parallax_depth());
+            match layer {
+                Layer::Image(image) => image.draw(frame, self.size, &self.view, offset, self.time),
+                Layer::Particles(particles) => particles.draw(frame, self.size, &self.view, offset),
+            }
+        }
+        true
+    }
+
+    #[inline]
+    fn frame_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    fn set_paused(&mut self, paused: bool) -> Result<(), anyhow::Error> {
+        self.paused = paused;
+        Ok(())
+    }
+
+    fn set_pointer(&mut self, pointer: Option<[f32; 2]>) {
+        // Back to the middle when the cursor leaves
+        self.pointer = pointer.unwrap_or([0.; 2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use assets::tests::{project, texture};
+
+    #[test]
+    fn maps_the_view_like_cover() {
+        // Wider scene than the frame, cropped on the sides
+        let view = View::new([200., 100.], PhysicalSize::new(100, 100));
+        assert_eq!(view.scale(), 1.);
+        assert_eq!(view.to_frame([100., 50.]), [50., 50.]);
+        // Y goes up in the scene
+        assert_eq!(view.to_frame([50., 100.]), [0., 0.]);
+        assert_eq!(view.to_frame([150., 0.]), [100., 100.]);
+
+        let view = View::new([100., 100.], PhysicalSize::new(300, 200));
+        assert_eq!(view.scale(), 3.);
+    }
+
+    #[test]
+    fn reads_placements() {
+        let placement = Placement::read(&json!({
+            "origin": "10 20 30",
+            "scale": "2 3 1",
+            "angles": "0 0 1.5",
+            "parallaxDepth": "0.5 0"
+        }));
+        assert_eq!(placement.origin, [10., 20.]);
+        assert_eq!(placement.scale, [2., 3.]);
+        assert_eq!(placement.angle, 1.5);
+        assert_eq!(placement.parallax_depth, [0.5, 0.]);
+
+        let placement = Placement::read(&json!({}));
+        assert_eq!(placement.scale, [1.; 2]);
+        assert_eq!(placement.parallax_depth, [1.; 2]);
+    }
+
+    #[test]
+    fn reads_the_parallax() {
+        assert!(Parallax::read(&json!({ "cameraparallax": false })).is_none());
+
+        let parallax = Parallax::read(&json!({
+            "cameraparallax": true,
+            "cameraparallaxamount": { "user": "parallax", "value": 2 }
+        }))
+        .unwrap();
+        assert_eq!(parallax.amount, 2.);
+        assert_eq!(parallax.mouse_influence, 0.5);
+    }
+
+    /// A 4x4 scene with a blue background and a red 2x2 image in the middle, and `objects`
+    fn scene(objects: serde_json::Value) -> tempfile::TempDir {
+        let scene = json!({
+            "general": {
+                "orthogonalprojection": { "width": 4, "height": 4 },
+                "clearcolor": "0 0 1",
+                "cameraparallax": true,
+                "cameraparallaxamount": 1,
+                "cameraparallaxmouseinfluence": 1
+            },
+            "objects": objects
+        });
+        project(&[
+            ("scene.json", scene.to_string().as_bytes()),
+            (
+                "models/red.json",
+                br#"{ "material": "materials/red.json" }"#,
+            ),
+            (
+                "materials/red.json",
+                br#"{ "passes": [{ "textures": ["red"] }] }"#,
+            ),
+            ("materials/red.tex", &texture([0xff, 0, 0, 0xff])),
+        ])
+    }
+
+    fn pixel(frame: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let i = (y * 4 + x) * 4;
+        [frame[i], frame[i + 1], frame[i + 2]]
+    }
+
+    #[test]
+    fn draws_the_layers() {
+        let dir = scene(json!([
+            { "name": "red", "image": "models/red.json", "origin": "2 2 0", "size": "2 2" },
+            { "name": "music", "sound": ["music.mp3"] },
+            { "name": "hidden", "image": "models/missing.json", "visible": false }
+        ]));
+        let mut scene = Scene::open(PhysicalSize::new(4, 4), dir.path()).unwrap();
+        assert_eq!(scene.layers.len(), 1);
+
+        let mut frame = vec![0; 4 * 4 * 4];
+        assert!(scene.render(&mut frame));
+        assert_eq!(pixel(&frame, 0, 0), [0, 0, 0xff]);
+        assert_eq!(pixel(&frame, 1, 1), [0xff, 0, 0]);
+        assert_eq!(pixel(&frame, 2, 2), [0xff, 0, 0]);
+        assert_eq!(pixel(&frame, 3, 3), [0, 0, 0xff]);
+        // Nothing changed
+        assert!(!scene.render(&mut frame));
+    }
+
+    #[test]
+    fn moves_layers_away_from_the_cursor() {
+        let dir = scene(json!([{ "image": "models/red.json", "parallaxDepth": "1 0" }]));
+        let mut scene = Scene::open(PhysicalSize::new(4, 4), dir.path()).unwrap();
+        assert_eq!(scene.parallax_offset([1., 1.]), [0.; 2]);
+
+        scene.camera = [1., 1.];
+        let [x, y] = scene.parallax_offset([1., 0.]);
+        assert!((x + 4. * PARALLAX_RANGE).abs() < 1e-6);
+        assert_eq!(y, 0.);
+    }
+
+    #[test]
+    fn needs_something_to_draw() {
+        let dir = scene(json!([{ "name": "title", "text": { "value": "Hello" } }]));
+        assert!(Scene::open(PhysicalSize::new(4, 4), dir.path()).is_err());
+
+        let dir = scene(json!([{ "image": "models/missing.json" }]));
+        assert!(Scene::open(PhysicalSize::new(4, 4), dir.path()).is_err());
+    }
+}
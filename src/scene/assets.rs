@@ -0,0 +1,185 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use super::{raster::Blend, values};
+use crate::{
+    pkg::{self, Pkg},
+    tex::Tex,
+};
+
+/// Package of a scene in its project folder
+const PACKAGE: &str = "scene.pkg";
+const PROJECT_FILE: &str = "project.json";
+const DEFAULT_SCENE: &str = "scene.json";
+
+/// Prefix of the textures a shader renders into, which need the GPU pipeline
+const RENDER_TARGET_PREFIX: &str = "_rt_";
+
+/// The texture of an object, with how it is composited
+pub(crate) struct Material {
+    pub(crate) texture: Tex,
+    pub(crate) blend: Blend,
+}
+
+/// Files of a scene: the entries of its package, then the files of its project folder.
+pub(crate) struct Assets {
+    pkg: Option<Pkg<BufReader<File>>>,
+    dir: PathBuf,
+    /// Name of the scene's description, usually `scene.json`
+    pub(crate) scene: String,
+}
+
+impl Assets {
+    /// Opens a `scene.pkg`, or a project folder with a `scene.pkg` or extracted files.
+    pub(crate) fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let (pkg, dir) = if path.is_dir() {
+            let package = path.join(PACKAGE);
+            let pkg = package.is_file().then(|| Pkg::open(&package)).transpose()?;
+            (pkg, path.to_owned())
+        } else {
+            let dir = path.parent().unwrap_or_else(|| Path::new(".")).to_owned();
+            (Some(Pkg::open(path)?), dir)
+        };
+
+        // Named in the project, if there is one
+        let scene = std::fs::read(dir.join(PROJECT_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Value>(&data).ok())
+            .and_then(|project| values::string(project.get("file")).map(str::to_owned))
+            .filter(|file| file.ends_with(".json"))
+            .unwrap_or_else(|| DEFAULT_SCENE.to_owned());
+
+        Ok(Self { pkg, dir, scene })
+    }
+
+    /// Content of the file `name`, e.g. `models/background.json`
+    pub(crate) fn read(&mut self, name: &str) -> Result<Vec<u8>, anyhow::Error> {
+        if let Some(pkg) = &mut self.pkg {
+            if pkg.find(name).is_some() {
+                return pkg.read_entry(name);
+            }
+        }
+
+        // Never outside of the project folder
+        let path = self.dir.join(pkg::safe_path(name)?);
+        std::fs::read(&path).map_err(|e| anyhow::anyhow!("Failed to open {}: {}", name, e))
+    }
+
+    pub(crate) fn json(&mut self, name: &str) -> Result<Value, anyhow::Error> {
+        let data = self.read(name)?;
+        serde_json::from_slice(&data).map_err(|e| anyhow::anyhow!("Invalid {}: {}", name, e))
+    }
+
+    /// The material `name`, e.g. `materials/background.json`, with the texture of its first pass
+    pub(crate) fn material(&mut self, name: &str) -> Result<Material, anyhow::Error> {
+        let material = self.json(name)?;
+        let pass = material
+            .get("passes")
+            .and_then(|passes| passes.get(0))
+            .ok_or_else(|| anyhow::anyhow!("{} has no passes", name))?;
+
+        let texture = pass
+            .get("textures")
+            .and_then(|textures| textures.get(0))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow::anyhow!("{} has no texture", name))?;
+        if texture.starts_with(RENDER_TARGET_PREFIX) {
+            return Err(anyhow::anyhow!(
+                "{} draws render target {}, which isn't supported",
+                name,
+                texture
+            ));
+        }
+
+        let texture_name = format!("materials/{}.tex", texture);
+        let texture = Tex::decode(&self.read(&texture_name)?)
+            .map_err(|e| e.context(format!("Invalid texture {}", texture_name)))?;
+
+        Ok(Material {
+            texture,
+            blend: Blend::from_name(values::string(pass.get("blending"))),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A 1x1 RGBA texture of `pixel`
+    pub(crate) fn texture(pixel: [u8; 4]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(b"TEXV0005\0TEXI0001\0");
+        // Format, flags, texture size, image size, unknown
+        for value in [0u32, 0, 1, 1, 1, 1, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(b"TEXB0001\0");
+        // Images, mipmaps, mipmap size and length
+        for value in [1u32, 1, 1, 1, 4] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(pixel);
+        data
+    }
+
+    /// A project folder of `files`
+    pub(crate) fn project(files: &[(&str, &[u8])]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn reads_the_project_folder() {
+        let dir = project(&[
+            (PROJECT_FILE, br#"{ "file": "night.json" }"#),
+            ("night.json", b"{}"),
+        ]);
+        let mut assets = Assets::open(dir.path()).unwrap();
+        assert_eq!(assets.scene, "night.json");
+        assert_eq!(assets.read("night.json").unwrap(), b"{}");
+        assert!(assets.read("missing.json").is_err());
+        assert!(assets.read("../night.json").is_err());
+
+        // Only scenes, e.g. not the video of a video wallpaper
+        let dir = project(&[(PROJECT_FILE, br#"{ "file": "clip.mp4" }"#)]);
+        assert_eq!(Assets::open(dir.path()).unwrap().scene, DEFAULT_SCENE);
+    }
+
+    #[test]
+    fn reads_materials() {
+        let dir = project(&[
+            (
+                "materials/glow.json",
+                br#"{ "passes": [{ "textures": ["glow"], "blending": "additive" }] }"#,
+            ),
+            ("materials/glow.tex", &texture([1, 2, 3, 4])),
+            (
+                "materials/blur.json",
+                br#"{ "passes": [{ "textures": ["_rt_FullFrameBuffer"] }] }"#,
+            ),
+            ("materials/empty.json", br#"{ "passes": [] }"#),
+        ]);
+        let mut assets = Assets::open(dir.path()).unwrap();
+
+        let material = assets.material("materials/glow.json").unwrap();
+        assert_eq!(material.blend, Blend::Additive);
+        assert_eq!(
+            material.texture.first_frame().get_pixel(0, 0).0,
+            [1, 2, 3, 4]
+        );
+
+        assert!(assets.material("materials/blur.json").is_err());
+        assert!(assets.material("materials/empty.json").is_err());
+    }
+}
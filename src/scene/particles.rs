@@ -0,0 +1,685 @@
+use std::{
+    f32::consts::TAU,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::Value;
+use winit::dpi::PhysicalSize;
+
+use super::{
+    assets::{Assets, Material},
+    raster::{self, Sprite},
+    values, Placement, View,
+};
+
+/// Particles alive at once, whatever a system asks for
+const MAX_COUNT: usize = 10_000;
+const DEFAULT_COUNT: usize = 100;
+/// Per second, per emitter
+const DEFAULT_RATE: f32 = 5.;
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    /// Relative to the object's origin
+    position: [f32; 2],
+    velocity: [f32; 2],
+    size: f32,
+    /// From 0 to 1
+    color: [f32; 3],
+    alpha: f32,
+    rotation: f32,
+    angular_velocity: f32,
+    age: f32,
+    lifetime: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Anywhere between the min and max distances on each axis
+    Box,
+    /// Anywhere between the min and max distances from the origin
+    Sphere,
+}
+
+#[derive(Debug, Clone)]
+struct Emitter {
+    shape: Shape,
+    origin: [f32; 2],
+    rate: f32,
+    distance_min: [f32; 2],
+    distance_max: [f32; 2],
+    /// Scales the distances on each axis
+    directions: [f32; 2],
+    /// Fraction of a particle left to emit
+    pending: f32,
+}
+
+impl Emitter {
+    fn read(value: &Value) -> Result<Self, String> {
+        let shape = match values::string(value.get("name")) {
+            Some("boxrandom") => Shape::Box,
+            Some("sphererandom") => Shape::Sphere,
+            name => return Err(format!("emitter {}", name.unwrap_or("without name"))),
+        };
+
+        Ok(Self {
+            shape,
+            origin: values::vector(value.get("origin")).unwrap_or_default(),
+            rate: values::number(value.get("rate")).unwrap_or(DEFAULT_RATE),
+            distance_min: values::vector(value.get("distancemin")).unwrap_or_default(),
+            distance_max: values::vector(value.get("distancemax")).unwrap_or([256.; 2]),
+            directions: values::vector(value.get("directions")).unwrap_or([1.; 2]),
+            pending: 0.,
+        })
+    }
+
+    fn spawn_position(&self, rng: &mut Rng) -> [f32; 2] {
+        let [x, y] = match self.shape {
+            Shape::Box => [0, 1].map(|axis| {
+                let distance = rng.range([self.distance_min[axis], self.distance_max[axis]]);
+                if rng.next() < 0.5 {
+                    -distance
+                } else {
+                    distance
+                }
+            }),
+            Shape::Sphere => {
+                let (sin, cos) = (rng.next() * TAU).sin_cos();
+                let distance = rng.range([self.distance_min[0], self.distance_max[0]]);
+                [cos * distance, sin * distance]
+            }
+        };
+        [
+            self.origin[0] + x * self.directions[0],
+            self.origin[1] + y * self.directions[1],
+        ]
+    }
+}
+
+/// Ranges the properties of new particles are picked in
+#[derive(Debug, Clone)]
+struct Initializers {
+    lifetime: [f32; 2],
+    size: [f32; 2],
+    velocity: [[f32; 2]; 2],
+    /// From 0 to 255
+    color: [[f32; 3]; 2],
+    alpha: [f32; 2],
+    rotation: [f32; 2],
+    angular_velocity: [f32; 2],
+}
+
+impl Default for Initializers {
+    fn default() -> Self {
+        Self {
+            lifetime: [1., 1.],
+            size: [20., 20.],
+            velocity: [[0.; 2]; 2],
+            color: [[255.; 3]; 2],
+            alpha: [1., 1.],
+            rotation: [0., 0.],
+            angular_velocity: [0., 0.],
+        }
+    }
+}
+
+impl Initializers {
+    /// Applies the initializer `value`, returns whether it is supported.
+    fn apply(&mut self, value: &Value) -> bool {
+        let (min, max) = (value.get("min"), value.get("max"));
+        let range = |default: [f32; 2]| {
+            [
+                values::number(min).unwrap_or(default[0]),
+                values::number(max).unwrap_or(default[1]),
+            ]
+        };
+        // Only the z axis turns a 2D particle
+        let z_range = |default: [f32; 2]| {
+            [
+                values::vector::<3>(min).map_or(default[0], |vector| vector[2]),
+                values::vector::<3>(max).map_or(default[1], |vector| vector[2]),
+            ]
+        };
+
+        match values::string(value.get("name")) {
+            Some("lifetimerandom") => self.lifetime = range(self.lifetime),
+            Some("sizerandom") => self.size = range(self.size),
+            Some("alpharandom") => self.alpha = range(self.alpha),
+            Some("rotationrandom") => self.rotation = z_range(self.rotation),
+            Some("angularvelocityrandom") => self.angular_velocity = z_range(self.angular_velocity),
+            Some("velocityrandom") => {
+                self.velocity = [
+                    values::vector(min).unwrap_or(self.velocity[0]),
+                    values::vector(max).unwrap_or(self.velocity[1]),
+                ]
+            }
+            Some("colorrandom") => {
+                self.color = [
+                    values::vector(min).unwrap_or(self.color[0]),
+                    values::vector(max).unwrap_or(self.color[1]),
+                ]
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A value going from `start_value` to `end_value` between two fractions of the lifetime
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    start_time: f32,
+    end_time: f32,
+    start_value: f32,
+    end_value: f32,
+}
+
+impl Change {
+    fn read(value: &Value) -> Self {
+        Self {
+            start_time: values::number(value.get("starttime")).unwrap_or(0.),
+            end_time: values::number(value.get("endtime")).unwrap_or(1.),
+            start_value: values::number(value.get("startvalue")).unwrap_or(1.),
+            end_value: values::number(value.get("endvalue")).unwrap_or(0.),
+        }
+    }
+
+    /// At `life`, the fraction of the lifetime lived
+    fn at(&self, life: f32) -> f32 {
+        let span = self.end_time - self.start_time;
+        let progress = if span > 0. {
+            ((life - self.start_time) / span).clamp(0., 1.)
+        } else if life < self.start_time {
+            0.
+        } else {
+            1.
+        };
+        self.start_value + (self.end_value - self.start_value) * progress
+    }
+}
+
+/// What happens to particles while they live
+#[derive(Debug, Clone, Default)]
+struct Operators {
+    gravity: [f32; 2],
+    drag: f32,
+    /// Fractions of the lifetime over which particles fade in, and after which they fade out
+    fade: Option<[f32; 2]>,
+    size: Option<Change>,
+    alpha: Option<Change>,
+}
+
+impl Operators {
+    /// Applies the operator `value`, returns whether it is supported.
+    fn apply(&mut self, value: &Value) -> bool {
+        match values::string(value.get("name")) {
+            Some("movement") => {
+                self.gravity = values::vector(value.get("gravity")).unwrap_or_default();
+                self.drag = values::number(value.get("drag")).unwrap_or(0.);
+            }
+            Some("alphafade") => {
+                self.fade = Some([
+                    values::number(value.get("fadeintime")).unwrap_or(0.5),
+                    values::number(value.get("fadeouttime")).unwrap_or(0.5),
+                ])
+            }
+            Some("sizechange") => self.size = Some(Change::read(value)),
+            Some("alphachange") => self.alpha = Some(Change::read(value)),
+            // Turning at the initial angular velocity is always done
+            Some("angularmovement") => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// Alpha multiplier at `life`, the fraction of the lifetime lived
+    fn alpha(&self, life: f32) -> f32 {
+        let fade = self.fade.map_or(1., |[fade_in, fade_out]| {
+            let fading_in = if fade_in > 0. { life / fade_in } else { 1. };
+            let fading_out = if fade_out < 1. {
+                (1. - life) / (1. - fade_out)
+            } else {
+                1.
+            };
+            fading_in.min(fading_out).clamp(0., 1.)
+        });
+        fade * self.alpha.map_or(1., |change| change.at(life))
+    }
+
+    fn size(&self, life: f32) -> f32 {
+        self.size.map_or(1., |change| change.at(life))
+    }
+}
+
+/// Multipliers an object applies to its particle system, from its `instanceoverride`
+#[derive(Debug, Clone, Copy)]
+struct Overrides {
+    count: f32,
+    rate: f32,
+    lifetime: f32,
+    size: f32,
+    speed: f32,
+    alpha: f32,
+    /// From 0 to 1
+    color: Option<[f32; 3]>,
+}
+
+impl Overrides {
+    fn read(value: Option<&Value>) -> Self {
+        let factor = |name| {
+            values::number(value.and_then(|value| value.get(name)))
+                .filter(|factor| *factor >= 0.)
+                .unwrap_or(1.)
+        };
+
+        Self {
+            count: factor("count"),
+            rate: factor("rate"),
+            lifetime: factor("lifetime"),
+            size: factor("size"),
+            speed: factor("speed"),
+            alpha: factor("alpha"),
+            color: values::vector(value.and_then(|value| value.get("colorn"))),
+        }
+    }
+}
+
+/// A particle system object: emitters spawning sprites which move and fade, simulated on the CPU.
+pub(crate) struct Particles {
+    material: Material,
+    placement: Placement,
+    emitters: Vec<Emitter>,
+    initializers: Initializers,
+    operators: Operators,
+    overrides: Overrides,
+    max_count: usize,
+
+    particles: Vec<Particle>,
+    rng: Rng,
+}
+
+impl Particles {
+    /// Loads the system of `object`, warning about the parts which aren't supported.
+    pub(crate) fn load(
+        object: &Value,
+        name: &str,
+        assets: &mut Assets,
+    ) -> Result<Self, anyhow::Error> {
+        let file = values::string(object.get("particle"))
+            .ok_or_else(|| anyhow::anyhow!("No particle system"))?;
+        let system = assets.json(file)?;
+
+        let material = values::string(system.get("material"))
+            .ok_or_else(|| anyhow::anyhow!("{} has no material", file))?;
+        let material = assets.material(material)?;
+
+        let mut unsupported = Vec::new();
+        let list = |key: &str| {
+            system
+                .get(key)
+                .and_then(Value::as_array)
+                .map_or(&[][..], Vec::as_slice)
+        };
+
+        let emitters = list("emitter")
+            .iter()
+            .filter_map(|emitter| {
+                Emitter::read(emitter)
+                    .map_err(|kind| unsupported.push(kind))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+
+        let mut initializers = Initializers::default();
+        for initializer in list("initializer") {
+            if !initializers.apply(initializer) {
+                unsupported.push(format!("initializer {}", describe(initializer)));
+            }
+        }
+
+        let mut operators = Operators::default();
+        for operator in list("operator") {
+            if !operators.apply(operator) {
+                unsupported.push(format!("operator {}", describe(operator)));
+            }
+        }
+
+        for renderer in list("renderer") {
+            if values::string(renderer.get("name")) != Some("sprite") {
+                unsupported.push(format!("renderer {}, drawn as sprites", describe(renderer)));
+            }
+        }
+
+        for part in unsupported {
+            eprintln!("Skipping {} of {}, it isn't supported", part, name);
+        }
+
+        let overrides = Overrides::read(object.get("instanceoverride"));
+        let max_count = values::number(system.get("maxcount"))
+            .map_or(DEFAULT_COUNT, |count| count.max(0.) as usize);
+
+        Ok(Self {
+            material,
+            placement: Placement::read(object),
+            emitters,
+            initializers,
+            operators,
+            max_count: ((max_count as f32 * overrides.count) as usize).min(MAX_COUNT),
+            overrides,
+            particles: Vec::new(),
+            rng: Rng::new(),
+        })
+    }
+
+    /// Moves the particles `dt` seconds forward and emits new ones.
+    pub(crate) fn update(&mut self, dt: f32) {
+        let Operators { gravity, drag, .. } = self.operators;
+
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                return false;
+            }
+
+            let damping = (1. - drag * dt).max(0.);
+            for axis in 0..2 {
+                particle.velocity[axis] = (particle.velocity[axis] + gravity[axis] * dt) * damping;
+                particle.position[axis] += particle.velocity[axis] * dt;
+            }
+            particle.rotation += particle.angular_velocity * dt;
+            true
+        });
+
+        for i in 0..self.emitters.len() {
+            let emitter = &mut self.emitters[i];
+            emitter.pending += emitter.rate * self.overrides.rate * dt;
+            let count = emitter.pending.floor();
+            emitter.pending -= count;
+
+            for _ in 0..count as usize {
+                if self.particles.len() >= self.max_count {
+                    break;
+                }
+                let particle = self.spawn(i);
+                self.particles.push(particle);
+            }
+        }
+    }
+
+    fn spawn(&mut self, emitter: usize) -> Particle {
+        let position = self.emitters[emitter].spawn_position(&mut self.rng);
+        let (init, overrides, rng) = (&self.initializers, self.overrides, &mut self.rng);
+
+        let velocity =
+            [0, 1].map(|axis| rng.range([init.velocity[0][axis], init.velocity[1][axis]]));
+        let color = match overrides.color {
+            Some(color) => color,
+            None => [0, 1, 2]
+                .map(|channel| rng.range([init.color[0][channel], init.color[1][channel]]) / 255.),
+        };
+
+        Particle {
+            position,
+            velocity: velocity.map(|speed| speed * overrides.speed),
+            size: rng.range(init.size) * overrides.size,
+            color,
+            alpha: rng.range(init.alpha) * overrides.alpha,
+            rotation: rng.range(init.rotation),
+            angular_velocity: rng.range(init.angular_velocity),
+            age: 0.,
+            lifetime: (rng.range(init.lifetime) * overrides.lifetime).max(f32::EPSILON),
+        }
+    }
+
+    /// Draws the particles, shifted by `offset` in scene units.
+    pub(crate) fn draw(
+        &self,
+        frame: &mut [u8],
+        size: PhysicalSize<u32>,
+        view: &View,
+        offset: [f32; 2],
+    ) {
+        let placement = &self.placement;
+        let image = self.material.texture.first_frame();
+
+        for particle in &self.particles {
+            let life = particle.age / particle.lifetime;
+            let side = particle.size * self.operators.size(life) * view.scale();
+            let position = [0, 1].map(|axis| {
+                placement.origin[axis]
+                    + particle.position[axis] * placement.scale[axis]
+                    + offset[axis]
+            });
+            let [r, g, b] = particle.color;
+
+            raster::draw(
+                frame,
+                size,
+                &Sprite {
+                    image,
+                    center: view.to_frame(position),
+                    size: [side * placement.scale[0], side * placement.scale[1]],
+                    angle: placement.angle + particle.rotation,
+                    tint: [r, g, b, particle.alpha * self.operators.alpha(life)],
+                    blend: self.material.blend,
+                },
+            );
+        }
+    }
+
+    #[inline]
+    pub(crate) fn parallax_depth(&self) -> [f32; 2] {
+        self.placement.parallax_depth
+    }
+}
+
+/// `name` of an emitter, initializer, operator or renderer
+fn describe(value: &Value) -> &str {
+    values::string(value.get("name")).unwrap_or("without name")
+}
+
+/// xorshift64*, plenty for scattering particles
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        // Must not be 0
+        Self(seed | 1)
+    }
+
+    /// From 0 to 1
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn range(&mut self, [min, max]: [f32; 2]) -> f32 {
+        min + (max - min) * self.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::RgbaImage;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        scene::raster::Blend,
+        tex::{Frame, Tex},
+    };
+
+    #[test]
+    fn interpolates_changes() {
+        let change = Change::read(&json!({
+            "starttime": 0.5,
+            "endtime": 1,
+            "startvalue": 2,
+            "endvalue": 4
+        }));
+        assert_eq!(change.at(0.), 2.);
+        assert_eq!(change.at(0.5), 2.);
+        assert_eq!(change.at(0.75), 3.);
+        assert_eq!(change.at(2.), 4.);
+
+        // A step without a span
+        let step = Change::read(&json!({ "starttime": 0.5, "endtime": 0.5 }));
+        assert_eq!(step.at(0.4), 1.);
+        assert_eq!(step.at(0.5), 0.);
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        let mut operators = Operators::default();
+        assert!(operators.apply(&json!({
+            "name": "alphafade",
+            "fadeintime": 0.25,
+            "fadeouttime": 0.5
+        })));
+        assert!(!operators.apply(&json!({ "name": "vortex" })));
+
+        assert_eq!(operators.alpha(0.), 0.);
+        assert_eq!(operators.alpha(0.125), 0.5);
+        assert_eq!(operators.alpha(0.25), 1.);
+        assert_eq!(operators.alpha(0.5), 1.);
+        assert_eq!(operators.alpha(0.75), 0.5);
+        assert_eq!(operators.alpha(1.), 0.);
+        assert_eq!(operators.size(0.5), 1.);
+    }
+
+    #[test]
+    fn reads_initializers() {
+        let mut initializers = Initializers::default();
+        assert!(initializers.apply(&json!({ "name": "sizerandom", "min": 5, "max": "10" })));
+        assert!(initializers.apply(&json!({
+            "name": "rotationrandom",
+            "min": "0 0 1",
+            "max": "0 0 2"
+        })));
+        assert!(initializers.apply(&json!({ "name": "colorrandom", "min": "0 0 0" })));
+        assert!(!initializers.apply(&json!({ "name": "turbulentvelocityrandom" })));
+
+        assert_eq!(initializers.size, [5., 10.]);
+        assert_eq!(initializers.rotation, [1., 2.]);
+        assert_eq!(initializers.color, [[0.; 3], [255.; 3]]);
+        assert_eq!(initializers.lifetime, [1., 1.]);
+    }
+
+    #[test]
+    fn spawns_within_the_emitter() {
+        assert!(Emitter::read(&json!({ "name": "ringrandom" })).is_err());
+
+        let emitter = Emitter::read(&json!({
+            "name": "boxrandom",
+            "origin": "100 50 0",
+            "distancemin": "10 20",
+            "distancemax": "30 40",
+            "directions": "1 0"
+        }))
+        .unwrap();
+        let mut rng = Rng::new();
+        for _ in 0..100 {
+            let [x, y] = emitter.spawn_position(&mut rng);
+            assert!((10. ..=30.).contains(&(x - 100.).abs()), "{}", x);
+            assert_eq!(y, 50.);
+        }
+
+        let emitter = Emitter::read(&json!({
+            "name": "sphererandom",
+            "distancemin": 5,
+            "distancemax": 10
+        }))
+        .unwrap();
+        for _ in 0..100 {
+            let [x, y] = emitter.spawn_position(&mut rng);
+            let distance = x.hypot(y);
+            assert!((4.99..=10.01).contains(&distance), "{}", distance);
+        }
+    }
+
+    #[test]
+    fn ranges_stay_in_bounds() {
+        let mut rng = Rng::new();
+        for _ in 0..1000 {
+            let value = rng.next();
+            assert!((0. ..1.).contains(&value));
+            assert!((-2. ..=3.).contains(&rng.range([-2., 3.])));
+        }
+    }
+
+    /// A system of one emitter of `rate` particles per second, living 1 s, falling
+    fn system(rate: f32, max_count: usize) -> Particles {
+        let mut operators = Operators::default();
+        operators.apply(&json!({ "name": "movement", "gravity": "0 -10" }));
+
+        Particles {
+            material: Material {
+                texture: Tex {
+                    frames: vec![Frame {
+                        image: RgbaImage::new(1, 1),
+                        duration: Duration::ZERO,
+                    }],
+                },
+                blend: Blend::Normal,
+            },
+            placement: Placement {
+                origin: [0.; 2],
+                scale: [1.; 2],
+                angle: 0.,
+                parallax_depth: [1.; 2],
+            },
+            emitters: vec![
+                Emitter::read(&json!({ "name": "sphererandom", "rate": rate })).unwrap(),
+            ],
+            initializers: Initializers::default(),
+            operators,
+            overrides: Overrides::read(None),
+            max_count,
+            particles: Vec::new(),
+            rng: Rng::new(),
+        }
+    }
+
+    #[test]
+    fn emits_moves_and_expires() {
+        let mut particles = system(10., 100);
+        particles.update(0.25);
+        assert_eq!(particles.particles.len(), 2);
+        // Carried over to the next update
+        particles.update(0.05);
+        assert_eq!(particles.particles.len(), 3);
+
+        let first = particles.particles[0];
+        assert_eq!(first.velocity, [0., -0.5]);
+        particles.update(0.1);
+        let moved = particles.particles[0];
+        assert_eq!(moved.velocity, [0., -1.5]);
+        assert_eq!(moved.position[0], first.position[0]);
+        assert!(moved.position[1] < first.position[1]);
+
+        // Past their lifetime
+        particles.update(0.9);
+        assert!(particles.particles.iter().all(|particle| particle.age < 1.));
+    }
+
+    #[test]
+    fn caps_the_count() {
+        let mut particles = system(1000., 10);
+        particles.update(0.1);
+        assert_eq!(particles.particles.len(), 10);
+
+        let overrides =
+            Overrides::read(Some(&json!({ "count": 2, "rate": -1, "colorn": "1 0 0" })));
+        assert_eq!(overrides.count, 2.);
+        // Negative factors are ignored
+        assert_eq!(overrides.rate, 1.);
+        assert_eq!(overrides.color, Some([1., 0., 0.]));
+    }
+}
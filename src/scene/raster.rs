@@ -0,0 +1,237 @@
+use image::RgbaImage;
+use winit::dpi::PhysicalSize;
+
+/// How a layer is composited over the ones below
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Blend {
+    /// `translucent` and `normal`, alpha blended
+    #[default]
+    Normal,
+    Additive,
+}
+
+impl Blend {
+    /// From a material pass' `blending`, any other mode is drawn as `Normal`
+    pub(crate) fn from_name(name: Option<&str>) -> Self {
+        match name {
+            Some("additive") => Self::Additive,
+            _ => Self::Normal,
+        }
+    }
+}
+
+/// An image drawn at the frame's pixel coordinates
+pub(crate) struct Sprite<'a> {
+    pub(crate) image: &'a RgbaImage,
+    pub(crate) center: [f32; 2],
+    /// Negative to mirror the image
+    pub(crate) size: [f32; 2],
+    /// Radians, counterclockwise
+    pub(crate) angle: f32,
+    /// Multiplies the image's color and alpha
+    pub(crate) tint: [f32; 4],
+    pub(crate) blend: Blend,
+}
+
+/// Draws `sprite` into the RGBA `frame` with nearest-neighbour sampling.
+pub(crate) fn draw(frame: &mut [u8], size: PhysicalSize<u32>, sprite: &Sprite) {
+    let [width, height] = sprite.size;
+    if width.abs() < f32::EPSILON || height.abs() < f32::EPSILON || sprite.tint[3] <= 0. {
+        return;
+    }
+    let (image_width, image_height) = sprite.image.dimensions();
+    if image_width == 0 || image_height == 0 {
+        return;
+    }
+
+    let (sin, cos) = sprite.angle.sin_cos();
+    let [center_x, center_y] = sprite.center;
+
+    // Bounds of the rotated rectangle, in which every pixel is mapped back to the image
+    let half_width = (width.abs() * cos.abs() + height.abs() * sin.abs()) / 2.;
+    let half_height = (width.abs() * sin.abs() + height.abs() * cos.abs()) / 2.;
+    let clamp = |value: f32, max: u32| value.clamp(0., max as f32) as u32;
+    let (left, right) = (
+        clamp((center_x - half_width).floor(), size.width),
+        clamp((center_x + half_width).ceil(), size.width),
+    );
+    let (top, bottom) = (
+        clamp((center_y - half_height).floor(), size.height),
+        clamp((center_y + half_height).ceil(), size.height),
+    );
+
+    let pixels = sprite.image.as_raw();
+    let [tint_r, tint_g, tint_b, tint_a] = sprite.tint;
+
+    for y in top..bottom {
+        let dy = y as f32 + 0.5 - center_y;
+        let row = (y * size.width) as usize * 4;
+
+        for x in left..right {
+            let dx = x as f32 + 0.5 - center_x;
+            // Rotated back, the screen's y axis points down
+            let u = (dx * cos - dy * sin) / width + 0.5;
+            let v = (dx * sin + dy * cos) / height + 0.5;
+            if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) {
+                continue;
+            }
+
+            // Clamped against rounding up to the side
+            let texel_x = ((u * image_width as f32) as u32).min(image_width - 1);
+            let texel_y = ((v * image_height as f32) as u32).min(image_height - 1);
+            let texel = (texel_y as usize * image_width as usize + texel_x as usize) * 4;
+            let [r, g, b, a] = [0, 1, 2, 3].map(|i| pixels[texel + i] as f32);
+            let alpha = a / 255. * tint_a;
+            if alpha <= 0. {
+                continue;
+            }
+
+            let source = [r * tint_r, g * tint_g, b * tint_b];
+            let pixel = &mut frame[row + x as usize * 4..][..3];
+            for (destination, source) in pixel.iter_mut().zip(source) {
+                let value = match sprite.blend {
+                    Blend::Normal => source * alpha + *destination as f32 * (1. - alpha),
+                    Blend::Additive => *destination as f32 + source * alpha,
+                };
+                *destination = value.clamp(0., 255.) as u8;
+            }
+        }
+    }
+}
+
+/// Fills `frame` with an opaque color.
+pub(crate) fn clear(frame: &mut [u8], color: [u8; 3]) {
+    let [r, g, b] = color;
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[r, g, b, 0xff]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(4, 4);
+
+    fn black_frame() -> Vec<u8> {
+        let mut frame = vec![0; 4 * 4 * 4];
+        clear(&mut frame, [0; 3]);
+        frame
+    }
+
+    fn pixel(frame: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let i = (y * 4 + x) * 4;
+        [frame[i], frame[i + 1], frame[i + 2]]
+    }
+
+    /// A 2x1 image, red then blue
+    fn red_blue() -> RgbaImage {
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([0xff, 0, 0, 0xff]));
+        image.put_pixel(1, 0, Rgba([0, 0, 0xff, 0xff]));
+        image
+    }
+
+    fn sprite(image: &RgbaImage) -> Sprite<'_> {
+        Sprite {
+            image,
+            center: [2., 2.],
+            size: [2., 2.],
+            angle: 0.,
+            tint: [1.; 4],
+            blend: Blend::Normal,
+        }
+    }
+
+    #[test]
+    fn draws_inside_the_rectangle() {
+        let image = red_blue();
+        let mut frame = black_frame();
+        draw(&mut frame, SIZE, &sprite(&image));
+
+        assert_eq!(pixel(&frame, 1, 1), [0xff, 0, 0]);
+        assert_eq!(pixel(&frame, 2, 2), [0, 0, 0xff]);
+        for (x, y) in [(0, 0), (3, 1), (1, 3)] {
+            assert_eq!(pixel(&frame, x, y), [0; 3]);
+        }
+    }
+
+    #[test]
+    fn mirrors_and_rotates() {
+        let image = red_blue();
+
+        let mut frame = black_frame();
+        draw(
+            &mut frame,
+            SIZE,
+            &Sprite {
+                size: [-2., 2.],
+                ..sprite(&image)
+            },
+        );
+        assert_eq!(pixel(&frame, 1, 1), [0, 0, 0xff]);
+
+        // A quarter turn counterclockwise puts the red side at the bottom
+        let mut frame = black_frame();
+        draw(
+            &mut frame,
+            SIZE,
+            &Sprite {
+                angle: std::f32::consts::FRAC_PI_2,
+                ..sprite(&image)
+            },
+        );
+        assert_eq!(pixel(&frame, 1, 2), [0xff, 0, 0]);
+        assert_eq!(pixel(&frame, 1, 1), [0, 0, 0xff]);
+    }
+
+    #[test]
+    fn blends() {
+        let image = red_blue();
+
+        let mut frame = black_frame();
+        clear(&mut frame, [0, 0, 200]);
+        draw(
+            &mut frame,
+            SIZE,
+            &Sprite {
+                tint: [1., 1., 1., 0.5],
+                ..sprite(&image)
+            },
+        );
+        assert_eq!(pixel(&frame, 1, 1), [127, 0, 100]);
+
+        draw(
+            &mut frame,
+            SIZE,
+            &Sprite {
+                blend: Blend::Additive,
+                ..sprite(&image)
+            },
+        );
+        // Saturated
+        assert_eq!(pixel(&frame, 2, 1), [0, 0, 0xff]);
+        assert_eq!(pixel(&frame, 1, 1), [0xff, 0, 100]);
+    }
+
+    #[test]
+    fn clips_to_the_frame() {
+        let image = red_blue();
+        let mut frame = black_frame();
+        draw(
+            &mut frame,
+            SIZE,
+            &Sprite {
+                center: [-1., 5.],
+                size: [100., 100.],
+                ..sprite(&image)
+            },
+        );
+        assert!(frame.chunks(4).all(|pixel| pixel[3] == 0xff));
+
+        assert_eq!(Blend::from_name(Some("additive")), Blend::Additive);
+        assert_eq!(Blend::from_name(Some("translucent")), Blend::Normal);
+    }
+}
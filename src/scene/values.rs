@@ -0,0 +1,100 @@
+use serde_json::Value;
+
+/// The value itself, unwrapping `{"user": "property", "value": ...}` which binds it to a user
+/// property; the default value is used.
+pub(crate) fn unwrap(value: &Value) -> &Value {
+    match value {
+        Value::Object(object) => object.get("value").unwrap_or(value),
+        value => value,
+    }
+}
+
+pub(crate) fn number(value: Option<&Value>) -> Option<f32> {
+    match unwrap(value?) {
+        Value::Number(number) => number.as_f64().map(|number| number as f32),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// A vector written as `"x y z"`, an array, or a single number for all components. Missing
+/// components are 0.
+pub(crate) fn vector<const N: usize>(value: Option<&Value>) -> Option<[f32; N]> {
+    let mut vector = [0.; N];
+
+    match unwrap(value?) {
+        Value::Number(number) => vector = [number.as_f64()? as f32; N],
+        Value::String(string) => {
+            let mut parts = string.split_whitespace();
+            for component in &mut vector {
+                match parts.next() {
+                    Some(part) => *component = part.parse().ok()?,
+                    None => break,
+                }
+            }
+        }
+        Value::Array(array) => {
+            for (component, value) in vector.iter_mut().zip(array) {
+                *component = value.as_f64()? as f32;
+            }
+        }
+        _ => return None,
+    }
+    Some(vector)
+}
+
+pub(crate) fn boolean(value: Option<&Value>) -> Option<bool> {
+    match unwrap(value?) {
+        Value::Bool(boolean) => Some(*boolean),
+        Value::Number(number) => number.as_f64().map(|number| number != 0.),
+        _ => None,
+    }
+}
+
+pub(crate) fn string(value: Option<&Value>) -> Option<&str> {
+    unwrap(value?).as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn unwraps_user_properties() {
+        let bound = json!({ "user": "speed", "value": 2.5 });
+        assert_eq!(number(Some(&bound)), Some(2.5));
+        assert_eq!(number(Some(&json!(" 3 "))), Some(3.));
+        assert_eq!(number(Some(&json!("fast"))), None);
+        assert_eq!(number(None), None);
+    }
+
+    #[test]
+    fn reads_vectors() {
+        assert_eq!(vector(Some(&json!("1 2.5 -3"))), Some([1., 2.5, -3.]));
+        assert_eq!(vector(Some(&json!([4, 5]))), Some([4., 5., 0.]));
+        assert_eq!(vector(Some(&json!(0.5))), Some([0.5; 3]));
+        // Missing components are 0, extra ones are ignored
+        assert_eq!(vector(Some(&json!("7"))), Some([7., 0.]));
+        assert_eq!(vector(Some(&json!("1 2 3"))), Some([1., 2.]));
+        assert_eq!(vector::<2>(Some(&json!("1 x"))), None);
+        assert_eq!(
+            vector(Some(&json!({ "user": "color", "value": "0 0.5 1" }))),
+            Some([0., 0.5, 1.])
+        );
+    }
+
+    #[test]
+    fn reads_booleans_and_strings() {
+        assert_eq!(boolean(Some(&json!(true))), Some(true));
+        assert_eq!(boolean(Some(&json!(0))), Some(false));
+        assert_eq!(
+            boolean(Some(&json!({ "user": "on", "value": 1 }))),
+            Some(true)
+        );
+        assert_eq!(boolean(Some(&json!("true"))), None);
+        assert_eq!(string(Some(&json!("models/a.json"))), Some("models/a.json"));
+        assert_eq!(string(Some(&json!(1))), None);
+    }
+}
@@ -0,0 +1,474 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use image::RgbaImage;
+use winit::dpi::PhysicalSize;
+
+use crate::{source::Source, still::Still};
+
+/// Where the time comes from, so that a schedule can be followed at any time
+pub(crate) trait Clock: Send {
+    /// Local time, like the slideshow's start time
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// The wall clock
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// A part of the slideshow, in seconds
+#[derive(Debug, Clone)]
+enum Section {
+    Static {
+        duration: f64,
+        file: PathBuf,
+    },
+    /// Always crossfaded, whatever the `type`
+    Transition {
+        duration: f64,
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+impl Section {
+    fn duration(&self) -> f64 {
+        match self {
+            Self::Static { duration, .. } | Self::Transition { duration, .. } => *duration,
+        }
+    }
+}
+
+/// What to show at some time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Slide<'a> {
+    Static(&'a Path),
+    Crossfade {
+        from: &'a Path,
+        to: &'a Path,
+        /// From 0, all `from`, to 1, all `to`
+        progress: f64,
+    },
+}
+
+/// A GNOME `<background>` file: static and transition sections played one after the other from
+/// the start time, looping.
+#[derive(Debug, Clone)]
+pub(crate) struct Schedule {
+    start: NaiveDateTime,
+    sections: Vec<Section>,
+    /// Sum of the sections' durations
+    period: f64,
+}
+
+impl Schedule {
+    /// Parses `xml`, resolving relative files against `dir`. Where a static section has images
+    /// of several sizes, the one closest to `size` is picked.
+    pub(crate) fn parse(
+        xml: &str,
+        dir: &Path,
+        size: PhysicalSize<u32>,
+    ) -> Result<Self, anyhow::Error> {
+        let document = roxmltree::Document::parse(xml)?;
+        let root = document.root_element();
+        if root.tag_name().name() != "background" {
+            return Err(anyhow::anyhow!(
+                "Expected <background>, found <{}>",
+                root.tag_name().name()
+            ));
+        }
+
+        let mut start = None;
+        let mut sections = Vec::new();
+        for node in root.children().filter(roxmltree::Node::is_element) {
+            match node.tag_name().name() {
+                "starttime" => start = Some(parse_start(node)?),
+                "static" => sections.push(Section::Static {
+                    duration: parse_duration(node)?,
+                    file: dir.join(pick_file(child(node, "file")?, size)?),
+                }),
+                "transition" => sections.push(Section::Transition {
+                    duration: parse_duration(node)?,
+                    from: dir.join(text(child(node, "from")?)?),
+                    to: dir.join(text(child(node, "to")?)?),
+                }),
+                name => eprintln!("Skipping <{}> of the slideshow, it isn't supported", name),
+            }
+        }
+
+        let start = start.ok_or_else(|| anyhow::anyhow!("No <starttime>"))?;
+        let period = sections.iter().map(Section::duration).sum::<f64>();
+        if sections.is_empty() || period <= 0. {
+            return Err(anyhow::anyhow!("No sections with a duration"));
+        }
+
+        Ok(Self {
+            start,
+            sections,
+            period,
+        })
+    }
+
+    /// The slide at `time`, looping over the sections from the start time
+    pub(crate) fn at(&self, time: NaiveDateTime) -> Slide<'_> {
+        let elapsed = (time - self.start).num_milliseconds() as f64 / 1000.;
+        // Also before the start, as if it had always been playing
+        let mut position = elapsed.rem_euclid(self.period);
+
+        for section in &self.sections {
+            let duration = section.duration();
+            if position < duration {
+                return match section {
+                    Section::Static { file, .. } => Slide::Static(file),
+                    Section::Transition { from, to, .. } => Slide::Crossfade {
+                        from,
+                        to,
+                        progress: position / duration,
+                    },
+                };
+            }
+            position -= duration;
+        }
+
+        // Rounding past the last section
+        match self.sections.last().expect("Checked when parsing") {
+            Section::Static { file, .. } => Slide::Static(file),
+            Section::Transition { to, .. } => Slide::Static(to),
+        }
+    }
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Result<roxmltree::Node<'a, 'input>, anyhow::Error> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .ok_or_else(|| anyhow::anyhow!("<{}> without <{}>", node.tag_name().name(), name))
+}
+
+fn text<'a>(node: roxmltree::Node<'a, '_>) -> Result<&'a str, anyhow::Error> {
+    node.text()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Empty <{}>", node.tag_name().name()))
+}
+
+fn parse_duration(node: roxmltree::Node) -> Result<f64, anyhow::Error> {
+    let duration = text(child(node, "duration")?)?;
+    duration
+        .parse::<f64>()
+        .ok()
+        .filter(|duration| *duration >= 0. && duration.is_finite())
+        .ok_or_else(|| anyhow::anyhow!("Invalid duration {:?}", duration))
+}
+
+fn parse_start(node: roxmltree::Node) -> Result<NaiveDateTime, anyhow::Error> {
+    let field = |name: &str| -> Result<u32, anyhow::Error> {
+        let value = text(child(node, name)?)?;
+        value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid <{}> {:?}", name, value))
+    };
+
+    let date = NaiveDate::from_ymd_opt(field("year")? as i32, field("month")?, field("day")?);
+    let (hour, minute, second) = (field("hour")?, field("minute")?, field("second")?);
+    date.and_then(|date| date.and_hms_opt(hour, minute, second))
+        .ok_or_else(|| anyhow::anyhow!("Invalid <starttime>"))
+}
+
+/// The path in `<file>`, or of its `<size>` closest to `size`
+fn pick_file<'a>(
+    file: roxmltree::Node<'a, '_>,
+    size: PhysicalSize<u32>,
+) -> Result<&'a str, anyhow::Error> {
+    // `u32` sides, so the areas fit
+    let target = size.width as u64 * size.height as u64;
+    let sized = file
+        .children()
+        .filter(|child| child.has_tag_name("size"))
+        .filter_map(|child| {
+            let width = child.attribute("width")?.parse::<u32>().ok()?;
+            let height = child.attribute("height")?.parse::<u32>().ok()?;
+            let area = width as u64 * height as u64;
+            Some((area.abs_diff(target), text(child).ok()?))
+        })
+        .min_by_key(|(distance, _)| *distance);
+
+    match sized {
+        Some((_, path)) => Ok(path),
+        None => text(file),
+    }
+}
+
+/// A GNOME dynamic wallpaper: images changing and crossfading at times of the day.
+pub(crate) struct Slideshow<C> {
+    schedule: Schedule,
+    clock: C,
+    size: PhysicalSize<u32>,
+
+    /// The slide's images, covering the frame
+    images: Vec<(PathBuf, RgbaImage)>,
+    /// Slide drawn last, with the crossfade's progress in 1/255
+    drawn: Option<(PathBuf, Option<PathBuf>, u8)>,
+    frame: Vec<u8>,
+
+    need_render: bool,
+}
+
+impl<C: Clock> Slideshow<C> {
+    pub(crate) fn open<S>(size: S, path: &Path, clock: C) -> Result<Self, anyhow::Error>
+    where
+        S: Into<PhysicalSize<u32>>,
+    {
+        let size: PhysicalSize<u32> = size.into();
+        let xml = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let schedule = Schedule::parse(&xml, dir, size)
+            .map_err(|e| e.context(format!("Invalid slideshow {}", path.display())))?;
+
+        let mut slideshow = Self {
+            schedule,
+            clock,
+            size,
+            images: Vec::new(),
+            drawn: None,
+            frame: Vec::new(),
+            need_render: false,
+        };
+        // Fails early on a missing image
+        slideshow.update()?;
+        Ok(slideshow)
+    }
+
+    /// The image at `path` scaled to the frame, loaded once for as long as it is shown
+    fn image(&mut self, path: &Path) -> Result<&RgbaImage, anyhow::Error> {
+        let index = match self.images.iter().position(|(loaded, _)| loaded == path) {
+            Some(index) => index,
+            None => {
+                let image = Still::cover(&Still::open(path)?, self.size);
+                self.images.push((path.to_owned(), image));
+                self.images.len() - 1
+            }
+        };
+        Ok(&self.images[index].1)
+    }
+}
+
+impl<C: Clock> Source for Slideshow<C> {
+    fn update(&mut self) -> Result<(), anyhow::Error> {
+        let now = self.clock.now();
+        let (from, to, progress) = match self.schedule.at(now) {
+            Slide::Static(file) => (file.to_owned(), None, 0),
+            Slide::Crossfade { from, to, progress } => (
+                from.to_owned(),
+                Some(to.to_owned()),
+                (progress.clamp(0., 1.) * 255.).round() as u8,
+            ),
+        };
+
+        let slide = (from, to, progress);
+        if self.drawn.as_ref() == Some(&slide) {
+            return Ok(());
+        }
+        let (from, to, progress) = &slide;
+
+        // Only the images of this slide are kept
+        self.images
+            .retain(|(loaded, _)| loaded == from || Some(loaded) == to.as_ref());
+        self.frame = self.image(from)?.as_raw().clone();
+        if let Some(to) = to {
+            let progress = *progress as u32;
+            let to = self.image(to)?.as_raw().clone();
+            for (pixel, to) in self.frame.iter_mut().zip(to) {
+                *pixel = ((*pixel as u32 * (255 - progress) + to as u32 * progress) / 255) as u8;
+            }
+        }
+
+        self.drawn = Some(slide);
+        self.need_render = true;
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut [u8]) -> bool {
+        if self.need_render && frame.len() == self.frame.len() {
+            self.need_render = false;
+
+            frame.copy_from_slice(&self.frame);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn frame_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A clock the test sets, shared with the slideshow
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<NaiveDateTime>>);
+
+    impl FakeClock {
+        fn new(time: NaiveDateTime) -> Self {
+            Self(Arc::new(Mutex::new(time)))
+        }
+
+        fn set(&self, time: NaiveDateTime) {
+            *self.0.lock().unwrap() = time;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> NaiveDateTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// A minute of `a.png`, a 20 s crossfade to `b.png`, a minute of it and back, 160 s in all
+    const XML: &str = r#"<background>
+        <starttime>
+            <year>2026</year><month>1</month><day>1</day>
+            <hour>8</hour><minute>0</minute><second>0</second>
+        </starttime>
+        <static>
+            <duration>60.0</duration>
+            <file>
+                <size width="640" height="480">a-small.png</size>
+                <size width="4" height="2">a.png</size>
+            </file>
+        </static>
+        <transition type="overlay">
+            <duration>20.0</duration>
+            <from>a.png</from>
+            <to>b.png</to>
+        </transition>
+        <static>
+            <duration>60.0</duration>
+            <file>b.png</file>
+        </static>
+        <transition>
+            <duration>20.0</duration>
+            <from>b.png</from>
+            <to>a.png</to>
+        </transition>
+    </background>"#;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(4, 2);
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    fn crossfade(slide: Slide<'_>) -> (&Path, &Path, f64) {
+        match slide {
+            Slide::Crossfade { from, to, progress } => (from, to, progress),
+            slide => panic!("Expected a crossfade, got {:?}", slide),
+        }
+    }
+
+    #[test]
+    fn follows_the_schedule() {
+        let dir = Path::new("/slides");
+        let schedule = Schedule::parse(XML, dir, SIZE).unwrap();
+        let (a, b) = (dir.join("a.png"), dir.join("b.png"));
+
+        assert_eq!(schedule.at(at(8, 0, 0)), Slide::Static(&a));
+        assert_eq!(schedule.at(at(8, 0, 30)), Slide::Static(&a));
+
+        let (from, to, progress) = crossfade(schedule.at(at(8, 1, 10)));
+        assert_eq!((from, to), (a.as_path(), b.as_path()));
+        assert!((progress - 0.5).abs() < 1e-9);
+
+        assert_eq!(schedule.at(at(8, 1, 30)), Slide::Static(&b));
+
+        // Looping: 190 s is 30 s into the second period
+        assert_eq!(schedule.at(at(8, 3, 10)), Slide::Static(&a));
+
+        // 10 s before the start is halfway through the crossfade back to `a`
+        let (from, to, progress) = crossfade(schedule.at(at(7, 59, 50)));
+        assert_eq!((from, to), (b.as_path(), a.as_path()));
+        assert!((progress - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        let dir = Path::new("/slides");
+        for xml in [
+            "<slides/>",
+            "<background><static><duration>1</duration><file>a.png</file></static></background>",
+            "<background><starttime><year>2026</year></starttime></background>",
+        ] {
+            assert!(Schedule::parse(xml, dir, SIZE).is_err(), "{}", xml);
+        }
+    }
+
+    #[test]
+    fn picks_the_closest_size() {
+        let xml = r#"<file>
+            <size width="4294967295" height="4294967295">huge.png</size>
+            <size width="99999999999" height="1">invalid.png</size>
+            <size width="1920" height="1080">hd.png</size>
+        </file>"#;
+        let document = roxmltree::Document::parse(xml).unwrap();
+        let file = document.root_element();
+
+        assert_eq!(
+            pick_file(file, PhysicalSize::new(1280, 720)).unwrap(),
+            "hd.png"
+        );
+        assert_eq!(
+            pick_file(file, PhysicalSize::new(u32::MAX, u32::MAX)).unwrap(),
+            "huge.png"
+        );
+    }
+
+    #[test]
+    fn crossfades_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, pixel: [u8; 4]| {
+            RgbaImage::from_pixel(SIZE.width, SIZE.height, image::Rgba(pixel))
+                .save(dir.path().join(name))
+                .unwrap();
+        };
+        write("a.png", [255, 0, 0, 255]);
+        write("b.png", [0, 0, 255, 255]);
+        let path = dir.path().join("slideshow.xml");
+        std::fs::write(&path, XML).unwrap();
+
+        let clock = FakeClock::new(at(8, 1, 10));
+        let mut slideshow = Slideshow::open(SIZE, &path, clock.clone()).unwrap();
+        let mut frame = vec![0; (SIZE.width * SIZE.height * 4) as usize];
+
+        // Halfway is 128/255 of `b`
+        assert!(slideshow.render(&mut frame));
+        assert!(frame.chunks(4).all(|pixel| pixel == [127, 0, 128, 255]));
+
+        // Nothing new until the slide changes
+        slideshow.update().unwrap();
+        assert!(!slideshow.render(&mut frame));
+
+        clock.set(at(8, 1, 30));
+        slideshow.update().unwrap();
+        assert!(slideshow.render(&mut frame));
+        assert!(frame.chunks(4).all(|pixel| pixel == [0, 0, 255, 255]));
+    }
+}
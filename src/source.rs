@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use winit::dpi::PhysicalSize;
+
+use crate::reactive::Energies;
+
+/// Something which draws the wallpaper into the pixels buffer, e.g. a `Video`.
+pub(crate) trait Source: Send {
+    /// Called at the target framerate. Errors are recovered from by rebuilding the source.
+    fn update(&mut self) -> Result<(), anyhow::Error>;
+
+    /// Draws into `frame` if there is something new, returns whether it did.
+    fn render(&mut self, frame: &mut [u8]) -> bool;
+
+    /// Size of the frames drawn by `render`, which the pixels buffer is resized to
+    fn frame_size(&self) -> PhysicalSize<u32>;
+
+    /// Stops producing frames while the wallpaper can't be seen, see `Activity`.
+    fn set_paused(&mut self, _paused: bool) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Produces at most `framerate` frames per second, or as many as it can with `None`.
+    fn set_max_framerate(&mut self, _framerate: Option<f64>) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Silences the wallpaper's audio, if it plays any.
+    fn set_muted(&mut self, _muted: bool) {}
+
+    /// Where the cursor is over the wallpaper, from -1 to 1 with y going up, `None` if it isn't.
+    fn set_pointer(&mut self, _pointer: Option<[f32; 2]>) {}
+
+    fn update_surface_size(&mut self, _size: PhysicalSize<u32>) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Starts fading the audio out, returns how long it takes.
+    fn fade_out(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Energies of the wallpaper's own audio, `None` if it has none
+    fn energies(&self) -> Option<Energies> {
+        None
+    }
+
+    /// Factory name of the video decoder in use, for the status
+    fn decoder(&self) -> Option<String> {
+        None
+    }
+}
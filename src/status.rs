@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{activity::Reason, governor::Level, paths};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum PlaybackState {
+    #[default]
+    Starting,
+    Playing,
+    /// Hidden from view, see `Status::throttled`
+    Paused,
+    /// Waiting to retry after an error
+    Retrying,
+    /// Showing the fallback, while still retrying
+    Fallback,
+    /// Stopped to save power, showing the fallback
+    Stopped,
+}
+
+/// What the running instance is doing, published to `$XDG_RUNTIME_DIR/awa/status.json`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Status {
+    pub(crate) pid: u32,
+    /// Description of the wallpaper, e.g. its URI
+    pub(crate) wallpaper: String,
+    pub(crate) state: PlaybackState,
+    /// Factory name of the video decoder playbin picked
+    pub(crate) decoder: Option<String>,
+    /// Consecutive failures of the wallpaper
+    pub(crate) failures: u32,
+    /// Why the wallpaper is paused or slowed down
+    #[serde(default)]
+    pub(crate) throttled: Vec<Reason>,
+    /// Throttle level of the CPU budget governor
+    #[serde(default)]
+    pub(crate) cpu_level: Level,
+    /// CPU usage in percent of one core, measured by the governor
+    #[serde(default)]
+    pub(crate) cpu_usage: Option<f64>,
+}
+
+impl Status {
+    #[inline]
+    pub(crate) fn path() -> PathBuf {
+        paths::runtime_dir().join("status.json")
+    }
+
+    pub(crate) fn publish(&self) -> Result<(), anyhow::Error> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Readers never see a partially written file
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub(crate) fn read() -> Result<Self, anyhow::Error> {
+        let path = Self::path();
+        let data = std::fs::read(&path).map_err(|e| {
+            anyhow::anyhow!("awa doesn't seem to be running ({}: {})", path.display(), e)
+        })?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+/// `awa status`
+pub(crate) fn print(json: bool) -> Result<(), anyhow::Error> {
+    let status = Status::read()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    println!("PID:      {}", status.pid);
+    println!("Playing:  {}", status.wallpaper);
+    println!("State:    {:?}", status.state);
+    println!(
+        "Decoder:  {}",
+        status.decoder.as_deref().unwrap_or("(none)")
+    );
+    println!("Failures: {}", status.failures);
+    if let Some(usage) = status.cpu_usage {
+        println!(
+            "CPU:      {:.1}% ({})",
+            usage,
+            format!("{:?}", status.cpu_level).to_lowercase()
+        );
+    }
+    if !status.throttled.is_empty() {
+        let reasons = status
+            .throttled
+            .iter()
+            .map(|reason| format!("{:?}", reason).to_lowercase())
+            .collect::<Vec<_>>();
+        println!("Throttled: {}", reasons.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use image::{imageops, RgbaImage};
+use winit::dpi::PhysicalSize;
+
+use crate::{config::Fallback, source::Source, tex::Tex};
+
+/// A still image or a solid color, rendered once at the buffer size.
+pub(crate) struct Still {
+    frame: Vec<u8>,
+    size: PhysicalSize<u32>,
+
+    need_render: bool,
+}
+
+impl Still {
+    pub(crate) fn new<S>(fallback: &Fallback, size: S) -> Result<Self, anyhow::Error>
+    where
+        S: Into<PhysicalSize<u32>>,
+    {
+        let size: PhysicalSize<u32> = size.into();
+
+        let frame = match fallback {
+            Fallback::Image { path } => {
+                let image = Self::open(path)?;
+
+                Self::cover(&image, size).into_raw()
+            }
+            Fallback::Color { color } => Self::solid(*color, size),
+        };
+
+        Ok(Self {
+            frame,
+            size,
+            need_render: true,
+        })
+    }
+
+    /// The image at `path` as a wallpaper, e.g. from the library
+    pub(crate) fn image(path: &Path, size: PhysicalSize<u32>) -> Result<Self, anyhow::Error> {
+        Self::new(
+            &Fallback::Image {
+                path: path.to_owned(),
+            },
+            size,
+        )
+    }
+
+    /// Opens an image file, or a Wallpaper Engine texture.
+    pub(crate) fn open(path: &Path) -> Result<RgbaImage, anyhow::Error> {
+        let is_tex = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("tex"));
+        if is_tex {
+            return Ok(Tex::open(path)?.first_frame().clone());
+        }
+
+        Ok(image::open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?
+            .into_rgba8())
+    }
+
+    #[inline]
+    pub(crate) fn solid(color: [u8; 3], size: PhysicalSize<u32>) -> Vec<u8> {
+        let [r, g, b] = color;
+        [r, g, b, 0xff].repeat(size.width as usize * size.height as usize)
+    }
+
+    /// Scales `image` to fill `size`, cropping the overflowing sides like `aspectratiocrop` does.
+    pub(crate) fn cover(image: &RgbaImage, size: PhysicalSize<u32>) -> RgbaImage {
+        let (width, height) = image.dimensions();
+        let (target_width, target_height) = (size.width.max(1), size.height.max(1));
+
+        let (crop_width, crop_height) =
+            if width as u64 * target_height as u64 > height as u64 * target_width as u64 {
+                (
+                    (height as u64 * target_width as u64 / target_height as u64) as u32,
+                    height,
+                )
+            } else {
+                (
+                    width,
+                    (width as u64 * target_height as u64 / target_width as u64) as u32,
+                )
+            };
+        let (crop_width, crop_height) = (crop_width.max(1), crop_height.max(1));
+
+        let cropped = imageops::crop_imm(
+            image,
+            (width - crop_width) / 2,
+            (height - crop_height) / 2,
+            crop_width,
+            crop_height,
+        )
+        .to_image();
+
+        imageops::resize(
+            &cropped,
+            target_width,
+            target_height,
+            imageops::FilterType::Triangle,
+        )
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut [u8]) -> bool {
+        if self.need_render && frame.len() == self.frame.len() {
+            self.need_render = false;
+
+            frame.copy_from_slice(&self.frame);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Source for Still {
+    fn update(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut [u8]) -> bool {
+        Still::render(self, frame)
+    }
+
+    fn frame_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+}
@@ -0,0 +1,722 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+    time::Duration,
+};
+
+use image::{imageops, RgbaImage};
+
+/// Containers of a `.tex` file, each starting with a NUL-terminated 8-byte magic
+const TEXV: &str = "TEXV";
+const TEXI: &str = "TEXI";
+const TEXB: &str = "TEXB";
+const TEXS: &str = "TEXS";
+
+/// `TEXI` flag of sprite sheets, which end with a `TEXS` frame table
+const FLAG_SPRITE_SHEET: u32 = 1 << 2;
+
+/// Bounds on what a texture may claim, so that a corrupt one can't exhaust memory
+const MAX_SIDE: u32 = 16_384;
+const MAX_IMAGES: u32 = 4096;
+const MAX_MIPMAPS: u32 = 32;
+const MAX_FRAMES: u32 = 65_536;
+/// Longest a sprite sheet frame is shown, in seconds
+const MAX_FRAMETIME: f32 = 3600.;
+/// Headers and metadata an encoded mipmap may have on top of its pixels
+const MAX_ENCODED_OVERHEAD: usize = 1 << 16;
+
+/// Pixel format of the mipmaps which aren't encoded image files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Rgba8888,
+    Dxt5,
+    Dxt3,
+    Dxt1,
+    /// Luminance and alpha
+    Rg88,
+    /// Luminance
+    R8,
+}
+
+impl Format {
+    fn from_id(id: u32) -> Result<Self, anyhow::Error> {
+        Ok(match id {
+            0 => Self::Rgba8888,
+            4 => Self::Dxt5,
+            6 => Self::Dxt3,
+            7 => Self::Dxt1,
+            8 => Self::Rg88,
+            9 => Self::R8,
+            id => return Err(anyhow::anyhow!("Unknown texture format {}", id)),
+        })
+    }
+
+    /// Bytes of a `width`x`height` mipmap in this format
+    fn mipmap_len(self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        let blocks = || ((width + 3) / 4) * ((height + 3) / 4);
+
+        match self {
+            Self::Rgba8888 => width * height * 4,
+            Self::Rg88 => width * height * 2,
+            Self::R8 => width * height,
+            Self::Dxt1 => blocks() * 8,
+            Self::Dxt3 | Self::Dxt5 => blocks() * 16,
+        }
+    }
+}
+
+/// A frame of an animated texture
+#[derive(Debug, Clone)]
+pub(crate) struct Frame {
+    pub(crate) image: RgbaImage,
+    pub(crate) duration: Duration,
+}
+
+/// A decoded Wallpaper Engine texture: one image, or the frames of a sprite sheet.
+#[derive(Debug, Clone)]
+pub(crate) struct Tex {
+    pub(crate) frames: Vec<Frame>,
+}
+
+impl Tex {
+    pub(crate) fn open(path: &Path) -> Result<Self, anyhow::Error> {
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        Self::decode(&data).map_err(|e| e.context(format!("Invalid texture {}", path.display())))
+    }
+
+    /// Decodes a `.tex` file: a `TEXV` header, a `TEXI` header with the format and sizes, a `TEXB`
+    /// container of images and their mipmaps, and a `TEXS` frame table for sprite sheets.
+    pub(crate) fn decode(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut reader = Cursor::new(data);
+
+        expect_magic(&mut reader, TEXV)?;
+        expect_magic(&mut reader, TEXI)?;
+        let format = Format::from_id(read_u32(&mut reader)?)?;
+        let flags = read_u32(&mut reader)?;
+        let _texture_width = read_u32(&mut reader)?;
+        let _texture_height = read_u32(&mut reader)?;
+        let image_width = read_u32(&mut reader)?;
+        let image_height = read_u32(&mut reader)?;
+        let _unknown = read_u32(&mut reader)?;
+        check_size(image_width, image_height)?;
+
+        let version = expect_magic(&mut reader, TEXB)?;
+        let image_count = read_u32(&mut reader)?;
+        if image_count == 0 || image_count > MAX_IMAGES {
+            return Err(anyhow::anyhow!("{} images", image_count));
+        }
+        // Set when the mipmaps are image files, e.g. 13 for PNG, -1 otherwise
+        let encoded = match version {
+            1 | 2 => false,
+            3 => read_u32(&mut reader)? as i32 != -1,
+            version => return Err(anyhow::anyhow!("TEXB{:04} isn't supported", version)),
+        };
+
+        let images = (0..image_count)
+            .map(|_| {
+                let image = read_image(&mut reader, version, format, encoded)?;
+                // Textures are padded to powers of two
+                let (width, height) = (
+                    image_width.min(image.width()),
+                    image_height.min(image.height()),
+                );
+                Ok(imageops::crop_imm(&image, 0, 0, width, height).to_image())
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        let frames = if flags & FLAG_SPRITE_SHEET != 0 {
+            read_sprite_frames(&mut reader, &images)?
+        } else {
+            images
+                .into_iter()
+                .map(|image| Frame {
+                    image,
+                    duration: Duration::ZERO,
+                })
+                .collect()
+        };
+
+        Ok(Self { frames })
+    }
+
+    #[inline]
+    pub(crate) fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    /// The first frame, which is all there is to a still texture
+    #[inline]
+    pub(crate) fn first_frame(&self) -> &RgbaImage {
+        &self.frames[0].image
+    }
+
+    /// The frame shown `elapsed` after the start, looping over the sprite sheet
+    pub(crate) fn frame_at(&self, elapsed: Duration) -> &RgbaImage {
+        let total = self.frames.iter().fold(Duration::ZERO, |total, frame| {
+            total.saturating_add(frame.duration)
+        });
+        if total.is_zero() {
+            return self.first_frame();
+        }
+
+        let mut position = Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64);
+        for frame in &self.frames {
+            if position < frame.duration {
+                return &frame.image;
+            }
+            position -= frame.duration;
+        }
+        self.first_frame()
+    }
+}
+
+/// The largest mipmap of an image, the others are skipped.
+fn read_image(
+    reader: &mut Cursor<&[u8]>,
+    version: u32,
+    format: Format,
+    encoded: bool,
+) -> Result<RgbaImage, anyhow::Error> {
+    let mipmap_count = read_u32(reader)?;
+    if mipmap_count == 0 || mipmap_count > MAX_MIPMAPS {
+        return Err(anyhow::anyhow!("{} mipmaps", mipmap_count));
+    }
+
+    let mut largest = None;
+    for _ in 0..mipmap_count {
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        check_size(width, height)?;
+
+        let (lz4, decompressed_len) = if version >= 2 {
+            (read_u32(reader)? == 1, read_u32(reader)? as usize)
+        } else {
+            (false, 0)
+        };
+        let len = read_u32(reader)? as usize;
+        let data = read_bytes(reader, len)?;
+
+        if largest.is_some() {
+            continue;
+        }
+        let data = if lz4 {
+            // An image file of the pixels is rarely larger than they are
+            let max_len = if encoded {
+                (width * height) as usize * 4 + MAX_ENCODED_OVERHEAD
+            } else {
+                format.mipmap_len(width, height)
+            };
+            if decompressed_len > max_len {
+                return Err(anyhow::anyhow!(
+                    "{}x{} mipmap of {} bytes",
+                    width,
+                    height,
+                    decompressed_len
+                ));
+            }
+            lz4_flex::block::decompress(&data, decompressed_len)?
+        } else {
+            data
+        };
+
+        largest = Some(if encoded {
+            image::load_from_memory(&data)?.into_rgba8()
+        } else {
+            decode_pixels(&data, format, width, height)?
+        });
+    }
+
+    largest.ok_or_else(|| anyhow::anyhow!("No mipmaps"))
+}
+
+/// Cuts the frames out of the sprite sheets in `images`, following the `TEXS` table.
+fn read_sprite_frames(
+    reader: &mut Cursor<&[u8]>,
+    images: &[RgbaImage],
+) -> Result<Vec<Frame>, anyhow::Error> {
+    let version = expect_magic(reader, TEXS)?;
+    let count = read_u32(reader)?;
+    if count == 0 || count > MAX_FRAMES {
+        return Err(anyhow::anyhow!("{} frames", count));
+    }
+    if version >= 3 {
+        // Size of the animation, the same as the frames'
+        let _width = read_u32(reader)?;
+        let _height = read_u32(reader)?;
+    }
+
+    (0..count)
+        .map(|_| {
+            let image_id = read_u32(reader)? as usize;
+            let frametime = read_f32(reader)?;
+            // Integers in the first version, floats since
+            let mut rect = [0.; 6];
+            for value in &mut rect {
+                *value = if version == 1 {
+                    read_u32(reader)? as i32 as f32
+                } else {
+                    read_f32(reader)?
+                };
+            }
+            let [x, y, width, _width_y, _height_x, height] = rect;
+
+            let sheet = images
+                .get(image_id)
+                .ok_or_else(|| anyhow::anyhow!("Frame of missing image {}", image_id))?;
+            let (x, y) = (x.max(0.) as u32, y.max(0.) as u32);
+            // Negative when flipped
+            let (width, height) = (width.abs() as u32, height.abs() as u32);
+            let inside = x.saturating_add(width) <= sheet.width()
+                && y.saturating_add(height) <= sheet.height();
+            if !inside || width == 0 || height == 0 {
+                return Err(anyhow::anyhow!("Frame outside of its sprite sheet"));
+            }
+
+            Ok(Frame {
+                image: imageops::crop_imm(sheet, x, y, width, height).to_image(),
+                // Also for NaN or negative times
+                duration: Duration::try_from_secs_f32(frametime.min(MAX_FRAMETIME))
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn decode_pixels(
+    data: &[u8],
+    format: Format,
+    width: u32,
+    height: u32,
+) -> Result<RgbaImage, anyhow::Error> {
+    let pixels = (width * height) as usize;
+    let truncated = || anyhow::anyhow!("Truncated {:?} mipmap", format);
+
+    let rgba = match format {
+        Format::Rgba8888 => data.get(..pixels * 4).ok_or_else(truncated)?.to_vec(),
+        Format::Rg88 => data
+            .get(..pixels * 2)
+            .ok_or_else(truncated)?
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        Format::R8 => data
+            .get(..pixels)
+            .ok_or_else(truncated)?
+            .iter()
+            .flat_map(|&l| [l, l, l, 0xff])
+            .collect(),
+        Format::Dxt1 | Format::Dxt3 | Format::Dxt5 => {
+            decode_blocks(data, format, width, height).ok_or_else(truncated)?
+        }
+    };
+
+    RgbaImage::from_raw(width, height, rgba).ok_or_else(truncated)
+}
+
+/// Decodes DXT1/3/5 (BC1/2/3) blocks of 4x4 pixels.
+fn decode_blocks(data: &[u8], format: Format, width: u32, height: u32) -> Option<Vec<u8>> {
+    let block_len = if format == Format::Dxt1 { 8 } else { 16 };
+    let (blocks_x, blocks_y) = ((width as usize + 3) / 4, (height as usize + 3) / 4);
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![0; width * height * 4];
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let offset = (by * blocks_x + bx) * block_len;
+            let block = data.get(offset..offset + block_len)?;
+
+            let pixels = match format {
+                Format::Dxt1 => color_block(block, true),
+                Format::Dxt3 => {
+                    let mut pixels = color_block(&block[8..], false);
+                    let alphas = u64::from_le_bytes(block[..8].try_into().ok()?);
+                    for (i, pixel) in pixels.iter_mut().enumerate() {
+                        pixel[3] = ((alphas >> (i * 4)) & 0xf) as u8 * 17;
+                    }
+                    pixels
+                }
+                _ => {
+                    let mut pixels = color_block(&block[8..], false);
+                    let alphas = alpha_block(&block[..8]);
+                    for (pixel, alpha) in pixels.iter_mut().zip(alphas) {
+                        pixel[3] = alpha;
+                    }
+                    pixels
+                }
+            };
+
+            for (i, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (bx * 4 + i % 4, by * 4 + i / 4);
+                // Blocks overhang sizes which aren't multiples of 4
+                if x < width && y < height {
+                    let at = (y * width + x) * 4;
+                    rgba[at..at + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+    Some(rgba)
+}
+
+/// The 16 pixels of a BC1 color block, with 1-bit alpha if `dxt1`
+fn color_block(block: &[u8], dxt1: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| {
+        let channel = |i: usize| ((a[i] as u32 * wa + b[i] as u32 * wb) / (wa + wb)) as u8;
+        [channel(0), channel(1), channel(2), 0xff]
+    };
+    let palette = if c0 > c1 || !dxt1 {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), [0, 0, 0, 0]]
+    };
+
+    std::array::from_fn(|i| palette[((indices >> (i * 2)) & 0b11) as usize])
+}
+
+/// The 16 alphas of a BC3 alpha block
+fn alpha_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let indices = block[2..8]
+        .iter()
+        .rev()
+        .fold(0u64, |bits, &byte| (bits << 8) | byte as u64);
+
+    let palette: [u8; 8] = std::array::from_fn(|i| match i {
+        0 => a0 as u8,
+        1 => a1 as u8,
+        i if a0 > a1 => ((a0 * (8 - i as u32) + a1 * (i as u32 - 1)) / 7) as u8,
+        6 => 0,
+        7 => 0xff,
+        i => ((a0 * (6 - i as u32) + a1 * (i as u32 - 1)) / 5) as u8,
+    });
+
+    std::array::from_fn(|i| palette[((indices >> (i * 3)) & 0b111) as usize])
+}
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let (r, g, b) = ((color >> 11) & 0x1f, (color >> 5) & 0x3f, color & 0x1f);
+    [
+        (r * 255 / 31) as u8,
+        (g * 255 / 63) as u8,
+        (b * 255 / 31) as u8,
+        0xff,
+    ]
+}
+
+fn check_size(width: u32, height: u32) -> Result<(), anyhow::Error> {
+    if width == 0 || height == 0 || width > MAX_SIDE || height > MAX_SIDE {
+        return Err(anyhow::anyhow!("Invalid size {}x{}", width, height));
+    }
+    Ok(())
+}
+
+/// Reads an 8-byte magic like `TEXB0003` and its NUL, returns its version.
+fn expect_magic(reader: &mut Cursor<&[u8]>, magic: &str) -> Result<u32, anyhow::Error> {
+    let bytes = read_bytes(reader, 9)?;
+    let text = std::str::from_utf8(&bytes[..8])
+        .ok()
+        .filter(|text| text.starts_with(magic) && bytes[8] == 0)
+        .ok_or_else(|| anyhow::anyhow!("Expected {}, found {:?}", magic, &bytes[..8]))?;
+
+    text[4..]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid version {}", text))
+}
+
+fn read_bytes(reader: &mut Cursor<&[u8]>, len: usize) -> Result<Vec<u8>, anyhow::Error> {
+    // Checked first, the length comes from the file
+    let remaining = (reader.get_ref().len() as u64).saturating_sub(reader.position());
+    if len as u64 > remaining {
+        return Err(anyhow::anyhow!("Truncated texture"));
+    }
+
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, anyhow::Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32, anyhow::Error> {
+    Ok(f32::from_bits(read_u32(reader)?))
+}
+
+/// `awa tex`
+pub(crate) fn convert(input: &Path, output: &Path) -> Result<(), anyhow::Error> {
+    let tex = Tex::open(input)?;
+    let gif = output
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gif"));
+
+    if gif && tex.is_animated() {
+        let file = std::io::BufWriter::new(std::fs::File::create(output)?);
+        let mut encoder = image::codecs::gif::GifEncoder::new(file);
+        encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+        encoder.encode_frames(tex.frames.into_iter().map(|frame| {
+            let delay = image::Delay::from_saturating_duration(frame.duration);
+            image::Frame::from_parts(frame.image, 0, 0, delay)
+        }))?;
+    } else {
+        tex.first_frame().save(output)?;
+    }
+
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0xf800;
+    const BLUE: u16 = 0x001f;
+
+    #[test]
+    fn expands_rgb565() {
+        assert_eq!(rgb565(0), [0, 0, 0, 0xff]);
+        assert_eq!(rgb565(0xffff), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(rgb565(RED), [0xff, 0, 0, 0xff]);
+        assert_eq!(rgb565(0x07e0), [0, 0xff, 0, 0xff]);
+        assert_eq!(rgb565(BLUE), [0, 0, 0xff, 0xff]);
+    }
+
+    /// A color block of `c0` and `c1` whose pixels use the palette entries 0 to 3 in turn
+    fn color_data(c0: u16, c1: u16) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend(c0.to_le_bytes());
+        block.extend(c1.to_le_bytes());
+        block.extend([0b11_10_01_00; 4]);
+        block
+    }
+
+    #[test]
+    fn decodes_color_blocks() {
+        let four_colors = [
+            [0xff, 0, 0, 0xff],
+            [0, 0, 0xff, 0xff],
+            [170, 0, 85, 0xff],
+            [85, 0, 170, 0xff],
+        ];
+        let pixels = color_block(&color_data(RED, BLUE), true);
+        assert!(pixels.chunks(4).all(|row| row == four_colors));
+
+        // DXT1 has three colors and transparency when c0 <= c1
+        let pixels = color_block(&color_data(BLUE, RED), true);
+        let three_colors = [
+            [0, 0, 0xff, 0xff],
+            [0xff, 0, 0, 0xff],
+            [127, 0, 127, 0xff],
+            [0, 0, 0, 0],
+        ];
+        assert!(pixels.chunks(4).all(|row| row == three_colors));
+
+        // Always four colors in DXT3 and DXT5
+        let pixels = color_block(&color_data(BLUE, RED), false);
+        assert_eq!(pixels[3], [170, 0, 85, 0xff]);
+    }
+
+    /// An alpha block of `a0` and `a1` whose pixels use the palette entries 0 to 7 in turn
+    fn alpha_data(a0: u8, a1: u8) -> Vec<u8> {
+        let indices = (0..16u64).fold(0, |bits, i| bits | (i % 8) << (i * 3));
+        let mut block = vec![a0, a1];
+        block.extend(&indices.to_le_bytes()[..6]);
+        block
+    }
+
+    #[test]
+    fn decodes_alpha_blocks() {
+        let eight_alphas = [255, 0, 218, 182, 145, 109, 72, 36];
+        let alphas = alpha_block(&alpha_data(255, 0));
+        assert_eq!(alphas[..8], eight_alphas);
+        assert_eq!(alphas[8..], eight_alphas);
+
+        // Six interpolated alphas, then 0 and 255
+        let alphas = alpha_block(&alpha_data(0, 255));
+        assert_eq!(alphas[..8], [0, 255, 51, 102, 153, 204, 0, 255]);
+    }
+
+    /// A mipmap of a synthetic texture
+    struct Mipmap {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        /// Claimed decompressed length if compressed with LZ4
+        lz4: Option<u32>,
+    }
+
+    impl Mipmap {
+        fn new(width: u32, height: u32, data: Vec<u8>) -> Self {
+            Self {
+                width,
+                height,
+                data,
+                lz4: None,
+            }
+        }
+
+        fn compressed(mut self) -> Self {
+            self.lz4 = Some(self.data.len() as u32);
+            self.data = lz4_flex::block::compress(&self.data);
+            self
+        }
+    }
+
+    /// A `TEXB0002` texture of one image of `width`x`height`
+    fn texture(format: u32, width: u32, height: u32, mipmaps: &[Mipmap]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let u32s = |data: &mut Vec<u8>, values: &[u32]| {
+            for value in values {
+                data.extend(value.to_le_bytes());
+            }
+        };
+
+        data.extend(b"TEXV0005\0TEXI0001\0");
+        // Format, flags, texture size, image size, unknown
+        u32s(&mut data, &[format, 0, width, height, width, height, 0]);
+        data.extend(b"TEXB0002\0");
+        u32s(&mut data, &[1, mipmaps.len() as u32]);
+        for mipmap in mipmaps {
+            let (lz4, decompressed_len) = mipmap.lz4.map_or((0, 0), |len| (1, len));
+            u32s(
+                &mut data,
+                &[
+                    mipmap.width,
+                    mipmap.height,
+                    lz4,
+                    decompressed_len,
+                    mipmap.data.len() as u32,
+                ],
+            );
+            data.extend(&mipmap.data);
+        }
+        data
+    }
+
+    /// `width`x`height` RGBA pixels, each with its index in every channel but alpha
+    fn indexed_pixels(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| [i as u8, i as u8, i as u8, 0xff])
+            .collect()
+    }
+
+    #[test]
+    fn decodes_rgba_textures() {
+        // Padded to a power of two, with a smaller mipmap which is skipped
+        let mipmaps = [
+            Mipmap::new(4, 2, indexed_pixels(4, 2)),
+            Mipmap::new(2, 1, indexed_pixels(2, 1)),
+        ];
+        let mut data = texture(0, 4, 2, &mipmaps);
+        // Image width, after the magics, format, flags and texture size
+        data[34..38].copy_from_slice(&3u32.to_le_bytes());
+
+        let tex = Tex::decode(&data).unwrap();
+        assert!(!tex.is_animated());
+        let image = tex.first_frame();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(2, 0).0, [2, 2, 2, 0xff]);
+        assert_eq!(image.get_pixel(2, 1).0, [6, 6, 6, 0xff]);
+    }
+
+    #[test]
+    fn decodes_lz4_mipmaps() {
+        let pixels = indexed_pixels(4, 4);
+        let data = texture(0, 4, 4, &[Mipmap::new(4, 4, pixels.clone()).compressed()]);
+
+        let tex = Tex::decode(&data).unwrap();
+        assert_eq!(tex.first_frame().as_raw(), &pixels);
+    }
+
+    #[test]
+    fn bounds_lz4_mipmaps_by_their_size() {
+        let mut mipmap = Mipmap::new(4, 4, indexed_pixels(4, 4)).compressed();
+        mipmap.lz4 = Some(1 << 30);
+
+        assert!(Tex::decode(&texture(0, 4, 4, &[mipmap])).is_err());
+    }
+
+    #[test]
+    fn decodes_dxt1_textures() {
+        // 8x4 in two blocks, red then blue
+        let mut blocks = color_data(RED, BLUE);
+        blocks[4..8].fill(0);
+        blocks.extend(color_data(BLUE, 0));
+        blocks[12..16].fill(0);
+
+        let tex = Tex::decode(&texture(7, 8, 4, &[Mipmap::new(8, 4, blocks)])).unwrap();
+        let image = tex.first_frame();
+        assert_eq!(image.get_pixel(3, 3).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(image.get_pixel(4, 0).0, [0, 0, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn rejects_invalid_textures() {
+        let data = texture(0, 4, 4, &[Mipmap::new(4, 4, indexed_pixels(4, 4))]);
+        assert!(Tex::decode(&data[..data.len() - 1]).is_err());
+        assert!(Tex::decode(&data[1..]).is_err());
+
+        // Unknown format
+        assert!(Tex::decode(&texture(5, 4, 4, &[Mipmap::new(4, 4, vec![0; 64])])).is_err());
+        // Too large
+        let huge = Mipmap::new(MAX_SIDE + 1, 1, Vec::new());
+        assert!(Tex::decode(&texture(0, 4, 4, &[huge])).is_err());
+    }
+
+    fn frame(value: u8, duration: Duration) -> Frame {
+        Frame {
+            image: RgbaImage::from_pixel(1, 1, image::Rgba([value; 4])),
+            duration,
+        }
+    }
+
+    #[test]
+    fn loops_over_the_frames() {
+        let tex = Tex {
+            frames: vec![
+                frame(0, Duration::from_millis(100)),
+                frame(1, Duration::from_millis(200)),
+            ],
+        };
+        let value_at = |ms| tex.frame_at(Duration::from_millis(ms)).get_pixel(0, 0).0[0];
+
+        assert_eq!(value_at(0), 0);
+        assert_eq!(value_at(99), 0);
+        assert_eq!(value_at(100), 1);
+        assert_eq!(value_at(299), 1);
+        assert_eq!(value_at(300), 0);
+        assert_eq!(value_at(3150), 1);
+    }
+
+    #[test]
+    fn survives_huge_frametimes() {
+        let tex = Tex {
+            frames: vec![frame(0, Duration::MAX), frame(1, Duration::MAX)],
+        };
+        assert_eq!(tex.frame_at(Duration::from_secs(1)).get_pixel(0, 0).0[0], 0);
+
+        // Sprite sheet of one 1x1 image with a frame claiming forever
+        let mut data = texture(0, 1, 1, &[Mipmap::new(1, 1, indexed_pixels(1, 1))]);
+        data[22..26].copy_from_slice(&FLAG_SPRITE_SHEET.to_le_bytes());
+        data.extend(b"TEXS0002\0");
+        data.extend(1u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(f32::INFINITY.to_le_bytes());
+        for value in [0., 0., 1., 0., 0., 1.] {
+            data.extend(f32::to_le_bytes(value));
+        }
+
+        let tex = Tex::decode(&data).unwrap();
+        assert!(tex.frames[0].duration <= Duration::from_secs_f32(MAX_FRAMETIME));
+    }
+}
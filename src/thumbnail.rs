@@ -0,0 +1,394 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use gst::prelude::*;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops, Delay, Frame, RgbaImage,
+};
+
+use crate::{cache, paths};
+
+const TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(10);
+
+/// Frames of the animated preview, grabbed at evenly spaced percentages of the clip
+const PREVIEW_FRAMES: u64 = 12;
+const PREVIEW_DELAY_MS: u32 = 250;
+
+/// Sizes of the freedesktop `normal` and `large` thumbnails, which fit in a square of this side
+const NORMAL_SIZE: u32 = 128;
+const LARGE_SIZE: u32 = 256;
+
+/// Bytes hashed at each end of a file to name its thumbnails
+const HASHED_LEN: u64 = 1 << 20;
+
+/// Where the thumbnails of one file are
+#[derive(Debug, Clone)]
+pub(crate) struct Thumbnails {
+    pub(crate) normal: PathBuf,
+    pub(crate) large: PathBuf,
+    /// Animated GIF
+    pub(crate) preview: PathBuf,
+}
+
+impl Thumbnails {
+    /// Thumbnails of the file at `path`, named by a hash of its size and both of its ends
+    fn of(path: &Path) -> Result<Self, anyhow::Error> {
+        let name = format!("{:016x}", cache::hash_ends(path, HASHED_LEN)?);
+        Ok(Self::named(&thumbs_dir(), &name))
+    }
+
+    fn named(dir: &Path, name: &str) -> Self {
+        Self {
+            normal: dir.join("normal").join(format!("{}.png", name)),
+            large: dir.join("large").join(format!("{}.png", name)),
+            preview: dir.join("preview").join(format!("{}.gif", name)),
+        }
+    }
+
+    fn exist(&self) -> bool {
+        self.normal.is_file() && self.large.is_file() && self.preview.is_file()
+    }
+}
+
+/// `$XDG_CACHE_HOME/awa/thumbs`, laid out like the freedesktop thumbnail cache but with files
+/// named by a hash of their content instead of their URI, so that moved clips keep them.
+#[inline]
+pub(crate) fn thumbs_dir() -> PathBuf {
+    paths::cache_dir().join("thumbs")
+}
+
+/// Generates the thumbnails of the file at `path`, unless they exist already and not `force`.
+pub(crate) fn generate(path: &Path, force: bool) -> Result<Thumbnails, anyhow::Error> {
+    let thumbnails = Thumbnails::of(path)?;
+    if thumbnails.exist() && !force {
+        return Ok(thumbnails);
+    }
+
+    let uri = paths::path_to_uri(path)?;
+    let frames = grab_frames(&uri)?
+        .into_iter()
+        .map(|frame| fit(&frame, LARGE_SIZE))
+        .collect::<Vec<_>>();
+
+    // Fades and title cards make for poor thumbnails
+    let representative = frames
+        .iter()
+        .max_by(|a, b| luma_variance(a).total_cmp(&luma_variance(b)))
+        .ok_or_else(|| anyhow::anyhow!("No frames in {}", uri))?;
+
+    let metadata = std::fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |mtime| mtime.as_secs());
+    let text = [
+        ("Thumb::URI", uri.clone()),
+        ("Thumb::MTime", mtime.to_string()),
+        ("Thumb::Size", metadata.len().to_string()),
+        ("Software", "awa".to_owned()),
+    ];
+
+    write_png(&thumbnails.large, representative, &text)?;
+    write_png(&thumbnails.normal, &fit(representative, NORMAL_SIZE), &text)?;
+    write_preview(&thumbnails.preview, frames)?;
+
+    Ok(thumbnails)
+}
+
+/// Frames at `PREVIEW_FRAMES` evenly spaced percentages of the media at `uri`, or its only frame
+/// if it has no duration, e.g. an image.
+fn grab_frames(uri: &str) -> Result<Vec<RgbaImage>, anyhow::Error> {
+    gst::init()?;
+
+    // playbin uri={uri} flags=video video-sink=fakesink audio-sink=fakesink
+    let playbin = gst::ElementFactory::make("playbin")
+        .property("uri", uri)
+        .property("video-sink", gst::ElementFactory::make("fakesink").build()?)
+        .property("audio-sink", gst::ElementFactory::make("fakesink").build()?)
+        .build()?;
+    playbin.set_property_from_str("flags", "video");
+
+    let result = grab_frames_from(&playbin);
+    let _ = playbin.set_state(gst::State::Null);
+    result.map_err(|e| match pop_error(&playbin) {
+        Some(error) => error.context(e),
+        None => e,
+    })
+}
+
+fn grab_frames_from(playbin: &gst::Element) -> Result<Vec<RgbaImage>, anyhow::Error> {
+    playbin.set_state(gst::State::Paused)?;
+    playbin.state(TIMEOUT).0?;
+
+    let Some(duration) = playbin.query_duration::<gst::ClockTime>() else {
+        return Ok(vec![current_frame(playbin)?]);
+    };
+
+    (0..PREVIEW_FRAMES)
+        .map(|i| {
+            // In the middle of each of the `PREVIEW_FRAMES` parts
+            let position = duration.mul_div_floor(2 * i + 1, 2 * PREVIEW_FRAMES);
+            playbin.seek_simple(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+                position.unwrap_or(gst::ClockTime::ZERO),
+            )?;
+            playbin.state(TIMEOUT).0?;
+            current_frame(playbin)
+        })
+        .collect()
+}
+
+/// The prerolled frame, converted with playbin's `convert-sample`
+fn current_frame(playbin: &gst::Element) -> Result<RgbaImage, anyhow::Error> {
+    let caps = gst_video::VideoCapsBuilder::new()
+        .format(gst_video::VideoFormat::Rgba)
+        .pixel_aspect_ratio(gst::Fraction::new(1, 1))
+        .build();
+    let sample = playbin
+        .emit_by_name::<Option<gst::Sample>>("convert-sample", &[&caps])
+        .ok_or_else(|| anyhow::anyhow!("No video frame"))?;
+
+    let info = sample
+        .caps()
+        .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+        .ok_or_else(|| anyhow::anyhow!("Failed to get video info from sample"))?;
+    let buffer = sample
+        .buffer()
+        .ok_or_else(|| anyhow::anyhow!("Sample has no buffer"))?
+        .map_readable()?;
+
+    // Rows may be padded
+    let stride = info.stride()[0] as usize;
+    let row = info.width() as usize * 4;
+    let pixels = buffer
+        .chunks(stride)
+        .take(info.height() as usize)
+        .flat_map(|line| line.iter().take(row))
+        .copied()
+        .collect();
+
+    RgbaImage::from_raw(info.width(), info.height(), pixels)
+        .ok_or_else(|| anyhow::anyhow!("Truncated video frame"))
+}
+
+fn pop_error(playbin: &gst::Element) -> Option<anyhow::Error> {
+    playbin
+        .bus()?
+        .pop_filtered(&[gst::MessageType::Error])
+        .and_then(|msg| match msg.view() {
+            gst::MessageView::Error(e) => Some(anyhow::anyhow!("{} ({:?})", e.error(), e.debug())),
+            _ => None,
+        })
+}
+
+/// Scales `image` down to fit in a square of `side`, keeping its aspect ratio.
+fn fit(image: &RgbaImage, side: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width <= side && height <= side {
+        return image.clone();
+    }
+
+    let scale = side as f64 / width.max(height) as f64;
+    imageops::thumbnail(
+        image,
+        ((width as f64 * scale) as u32).max(1),
+        ((height as f64 * scale) as u32).max(1),
+    )
+}
+
+/// How much detail `image` has, near 0 for a solid frame
+fn luma_variance(image: &RgbaImage) -> f64 {
+    let count = (image.width() * image.height()).max(1) as f64;
+    let (sum, sum_squares) = image.pixels().fold((0., 0.), |(sum, sum_squares), pixel| {
+        let [r, g, b, _] = pixel.0;
+        let luma = 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+        (sum + luma, sum_squares + luma * luma)
+    });
+
+    let mean = sum / count;
+    sum_squares / count - mean * mean
+}
+
+/// Writes a PNG with freedesktop `tEXt` keys.
+fn write_png(path: &Path, image: &RgbaImage, text: &[(&str, String)]) -> Result<(), anyhow::Error> {
+    write_atomically(path, |file| {
+        let mut encoder = png::Encoder::new(file, image.width(), image.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, value) in text {
+            encoder.add_text_chunk(keyword.to_string(), value.clone())?;
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(image.as_raw())?;
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+fn write_preview(path: &Path, frames: Vec<RgbaImage>) -> Result<(), anyhow::Error> {
+    write_atomically(path, |file| {
+        let mut encoder = GifEncoder::new_with_speed(file, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.into_iter().map(|frame| {
+            Frame::from_parts(frame, 0, 0, Delay::from_numer_denom_ms(PREVIEW_DELAY_MS, 1))
+        }))?;
+        Ok(())
+    })
+}
+
+/// Writes `path` through a temporary file, so that it is never seen half written.
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut tmp = path.to_owned().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    // Flushed here, dropping the writer would swallow the errors of a truncated file
+    let result = write(&mut file).and_then(|()| Ok(file.flush()?));
+    match result {
+        Ok(()) => {
+            std::fs::rename(&tmp, path)?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// `awa thumbnail`
+pub(crate) fn print(files: &[PathBuf], force: bool) -> Result<(), anyhow::Error> {
+    for file in files {
+        let thumbnails = generate(file, force)
+            .map_err(|e| e.context(format!("Failed to thumbnail {}", file.display())))?;
+
+        println!("{}", file.display());
+        println!("    {}", thumbnails.large.display());
+        println!("    {}", thumbnails.normal.display());
+        println!("    {}", thumbnails.preview.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(path: &Path) -> PathBuf {
+        Thumbnails::of(path).unwrap().preview
+    }
+
+    #[test]
+    fn names_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+
+        let len = HASHED_LEN as usize;
+        let clip = vec![1; len * 3];
+        let original = write("clip.mp4", &clip);
+        assert_eq!(name(&original), name(&write("moved.mp4", &clip)));
+
+        let mut end = clip.clone();
+        *end.last_mut().unwrap() = 2;
+        assert_ne!(name(&original), name(&write("end.mp4", &end)));
+
+        let mut longer = clip.clone();
+        longer.push(1);
+        assert_ne!(name(&original), name(&write("longer.mp4", &longer)));
+
+        // Only the ends are read
+        let mut middle = clip;
+        middle[len + len / 2] = 2;
+        assert_eq!(name(&original), name(&write("middle.mp4", &middle)));
+
+        // Smaller than the hashed ends
+        assert_ne!(name(&write("a.gif", b"a")), name(&write("b.gif", b"b")));
+    }
+
+    #[test]
+    fn lays_out_like_freedesktop() {
+        let thumbnails = Thumbnails::named(Path::new("/thumbs"), "0123");
+        assert_eq!(thumbnails.normal, Path::new("/thumbs/normal/0123.png"));
+        assert_eq!(thumbnails.large, Path::new("/thumbs/large/0123.png"));
+        assert_eq!(thumbnails.preview, Path::new("/thumbs/preview/0123.gif"));
+    }
+
+    #[test]
+    fn fits_in_a_square() {
+        let image = RgbaImage::new(512, 128);
+        assert_eq!(fit(&image, LARGE_SIZE).dimensions(), (256, 64));
+        assert_eq!(fit(&image, 1024).dimensions(), (512, 128));
+        assert_eq!(fit(&RgbaImage::new(1000, 1), 100).dimensions(), (100, 1));
+    }
+
+    #[test]
+    fn prefers_detailed_frames() {
+        let solid = RgbaImage::from_pixel(4, 4, image::Rgba([90, 90, 90, 255]));
+        assert!(luma_variance(&solid).abs() < 1e-6);
+
+        let checkered = RgbaImage::from_fn(4, 4, |x, y| {
+            let value = if (x + y) % 2 == 0 { 0 } else { 255 };
+            image::Rgba([value, value, value, 255])
+        });
+        assert!(luma_variance(&checkered) > luma_variance(&solid));
+        assert_eq!(luma_variance(&RgbaImage::new(0, 0)), 0.);
+    }
+
+    #[test]
+    fn writes_freedesktop_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("normal").join("clip.png");
+        let image = RgbaImage::from_pixel(3, 2, image::Rgba([1, 2, 3, 255]));
+        write_png(
+            &path,
+            &image,
+            &[("Thumb::URI", "file:///clip.mp4".to_owned())],
+        )
+        .unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert!(info
+            .uncompressed_latin1_text
+            .iter()
+            .any(|chunk| chunk.keyword == "Thumb::URI" && chunk.text == "file:///clip.mp4"));
+
+        // Not left behind
+        assert!(!dir.path().join("normal").join("clip.png.tmp").exists());
+    }
+
+    #[test]
+    fn keeps_the_previous_file_on_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("preview.gif");
+        std::fs::write(&path, b"previous").unwrap();
+
+        let result = write_atomically(&path, |file| {
+            file.write_all(b"half")?;
+            Err(anyhow::anyhow!("Failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"previous");
+        assert!(!dir.path().join("preview.gif.tmp").exists());
+    }
+}
@@ -1,43 +1,151 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use cfg_if::cfg_if;
 use gst::{element_error, prelude::*, ElementFactory, Fraction, GhostPad};
 use gst_app::AppSinkCallbacks;
 use gst_video::VideoCapsBuilder;
 
 use winit::dpi::PhysicalSize;
 
+use crate::{
+    audio::Audio,
+    config::Config,
+    decoder,
+    library::Settings,
+    reactive::{self, Analysis, Energies},
+    source::Source,
+};
+
+mod loop_cache;
+mod policy;
+
+use loop_cache::{Loop, Recorder, Replay};
+use policy::Plan;
+
+/// Frames per second coming out of the pipeline, unless throttled
+const FRAMERATE: i32 = 30;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum VideoError {
+    /// The pipeline can be rebuilt after disabling `factory`
+    #[error("Hardware element {factory} failed: {message}")]
+    Hardware { factory: String, message: String },
+}
+
 pub(crate) struct Video {
     pipeline: gst::Pipeline,
 
-    #[allow(dead_code)]
     appsink: gst_app::AppSink,
     bus: Arc<gst::Bus>,
 
     surface_size: PhysicalSize<u32>,
+    /// Size of the decoded frames, see `policy::plan`
+    frame_size: PhysicalSize<u32>,
     repeat: bool,
 
-    #[allow(dead_code)]
+    /// Of the frames coming out of the pipeline, see `set_max_framerate`
     framerate: f64,
+    /// Playback speed, 1 for normal
+    rate: f64,
+    /// Paused by `set_paused`
+    paused: bool,
 
     need_render: Arc<AtomicBool>,
 
+    /// Factory name of the video decoder playbin picked
+    decoder: Arc<Mutex<Option<String>>>,
+
     frame_rx: tokio::sync::watch::Receiver<Option<Vec<u8>>>,
+
+    /// Recording the first loop, `None` if `config.loop_cache` isn't set
+    recorder: Option<Arc<Mutex<Recorder>>>,
+    /// The first loop, once it played through and fit in memory
+    frames: Option<Loop>,
+    /// Playing `frames` while the pipeline is paused
+    replay: Option<Replay>,
+
+    /// `None` when playing without sound
+    audio: Option<Audio>,
+    muted: bool,
+    /// Fed when `config.reactive` is set
+    analysis: Analysis,
 }
 
 impl Drop for Video {
     fn drop(&mut self) {
-        self.pipeline.set_state(gst::State::Null).unwrap();
+        let _ = self.pipeline.set_state(gst::State::Null);
     }
 }
 
 //unsafe impl Send for Video {}
 
+impl Source for Video {
+    #[inline]
+    fn update(&mut self) -> Result<(), anyhow::Error> {
+        Video::update(self)
+    }
+
+    #[inline]
+    fn render(&mut self, frame: &mut [u8]) -> bool {
+        Video::render(self, frame)
+    }
+
+    #[inline]
+    fn frame_size(&self) -> PhysicalSize<u32> {
+        self.frame_size
+    }
+
+    #[inline]
+    fn set_paused(&mut self, paused: bool) -> Result<(), anyhow::Error> {
+        Video::set_paused(self, paused)
+    }
+
+    #[inline]
+    fn set_muted(&mut self, muted: bool) {
+        Video::set_muted(self, muted)
+    }
+
+    #[inline]
+    fn set_max_framerate(&mut self, framerate: Option<f64>) -> Result<(), anyhow::Error> {
+        Video::set_max_framerate(self, framerate)
+    }
+
+    #[inline]
+    fn update_surface_size(&mut self, size: PhysicalSize<u32>) -> Result<(), anyhow::Error> {
+        Video::update_surface_size(self, size)
+    }
+
+    #[inline]
+    fn fade_out(&self) -> Duration {
+        Video::fade_out(self)
+    }
+
+    #[inline]
+    fn energies(&self) -> Option<Energies> {
+        self.analysis.energies()
+    }
+
+    #[inline]
+    fn decoder(&self) -> Option<String> {
+        Video::decoder(self)
+    }
+}
+
 impl Video {
-    pub(crate) fn new<S>(size: S) -> Result<Self, anyhow::Error>
+    /// Plays `uri` at `size` with its library `settings`, with hardware decoders over the
+    /// configured ones if `favor_hardware`.
+    pub(crate) fn new<S>(
+        size: S,
+        uri: &str,
+        config: &Config,
+        favor_hardware: bool,
+        settings: Settings,
+    ) -> Result<Self, anyhow::Error>
     where
         S: Into<PhysicalSize<u32>>,
     {
@@ -45,44 +153,66 @@ impl Video {
 
         let size = size.into();
 
-        cfg_if! {
-            if #[cfg(target_os = "macos")] {
-                Self::enable_factory("vtdec", true);
-
-            } else if #[cfg(target_os = "windows")] {
-                Self::enable_factory("d3d11h264dec", true);
-                Self::enable_factory("d3d11h265dec", true);
-                Self::enable_factory("d3d11vp8dec", true);
-                Self::enable_factory("d3d11vp9dec", true);
-                Self::enable_factory("d3d11mpeg2dec", true);
-                Self::enable_factory("d3d11av1dec", true);
-
-            } else if #[cfg(target_os = "linux")] {
-                Self::enable_factory("vaapih264dec", true);
-                Self::enable_factory("vaapivp8dec", true);
-                Self::enable_factory("vaapivp9dec", true);
-                Self::enable_factory("vaapijpegdec", true);
-                Self::enable_factory("vaapimpeg2dec", true);
-                Self::enable_factory("vaapimpeg4dec", true);
-                Self::enable_factory("vaapih265dec", true);
-                Self::enable_factory("vaapivc1dec", true);
-                Self::enable_factory("vaapiav1dec", true);
-                Self::enable_factory("vaapipostproc", true);
+        decoder::setup(&config.decoders, favor_hardware);
+        let rate = settings.rate.unwrap_or(1.);
+        let plan = policy::plan(
+            uri,
+            size,
+            &config.decode,
+            settings.position.unwrap_or_default(),
+        );
+
+        let audio = match Audio::new(&config.audio) {
+            Ok(audio) => Some(audio),
+            Err(e) => {
+                eprintln!("Error opening audio output, playing without sound: {:#}", e);
+                None
             }
-        }
+        };
+        let audiosink = match &audio {
+            Some(audio) => audio.sink()?,
+            None => ElementFactory::make("fakesink")
+                .property("sync", true)
+                .build()?,
+        };
+
+        let audiosink = if config.reactive.is_some() {
+            reactive::analysis_sink(audiosink)?
+        } else {
+            audiosink
+        };
 
-        let (pipeline, pad, appsink) = Self::create_pipeline(
-            "https://gstreamer.freedesktop.org/media/sintel_trailer-480p.webm",
-            size,
-        )
-        .unwrap();
-        let bus = pipeline.bus().unwrap();
+        let (pipeline, pad, appsink) = Self::create_pipeline(&plan, &audiosink)?;
+
+        let decoder = Arc::new(Mutex::new(None));
+        let decoder_ref = decoder.clone();
+        let frame_size = plan.size;
+        pipeline.connect_deep_element_added(move |_, _, element| {
+            if decoder::is_video_decoder(element) {
+                *decoder_ref.lock().unwrap() = element.factory().map(|f| f.name().to_string());
+            }
+
+            // Adaptive demuxers (HLS, DASH) then pick the variant stream closest to the frames
+            if element.find_property("max-video-width").is_some() {
+                element.set_property("max-video-width", frame_size.width);
+                element.set_property("max-video-height", frame_size.height);
+            }
+        });
+        let bus = pipeline
+            .bus()
+            .ok_or_else(|| anyhow::anyhow!("Pipeline has no bus"))?;
 
         let (frame_tx, frame_rx) = tokio::sync::watch::channel(None);
 
         let need_render = Arc::new(AtomicBool::new(false));
         let need_render_ref = need_render.clone();
 
+        let recorder = config
+            .loop_cache
+            .as_ref()
+            .map(|config| Arc::new(Mutex::new(Recorder::new(config))));
+        let recorder_ref = recorder.clone();
+
         appsink.set_callbacks(
             AppSinkCallbacks::builder()
                 .new_sample(move |appsink| {
@@ -111,9 +241,13 @@ impl Video {
                     buf.copy_to_slice(0, &mut f).unwrap();
                     */
 
-                    frame_tx
-                        .send(Some(buf.map_readable().unwrap().to_vec()))
-                        .unwrap();
+                    let data = buf.map_readable().unwrap().to_vec();
+                    if let Some(recorder) = &recorder_ref {
+                        recorder.lock().unwrap().record(data.clone());
+                    }
+
+                    // Fails only if the `Video` is already gone
+                    let _ = frame_tx.send(Some(data));
 
                     need_render_ref.store(true, Ordering::Release);
 
@@ -122,32 +256,141 @@ impl Video {
                 .build(),
         );
 
-        pipeline.set_state(gst::State::Playing)?;
-        pipeline.state(gst::ClockTime::from_seconds(5)).0?;
-
-        let caps = pad.current_caps().unwrap();
-
-        let s = caps.structure(0).unwrap();
-        let framerate = s.get::<gst::Fraction>("framerate").unwrap();
-        Ok(Self {
+        // Constructed before starting so that `Drop` stops the pipeline if starting fails
+        let mut video = Self {
             pipeline,
             appsink,
             bus: Arc::new(bus),
             surface_size: size,
+            frame_size,
             frame_rx,
+            recorder,
+            frames: None,
+            replay: None,
             repeat: true,
             need_render,
-            framerate: framerate.numer() as f64 / framerate.denom() as f64,
-        })
+            decoder,
+            muted: config.audio.muted,
+            audio,
+            analysis: Analysis::default(),
+            framerate: 0.,
+            rate,
+            paused: false,
+        };
+
+        let started = video
+            .pipeline
+            .set_state(gst::State::Playing)
+            .and_then(|_| video.pipeline.state(gst::ClockTime::from_seconds(5)).0);
+        if let Err(e) = started {
+            // The bus usually knows better than the state change
+            return Err(video.pop_error().unwrap_or_else(|| e.into()));
+        }
+
+        let caps = pad
+            .current_caps()
+            .ok_or_else(|| anyhow::anyhow!("Video sink has no negotiated caps"))?;
+
+        let s = caps
+            .structure(0)
+            .ok_or_else(|| anyhow::anyhow!("Video sink caps are empty"))?;
+        let framerate = s.get::<gst::Fraction>("framerate")?;
+        video.framerate = framerate.numer() as f64 / framerate.denom() as f64;
+
+        if rate != 1. {
+            video.rewind()?;
+        }
+
+        Ok(video)
     }
 
+    /// Seeks back to the start, at `rate`.
     #[inline]
     pub(crate) fn rewind(&mut self) -> Result<(), anyhow::Error> {
         self.pipeline
-            .seek_simple(gst::SeekFlags::FLUSH, gst::ClockTime::from_seconds(0))
+            .seek(
+                self.rate,
+                gst::SeekFlags::FLUSH,
+                gst::SeekType::Set,
+                gst::ClockTime::ZERO,
+                gst::SeekType::None,
+                gst::ClockTime::NONE,
+            )
             .map_err(anyhow::Error::from)
     }
 
+    /// Pauses the pipeline, which stops decoding along with the audio.
+    pub(crate) fn set_paused(&mut self, paused: bool) -> Result<(), anyhow::Error> {
+        self.paused = paused;
+
+        // The pipeline stays paused while replaying
+        if let Some(replay) = &mut self.replay {
+            replay.set_paused(paused, Instant::now());
+            return Ok(());
+        }
+
+        let state = if paused {
+            gst::State::Paused
+        } else {
+            gst::State::Playing
+        };
+        self.pipeline.set_state(state)?;
+        Ok(())
+    }
+
+    pub(crate) fn set_muted(&mut self, muted: bool) {
+        if let Some(audio) = &self.audio {
+            audio.set_muted(muted);
+        }
+        self.muted = muted;
+
+        // The audio only comes from the pipeline
+        if self.audible() {
+            if self.replay.take().is_some() {
+                if let Err(e) = self.resume_pipeline() {
+                    eprintln!("Error resuming {} after replaying: {:#}", self.uri(), e);
+                }
+            }
+        }
+    }
+
+    /// Whether the clip's audio is heard, which replaying from memory would cut
+    fn audible(&self) -> bool {
+        !self.muted && self.audio.is_some() && self.pipeline.property::<i32>("n-audio") > 0
+    }
+
+    fn uri(&self) -> String {
+        self.pipeline
+            .property::<Option<String>>("current-uri")
+            .unwrap_or_default()
+    }
+
+    /// Plays the pipeline from the start again after replaying from memory.
+    fn resume_pipeline(&mut self) -> Result<(), anyhow::Error> {
+        self.rewind()?;
+        self.set_paused(self.paused)
+    }
+
+    /// At the end of each loop: replays it from memory if it was recorded and nothing is heard,
+    /// rewinds the pipeline otherwise.
+    fn end_of_loop(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(recorder) = self.recorder.take() {
+            self.frames = recorder.lock().unwrap().finish(self.framerate);
+        }
+
+        if self.frames.is_some() && !self.audible() {
+            // Stays on its last frame, ready to resume if the audio is unmuted
+            self.pipeline.set_state(gst::State::Paused)?;
+            let now = Instant::now();
+            let mut replay = Replay::new(now);
+            replay.set_paused(self.paused, now);
+            self.replay = Some(replay);
+            Ok(())
+        } else {
+            self.rewind()
+        }
+    }
+
     pub(crate) fn update(&mut self) -> Result<(), anyhow::Error> {
         use gst::MessageView::*;
 
@@ -158,23 +401,79 @@ impl Video {
                 Eos(_eos) => {
                     // TODO(l3nemy): Handle EOS appropriately
                     if self.repeat {
-                        self.rewind()?;
+                        self.end_of_loop()?;
                     }
                     Ok(())
                 }
-                // TODO(l3nemy): Handle error(Connection closed)
-                Error(e) => Err(anyhow::anyhow!(
-                    "Error from {:?}: {} ({:?})",
-                    e.src().map(|s| s.path_string()),
-                    e.error(),
-                    e.debug()
-                )),
+                // Recovered from by the caller, see `Recovery`
+                Error(e) => Err(self.classify_error(e)),
+                Element(element) => {
+                    self.analysis.handle_message(element);
+                    Ok(())
+                }
                 _ => Ok(()),
             }
         })
     }
 
-    pub(crate) fn render(&self, frame: &mut [u8]) -> bool {
+    fn pop_error(&self) -> Option<anyhow::Error> {
+        self.bus
+            .pop_filtered(&[gst::MessageType::Error])
+            .and_then(|msg| match msg.view() {
+                gst::MessageView::Error(e) => Some(self.classify_error(e)),
+                _ => None,
+            })
+    }
+
+    /// Blames a hardware element for the error if possible, so that it can be disabled.
+    fn classify_error(&self, e: &gst::message::Error) -> anyhow::Error {
+        let message = format!(
+            "Error from {:?}: {} ({:?})",
+            e.src().map(|s| s.path_string()),
+            e.error(),
+            e.debug()
+        );
+
+        let src_factory = e
+            .src()
+            .and_then(|src| src.downcast_ref::<gst::Element>())
+            .and_then(|element| element.factory())
+            .map(|factory| factory.name().to_string());
+
+        let negotiation = e.error().matches(gst::CoreError::Negotiation)
+            || e.error().matches(gst::StreamError::Decode)
+            || e.debug()
+                .map_or(false, |debug| debug.contains("not-negotiated"));
+
+        let factory = match src_factory {
+            Some(factory) if decoder::is_hardware(&factory) => Some(factory),
+            _ if negotiation => self.decoder().filter(|d| decoder::is_hardware(d)),
+            _ => None,
+        };
+
+        match factory {
+            Some(factory) => VideoError::Hardware { factory, message }.into(),
+            None => anyhow::anyhow!(message),
+        }
+    }
+
+    /// Starts fading the audio out, returns how long it takes.
+    #[inline]
+    pub(crate) fn fade_out(&self) -> Duration {
+        self.audio.as_ref().map_or(Duration::ZERO, Audio::fade_out)
+    }
+
+    /// Factory name of the video decoder currently in use
+    #[inline]
+    pub(crate) fn decoder(&self) -> Option<String> {
+        self.decoder.lock().unwrap().clone()
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut [u8]) -> bool {
+        if let (Some(replay), Some(frames)) = (&mut self.replay, &self.frames) {
+            return replay.render(frames, Instant::now(), frame);
+        }
+
         if self.need_render() {
             self.need_render.store(false, Ordering::Release);
 
@@ -203,51 +502,66 @@ impl Video {
         self.need_render.load(Ordering::Acquire)
     }
 
-    fn enable_factory(name: &str, enable: bool) -> bool {
-        let registry = gst::Registry::get();
-        if let Some(factory) = ElementFactory::find(name) {
-            let factory = factory.upcast::<gst::PluginFeature>();
-            if enable {
-                factory.set_rank(gst::Rank::Primary + 4);
-            } else {
-                factory.set_rank(gst::Rank::None);
+    /// Caps of the frames handed to the appsink, which `videorate` converts to
+    fn sink_caps(size: PhysicalSize<u32>, framerate: Fraction) -> gst::Caps {
+        VideoCapsBuilder::new()
+            .width(size.width as _)
+            .height(size.height as _)
+            .pixel_aspect_ratio(Fraction::new(1, 1))
+            .format(gst_video::VideoFormat::Rgba)
+            .framerate(framerate)
+            .build()
+    }
+
+    /// Renegotiates the framerate, so that `videorate` drops the frames in excess before they
+    /// are converted, scaled and copied.
+    pub(crate) fn set_max_framerate(
+        &mut self,
+        framerate: Option<f64>,
+    ) -> Result<(), anyhow::Error> {
+        let framerate = match framerate {
+            Some(framerate) if framerate < FRAMERATE as f64 => {
+                Fraction::new((framerate * 1000.).round().max(1.) as i32, 1000)
             }
-            registry.add_feature(&factory).is_ok()
-        } else {
-            false
+            _ => Fraction::new(FRAMERATE, 1),
+        };
+        let rate = framerate.numer() as f64 / framerate.denom() as f64;
+        if rate == self.framerate {
+            return Ok(());
         }
+        self.framerate = rate;
+
+        // A loop recorded at two framerates would replay at the wrong speed
+        self.recorder = None;
+
+        self.appsink
+            .set_caps(Some(&Self::sink_caps(self.frame_size, framerate)));
+        let pad = self
+            .appsink
+            .static_pad("sink")
+            .ok_or_else(|| anyhow::anyhow!("Appsink has no sink pad"))?;
+        pad.push_event(gst::event::Reconfigure::new());
+        Ok(())
     }
 
-    fn create_pipeline<S>(
-        uri: &str,
-        size: S,
-    ) -> Result<(gst::Pipeline, gst::Pad, gst_app::AppSink), anyhow::Error>
-    where
-        S: Into<PhysicalSize<u32>>,
-    {
-        let size: PhysicalSize<u32> = size.into();
+    fn create_pipeline(
+        plan: &Plan,
+        audiosink: &gst::Element,
+    ) -> Result<(gst::Pipeline, gst::Pad, gst_app::AppSink), anyhow::Error> {
+        let size = plan.size;
         // {playbin} -> {sinkbin} ({aspectratiocrop} -> {videoconvertscale} -> {videorate} -> {appsink})
 
-        // playbin uri={uri} video-sink="aspectratiocrop aspect-ratio={width}/{height} ! videoconvertscale ! videorate ! appsink" audio-sink="autoaudiosink"
+        // playbin uri={uri} video-sink="aspectratiocrop aspect-ratio={width}/{height} ! videoconvertscale ! videorate ! appsink" audio-sink="{audiosink}"
 
         let playbin = ElementFactory::make("playbin")
-            .property("uri", uri)
+            .property("uri", plan.uri.as_str())
             .build()?
             .downcast::<gst::Pipeline>()
             .unwrap();
 
-        let audiosink = ElementFactory::make("autoaudiosink").build()?;
-
         let sinkbin = gst::Bin::builder().name("sinkbin").build();
 
-        let caps = VideoCapsBuilder::new()
-            //.framerate(Fraction::from(60))
-            .width(size.width as _)
-            .height(size.height as _)
-            .pixel_aspect_ratio(Fraction::new(1, 1))
-            .format(gst_video::VideoFormat::Rgba)
-            .framerate(Fraction::new(30, 1))
-            .build();
+        let caps = Self::sink_caps(size, Fraction::new(FRAMERATE, 1));
 
         let aspectratiocrop = ElementFactory::make("aspectratiocrop")
             .property(
@@ -270,27 +584,54 @@ impl Video {
 
         fpsdisplaysink.set_property("video-sink", &appsink);
 
-        sinkbin.add_many([
-            &aspectratiocrop,
-            &videorate,
-            &videoconvertscale,
-            &fpsdisplaysink,
-        ])?;
-
-        gst::Element::link_many([
-            &aspectratiocrop,
-            &videorate,
-            &videoconvertscale,
-            &fpsdisplaysink,
-        ])?;
-
-        let pad = aspectratiocrop.static_pad("sink").unwrap();
+        let mut elements = vec![
+            aspectratiocrop,
+            videorate,
+            videoconvertscale,
+            fpsdisplaysink,
+        ];
+
+        if let Some([left, right, top, bottom]) = plan.crop {
+            // videocrop left={left} right={right} top={top} bottom={bottom} ! ...
+            let videocrop = ElementFactory::make("videocrop")
+                .property("left", left as i32)
+                .property("right", right as i32)
+                .property("top", top as i32)
+                .property("bottom", bottom as i32)
+                .build()?;
+            elements.insert(0, videocrop);
+        }
+
+        if let Some(postproc) = plan.postproc {
+            // vaapipostproc crop-*={crop} width={width} height={height} ! video/x-raw ! ...
+            let [left, right, top, bottom] = postproc.crop;
+            let vaapipostproc = ElementFactory::make("vaapipostproc")
+                .property("crop-left", left)
+                .property("crop-right", right)
+                .property("crop-top", top)
+                .property("crop-bottom", bottom)
+                .property("width", size.width)
+                .property("height", size.height)
+                .build()?;
+            // Downloads the scaled frames to system memory
+            let download = ElementFactory::make("capsfilter")
+                .property("caps", gst::Caps::builder("video/x-raw").build())
+                .build()?;
+
+            elements.insert(0, download);
+            elements.insert(0, vaapipostproc);
+        }
+
+        sinkbin.add_many(&elements)?;
+        gst::Element::link_many(&elements)?;
+
+        let pad = elements[0].static_pad("sink").unwrap();
         let ghost_pad = GhostPad::builder_with_target(&pad)?.build();
         ghost_pad.set_active(true)?;
         sinkbin.add_pad(&ghost_pad)?;
 
         playbin.set_property("video-sink", &sinkbin);
-        playbin.set_property("audio-sink", &audiosink);
+        playbin.set_property("audio-sink", audiosink);
 
         Ok((
             playbin,
@@ -0,0 +1,245 @@
+use std::{
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::config::LoopCacheConfig;
+
+/// Frames waiting for the worker, recording gives up rather than holding up the pipeline past it
+const QUEUE_FRAMES: usize = 8;
+
+/// Records the decoded frames of the first loop of a clip, fed from the appsink callback.
+/// Compressing happens on a worker thread, so that it doesn't slow down decoding.
+pub(crate) struct Recorder {
+    compress: bool,
+    /// `None` once given up or finished
+    queue: Option<SyncSender<Vec<u8>>>,
+    /// Returns the frames, or `None` if they didn't fit the cap
+    worker: Option<JoinHandle<Option<Vec<Vec<u8>>>>>,
+}
+
+impl Recorder {
+    pub(crate) fn new(config: &LoopCacheConfig) -> Self {
+        let (queue, frames) = mpsc::sync_channel(QUEUE_FRAMES);
+        let (max_bytes, compress) = (config.max_bytes(), config.compress);
+        let worker = std::thread::Builder::new()
+            .name("loop-cache".to_owned())
+            .spawn(move || keep(frames, max_bytes, compress))
+            .map_err(|e| eprintln!("Error starting the loop cache: {:#}", e))
+            .ok();
+
+        Self {
+            compress,
+            queue: worker.is_some().then_some(queue),
+            worker,
+        }
+    }
+
+    /// Hands `frame` to the worker, or gives up for good once the loop doesn't fit the cap or
+    /// the worker falls behind.
+    pub(crate) fn record(&mut self, frame: Vec<u8>) {
+        let Some(queue) = &self.queue else {
+            return;
+        };
+
+        if queue.try_send(frame).is_err() {
+            // The worker stops once the queue is gone
+            self.queue = None;
+            self.worker = None;
+        }
+    }
+
+    /// Called at the end of the first loop, takes the recorded frames if they all fit. Waits for
+    /// the worker to compress the last few frames.
+    pub(crate) fn finish(&mut self, framerate: f64) -> Option<Loop> {
+        self.queue = None;
+        let frames = self.worker.take()?.join().ok()??;
+
+        (!frames.is_empty() && framerate > 0.).then(|| Loop {
+            frames,
+            compressed: self.compress,
+            framerate,
+        })
+    }
+}
+
+/// Keeps copies of the frames from `queue` until it is closed, `None` once over `max_bytes`.
+fn keep(queue: Receiver<Vec<u8>>, max_bytes: usize, compress: bool) -> Option<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    let mut bytes = 0;
+
+    for frame in queue {
+        let data = if compress {
+            lz4_flex::block::compress(&frame)
+        } else {
+            frame
+        };
+        bytes += data.len();
+        if bytes > max_bytes {
+            return None;
+        }
+        frames.push(data);
+    }
+    Some(frames)
+}
+
+/// Frames of a whole loop at the constant rate `videorate` produces them at
+pub(crate) struct Loop {
+    frames: Vec<Vec<u8>>,
+    /// Frames are LZ4 blocks
+    compressed: bool,
+    framerate: f64,
+}
+
+impl Loop {
+    /// Index of the frame shown `elapsed` after the start of a loop, wrapping around
+    fn index(&self, elapsed: Duration) -> usize {
+        (elapsed.as_secs_f64() * self.framerate) as usize % self.frames.len()
+    }
+
+    /// Copies frame `index` into `frame`, returns whether it fit.
+    fn copy_into(&self, index: usize, frame: &mut [u8]) -> bool {
+        let data = &self.frames[index];
+
+        if self.compressed {
+            lz4_flex::block::decompress_into(data, frame).map_or(false, |len| len == frame.len())
+        } else if data.len() == frame.len() {
+            frame.copy_from_slice(data);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Replays a `Loop` from memory, following the wall clock instead of the pipeline's.
+pub(crate) struct Replay {
+    start: Instant,
+    paused_at: Option<Instant>,
+    /// Index of the frame in the pixels buffer
+    shown: Option<usize>,
+}
+
+impl Replay {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            paused_at: None,
+            shown: None,
+        }
+    }
+
+    /// Stops the clock while paused, so that the loop resumes where it stopped.
+    pub(crate) fn set_paused(&mut self, paused: bool, now: Instant) {
+        match (paused, self.paused_at) {
+            (true, None) => self.paused_at = Some(now),
+            (false, Some(paused_at)) => {
+                self.start += now.duration_since(paused_at);
+                self.paused_at = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Draws the frame due at `now` into `frame` if it isn't shown yet, returns whether it did.
+    pub(crate) fn render(&mut self, frames: &Loop, now: Instant, frame: &mut [u8]) -> bool {
+        let elapsed = self.paused_at.unwrap_or(now).duration_since(self.start);
+        let index = frames.index(elapsed);
+        if self.shown == Some(index) {
+            return false;
+        }
+
+        let rendered = frames.copy_into(index, frame);
+        if rendered {
+            self.shown = Some(index);
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_LEN: usize = 64;
+
+    fn config(max_mb: u64, compress: bool) -> LoopCacheConfig {
+        LoopCacheConfig { max_mb, compress }
+    }
+
+    /// Frame `i`, all of its bytes `i`
+    fn frame(i: u8) -> Vec<u8> {
+        vec![i; FRAME_LEN]
+    }
+
+    fn record(config: &LoopCacheConfig, count: u8, framerate: f64) -> Option<Loop> {
+        let mut recorder = Recorder::new(config);
+        for i in 0..count {
+            recorder.record(frame(i));
+        }
+        recorder.finish(framerate)
+    }
+
+    #[test]
+    fn replays_the_frames_in_order() {
+        for compress in [false, true] {
+            let frames = record(&config(1, compress), 4, 10.).unwrap();
+            let start = Instant::now();
+            let mut replay = Replay::new(start);
+            let mut shown = vec![0; FRAME_LEN];
+
+            // Twice around the loop, at 10 fps
+            for i in 0..8 {
+                let now = start + Duration::from_millis(i * 100 + 50);
+                assert!(replay.render(&frames, now, &mut shown));
+                assert_eq!(shown, frame(i as u8 % 4), "{}", i);
+
+                // Already shown
+                assert!(!replay.render(&frames, now, &mut shown));
+            }
+        }
+    }
+
+    #[test]
+    fn holds_the_frame_while_paused() {
+        let frames = record(&config(1, true), 4, 10.).unwrap();
+        let start = Instant::now();
+        let mut replay = Replay::new(start);
+        let mut shown = vec![0; FRAME_LEN];
+
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(replay.render(&frames, at(150), &mut shown));
+        assert_eq!(shown, frame(1));
+
+        replay.set_paused(true, at(150));
+        assert!(!replay.render(&frames, at(1000), &mut shown));
+
+        // Resumes where it stopped, 100 ms later is the next frame
+        replay.set_paused(false, at(1000));
+        assert!(replay.render(&frames, at(1100), &mut shown));
+        assert_eq!(shown, frame(2));
+    }
+
+    #[test]
+    fn gives_up_over_the_cap() {
+        assert!(record(&config(0, false), 2, 10.).is_none());
+        assert!(record(&config(0, true), 2, 10.).is_none());
+    }
+
+    #[test]
+    fn needs_frames_and_a_framerate() {
+        assert!(record(&config(1, true), 0, 10.).is_none());
+        assert!(record(&config(1, true), 2, 0.).is_none());
+    }
+
+    #[test]
+    fn skips_frames_of_another_size() {
+        for compress in [false, true] {
+            let frames = record(&config(1, compress), 2, 10.).unwrap();
+            let mut replay = Replay::new(Instant::now());
+            let mut shown = vec![0; FRAME_LEN * 2];
+            assert!(!replay.render(&frames, Instant::now(), &mut shown));
+        }
+    }
+}
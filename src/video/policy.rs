@@ -0,0 +1,344 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, PoisonError},
+};
+
+use gst::ElementFactory;
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    cache,
+    config::DecodeConfig,
+    library::Position,
+    paths::{self, Stamp},
+    probe::{self, ProbeReport},
+};
+
+/// More than a playlist switches between, reports are probed again past it
+const MAX_PROBES: usize = 64;
+
+/// Reports of the clips probed so far, by URI, so that rebuilding a pipeline, e.g. when
+/// recovering or throttling, doesn't wait for the Discoverer again
+static PROBES: Mutex<BTreeMap<String, (Stamp, ProbeReport)>> = Mutex::new(BTreeMap::new());
+
+/// How a clip is decoded, decided before building the pipeline
+#[derive(Debug, Clone)]
+pub(crate) struct Plan {
+    /// The clip, or a cached copy of it
+    pub(crate) uri: String,
+    /// Size of the decoded frames, never larger than the requested size
+    pub(crate) size: PhysicalSize<u32>,
+    /// Crops and scales on the GPU before the frames are downloaded, if set
+    pub(crate) postproc: Option<PostProc>,
+    /// Left, right, top and bottom crop of a clip which isn't cropped in the middle, before
+    /// `aspectratiocrop` which then has nothing left to crop
+    pub(crate) crop: Option<[u32; 4]>,
+}
+
+/// Settings of `vaapipostproc`, from the clip's native size
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PostProc {
+    /// Left, right, top and bottom
+    pub(crate) crop: [u32; 4],
+}
+
+/// Decides how to decode `uri` for a surface of `target`, keeping `position` when cropping: a
+/// cached copy when there is one, the clip's own resolution when it is smaller than `target`, and
+/// `vaapipostproc` when the clip will be decoded with VA-API.
+///
+/// `decoder::setup` must have been called.
+pub(crate) fn plan(
+    uri: &str,
+    target: PhysicalSize<u32>,
+    config: &DecodeConfig,
+    position: Position,
+) -> Plan {
+    let target = clamp(target, config.max_width, config.max_height);
+    let fallback = Plan {
+        uri: uri.to_owned(),
+        size: target,
+        postproc: None,
+        crop: None,
+    };
+
+    // Cached copies are cropped in the middle
+    if config.use_cache && position == Position::Center {
+        if let Some(uri) = cache::lookup(uri, target).and_then(|p| paths::path_to_uri(&p).ok()) {
+            return Plan { uri, ..fallback };
+        }
+    }
+
+    // Streams could take long to probe, adaptive ones pick their variant with `target`
+    if !uri.starts_with("file://") {
+        return fallback;
+    }
+
+    let report = match cached_probe(uri) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!(
+                "Error probing {}, decoding at {}x{}: {:#}",
+                uri, target.width, target.height, e
+            );
+            return fallback;
+        }
+    };
+    let Some(video) = report.video else {
+        return fallback;
+    };
+
+    let native = PhysicalSize::new(video.width, video.height);
+    let (crop, cropped) = crop_to_aspect(native, target, position);
+    // Scaled up by the GPU when rendering instead
+    let size = if cropped.width < target.width {
+        PhysicalSize::new(even(cropped.width), even(cropped.height))
+    } else {
+        target
+    };
+
+    let vaapi = report
+        .hardware_decoders
+        .iter()
+        .any(|decoder| decoder.starts_with("vaapi"));
+    let use_postproc = config.gpu_scaling && vaapi && postproc_enabled();
+    let postproc = use_postproc.then_some(PostProc { crop });
+
+    Plan {
+        uri: uri.to_owned(),
+        size,
+        postproc,
+        crop: (position != Position::Center && postproc.is_none()).then_some(crop),
+    }
+}
+
+/// `probe::probe` of the local file at `uri`, run once for as long as the file doesn't change
+fn cached_probe(uri: &str) -> Result<ProbeReport, anyhow::Error> {
+    let stamp = gst::glib::filename_from_uri(uri)
+        .ok()
+        .and_then(|(path, _)| paths::stamp(&path));
+    let probes = || PROBES.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(stamp) = stamp {
+        if let Some((probed, report)) = probes().get(uri) {
+            if *probed == stamp {
+                return Ok(report.clone());
+            }
+        }
+    }
+
+    // Not holding the lock for the probe
+    let report = probe::probe(uri)?;
+    if let Some(stamp) = stamp {
+        remember(&mut probes(), uri, stamp, report.clone());
+    }
+    Ok(report)
+}
+
+/// Adds the report of `uri` to `probes`, forgetting another one when there are `MAX_PROBES`.
+fn remember(
+    probes: &mut BTreeMap<String, (Stamp, ProbeReport)>,
+    uri: &str,
+    stamp: Stamp,
+    report: ProbeReport,
+) {
+    if probes.len() >= MAX_PROBES && !probes.contains_key(uri) {
+        let forgotten = probes.keys().next().cloned();
+        if let Some(forgotten) = forgotten {
+            probes.remove(&forgotten);
+        }
+    }
+    probes.insert(uri.to_owned(), (stamp, report));
+}
+
+/// Whether `vaapipostproc` exists and isn't disabled, see `decoder::disable`
+fn postproc_enabled() -> bool {
+    ElementFactory::find("vaapipostproc").map_or(false, |factory| factory.rank() > gst::Rank::None)
+}
+
+/// Fits `size` inside the maximums, keeping its aspect ratio.
+fn clamp(
+    size: PhysicalSize<u32>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> PhysicalSize<u32> {
+    let scale_width = max_width.map_or(1., |max| max as f64 / size.width as f64);
+    let scale_height = max_height.map_or(1., |max| max as f64 / size.height as f64);
+    let scale = scale_width.min(scale_height);
+
+    if scale >= 1. {
+        size
+    } else {
+        PhysicalSize::new(
+            even((size.width as f64 * scale) as u32),
+            even((size.height as f64 * scale) as u32),
+        )
+    }
+}
+
+/// Crop of `native` to the aspect ratio of `target`, keeping `position`, with the cropped size.
+/// In the middle like `aspectratiocrop` unless `position` is a side.
+fn crop_to_aspect(
+    native: PhysicalSize<u32>,
+    target: PhysicalSize<u32>,
+    position: Position,
+) -> ([u32; 4], PhysicalSize<u32>) {
+    let (nw, nh) = (native.width as u64, native.height as u64);
+    let (tw, th) = (target.width.max(1) as u64, target.height.max(1) as u64);
+
+    if nw * th > nh * tw {
+        // Wider than the target
+        let width = (nh * tw / th) as u32;
+        let excess = native.width - width;
+        let left = match position {
+            Position::Left => 0,
+            Position::Right => excess,
+            _ => excess / 2,
+        };
+        (
+            [left, excess - left, 0, 0],
+            PhysicalSize::new(width, native.height),
+        )
+    } else {
+        let height = (nw * th / tw) as u32;
+        let excess = native.height - height;
+        let top = match position {
+            Position::Top => 0,
+            Position::Bottom => excess,
+            _ => excess / 2,
+        };
+        (
+            [0, 0, top, excess - top],
+            PhysicalSize::new(native.width, height),
+        )
+    }
+}
+
+/// Most video formats want even sizes
+#[inline]
+fn even(length: u32) -> u32 {
+    (length & !1).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn size(width: u32, height: u32) -> PhysicalSize<u32> {
+        PhysicalSize::new(width, height)
+    }
+
+    fn report(uri: &str) -> ProbeReport {
+        ProbeReport {
+            uri: uri.to_owned(),
+            container: None,
+            duration_ms: None,
+            seekable: true,
+            video: None,
+            audio: None,
+            hardware_decoders: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn clamps_to_the_maximums() {
+        assert_eq!(clamp(size(3840, 2160), None, None), size(3840, 2160));
+        assert_eq!(
+            clamp(size(1280, 720), Some(1920), Some(1080)),
+            size(1280, 720)
+        );
+        assert_eq!(clamp(size(3840, 2160), Some(1920), None), size(1920, 1080));
+        // The tighter maximum wins, keeping the aspect ratio
+        assert_eq!(
+            clamp(size(3840, 2160), Some(1920), Some(720)),
+            size(1280, 720)
+        );
+        // Even, at least 2
+        assert_eq!(clamp(size(1001, 1001), Some(501), None), size(500, 500));
+        assert_eq!(clamp(size(4000, 10), Some(100), None), size(100, 2));
+    }
+
+    #[test]
+    fn crops_in_the_middle() {
+        // Wider than the target
+        let (crop, cropped) = crop_to_aspect(size(1920, 1080), size(1080, 1080), Position::Center);
+        assert_eq!(crop, [420, 420, 0, 0]);
+        assert_eq!(cropped, size(1080, 1080));
+
+        // Taller than the target
+        let (crop, cropped) = crop_to_aspect(size(1080, 1920), size(1920, 1080), Position::Center);
+        assert_eq!(crop, [0, 0, 656, 657]);
+        assert_eq!(cropped, size(1080, 607));
+
+        // Same aspect ratio
+        let (crop, cropped) = crop_to_aspect(size(3840, 2160), size(1920, 1080), Position::Center);
+        assert_eq!(crop, [0; 4]);
+        assert_eq!(cropped, size(3840, 2160));
+    }
+
+    #[test]
+    fn crops_to_the_position() {
+        let wide = |position| crop_to_aspect(size(1920, 1080), size(1080, 1080), position).0;
+        assert_eq!(wide(Position::Left), [0, 840, 0, 0]);
+        assert_eq!(wide(Position::Right), [840, 0, 0, 0]);
+        // Only the sides of the cropped dimension matter
+        assert_eq!(wide(Position::Top), wide(Position::Center));
+
+        let tall = |position| crop_to_aspect(size(1080, 1920), size(1080, 1080), position).0;
+        assert_eq!(tall(Position::Top), [0, 0, 0, 840]);
+        assert_eq!(tall(Position::Bottom), [0, 0, 840, 0]);
+        assert_eq!(tall(Position::Left), tall(Position::Center));
+    }
+
+    #[test]
+    fn survives_empty_targets() {
+        let (_, cropped) = crop_to_aspect(size(1920, 1080), size(0, 0), Position::Center);
+        assert_eq!(cropped, size(1080, 1080));
+    }
+
+    #[test]
+    fn evens_lengths() {
+        assert_eq!(even(1081), 1080);
+        assert_eq!(even(1080), 1080);
+        assert_eq!(even(1), 2);
+        assert_eq!(even(0), 2);
+    }
+
+    #[test]
+    fn streams_are_decoded_at_the_target() {
+        let config = DecodeConfig {
+            max_width: Some(1920),
+            use_cache: false,
+            ..DecodeConfig::default()
+        };
+        let plan = plan(
+            "https://example.com/clip.m3u8",
+            size(3840, 2160),
+            &config,
+            Position::Left,
+        );
+        assert_eq!(plan.uri, "https://example.com/clip.m3u8");
+        assert_eq!(plan.size, size(1920, 1080));
+        assert!(plan.postproc.is_none());
+        assert_eq!(plan.crop, None);
+    }
+
+    #[test]
+    fn remembers_a_bounded_number_of_probes() {
+        let mut probes = BTreeMap::new();
+        let stamp = (1, Duration::from_secs(1));
+        for i in 0..MAX_PROBES * 2 {
+            let uri = format!("file:///clips/{}.mp4", i);
+            remember(&mut probes, &uri, stamp, report(&uri));
+        }
+        assert_eq!(probes.len(), MAX_PROBES);
+
+        // Probing a remembered clip again replaces its report
+        let uri = probes.keys().next().unwrap().clone();
+        let changed = (2, Duration::from_secs(2));
+        remember(&mut probes, &uri, changed, report(&uri));
+        assert_eq!(probes.len(), MAX_PROBES);
+        assert_eq!(probes[&uri].0, changed);
+    }
+}
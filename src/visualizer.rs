@@ -0,0 +1,107 @@
+use winit::dpi::PhysicalSize;
+
+use crate::{
+    config::{VisualizerConfig, VisualizerStyle},
+    source::Source,
+};
+
+mod draw;
+pub(crate) mod input;
+pub(crate) mod spectrum;
+
+use draw::{Canvas, Palette};
+use input::{Capture, FileInput, Input};
+use spectrum::Spectrum;
+
+/// Samples analyzed per frame, about 46ms at 44.1kHz
+const WINDOW: usize = 2048;
+
+/// Bars, waveform or radial spectrum of the system audio, or of a file.
+pub(crate) struct Visualizer {
+    config: VisualizerConfig,
+    size: PhysicalSize<u32>,
+
+    input: Input,
+    spectrum: Spectrum,
+
+    /// Latest `WINDOW` mono samples
+    samples: Vec<f32>,
+    /// Smoothed band levels from 0 to 1
+    levels: Vec<f32>,
+
+    need_render: bool,
+}
+
+impl Visualizer {
+    pub(crate) fn new<S>(
+        size: S,
+        config: &VisualizerConfig,
+        framerate: f64,
+    ) -> Result<Self, anyhow::Error>
+    where
+        S: Into<PhysicalSize<u32>>,
+    {
+        let input = match &config.file {
+            Some(file) => Input::File(FileInput::open(file, framerate)?),
+            None => Input::Capture(Capture::open(config.device.as_deref(), WINDOW * 4)?),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            size: size.into(),
+            spectrum: Spectrum::new(WINDOW, input.rate()),
+            input,
+            samples: vec![0.; WINDOW],
+            levels: vec![0.; config.bar_count.max(1)],
+            need_render: true,
+        })
+    }
+}
+
+impl Source for Visualizer {
+    fn update(&mut self) -> Result<(), anyhow::Error> {
+        self.input.read(&mut self.samples)?;
+
+        let smoothing = self.config.smoothing.clamp(0., 0.99);
+        let bands = self.spectrum.bands(&self.samples, self.levels.len());
+        for (level, band) in self.levels.iter_mut().zip(bands) {
+            // Rises immediately, falls smoothly
+            *level = if band > *level {
+                band
+            } else {
+                *level * smoothing + band * (1. - smoothing)
+            };
+        }
+
+        self.need_render = true;
+        Ok(())
+    }
+
+    #[inline]
+    fn frame_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    fn render(&mut self, frame: &mut [u8]) -> bool {
+        if !self.need_render {
+            return false;
+        }
+        self.need_render = false;
+
+        let palette = Palette {
+            foreground: self.config.foreground,
+            peak: self.config.peak,
+        };
+
+        let mut canvas = Canvas::new(frame, self.size);
+        canvas.clear(self.config.background);
+
+        match self.config.style {
+            VisualizerStyle::Bars => draw::bars(&mut canvas, &self.levels, palette),
+            VisualizerStyle::Waveform => draw::waveform(&mut canvas, &self.samples, palette),
+            VisualizerStyle::Radial => draw::radial(&mut canvas, &self.levels, palette),
+        }
+
+        true
+    }
+}
@@ -0,0 +1,168 @@
+use std::f32::consts::PI;
+
+use winit::dpi::PhysicalSize;
+
+/// RGBA frame to draw into
+pub(crate) struct Canvas<'a> {
+    frame: &'a mut [u8],
+    width: i32,
+    height: i32,
+}
+
+impl<'a> Canvas<'a> {
+    pub(crate) fn new(frame: &'a mut [u8], size: PhysicalSize<u32>) -> Self {
+        Self {
+            frame,
+            width: size.width as i32,
+            height: size.height as i32,
+        }
+    }
+
+    pub(crate) fn clear(&mut self, color: [u8; 3]) {
+        let [r, g, b] = color;
+        for pixel in self.frame.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[r, g, b, 0xff]);
+        }
+    }
+
+    pub(crate) fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 3]) {
+        let (x0, x1) = (x.max(0), (x + width).min(self.width));
+        let (y0, y1) = (y.max(0), (y + height).min(self.height));
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        let [r, g, b] = color;
+        for y in y0..y1 {
+            let row = (y * self.width) as usize * 4;
+            for pixel in
+                self.frame[row + x0 as usize * 4..row + x1 as usize * 4].chunks_exact_mut(4)
+            {
+                pixel.copy_from_slice(&[r, g, b, 0xff]);
+            }
+        }
+    }
+
+    /// Line of `thickness` pixels between `from` and `to`
+    pub(crate) fn line(
+        &mut self,
+        from: (f32, f32),
+        to: (f32, f32),
+        thickness: i32,
+        color: [u8; 3],
+    ) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs()).ceil().max(1.) as i32;
+        let half = thickness / 2;
+
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let x = (from.0 + dx * t) as i32;
+            let y = (from.1 + dy * t) as i32;
+            self.fill_rect(x - half, y - half, thickness, thickness, color);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn width(&self) -> i32 {
+        self.width
+    }
+
+    #[inline]
+    pub(crate) fn height(&self) -> i32 {
+        self.height
+    }
+}
+
+/// Colors of the visualization
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Palette {
+    pub(crate) foreground: [u8; 3],
+    pub(crate) peak: [u8; 3],
+}
+
+impl Palette {
+    /// Color of a `level` from 0 to 1
+    pub(crate) fn at(&self, level: f32) -> [u8; 3] {
+        let level = level.clamp(0., 1.);
+        let mut color = [0; 3];
+        for ((out, from), to) in color.iter_mut().zip(self.foreground).zip(self.peak) {
+            *out = (from as f32 + (to as f32 - from as f32) * level) as u8;
+        }
+        color
+    }
+}
+
+/// Vertical bars rising from the bottom
+pub(crate) fn bars(canvas: &mut Canvas, levels: &[f32], palette: Palette) {
+    if levels.is_empty() {
+        return;
+    }
+
+    let slot = canvas.width() as f32 / levels.len() as f32;
+    let gap = (slot / 5.) as i32;
+    let max_height = canvas.height() as f32 * 0.9;
+
+    for (i, level) in levels.iter().enumerate() {
+        let height = (level * max_height) as i32;
+        let x = (i as f32 * slot) as i32;
+
+        canvas.fill_rect(
+            x + gap / 2,
+            canvas.height() - height,
+            (slot as i32 - gap).max(1),
+            height,
+            palette.at(*level),
+        );
+    }
+}
+
+/// Oscilloscope of the raw samples across the middle
+pub(crate) fn waveform(canvas: &mut Canvas, samples: &[f32], palette: Palette) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let middle = canvas.height() as f32 / 2.;
+    let amplitude = middle * 0.9;
+    let width = canvas.width().max(2);
+
+    let point = |x: i32| {
+        let sample = samples[x as usize * (samples.len() - 1) / (width - 1) as usize];
+        (x as f32, middle - sample.clamp(-1., 1.) * amplitude, sample)
+    };
+
+    let mut previous = point(0);
+    for x in 1..width {
+        let current = point(x);
+        canvas.line(
+            (previous.0, previous.1),
+            (current.0, current.1),
+            3,
+            palette.at(current.2.abs() * 2.),
+        );
+        previous = current;
+    }
+}
+
+/// Bars radiating from a circle in the middle
+pub(crate) fn radial(canvas: &mut Canvas, levels: &[f32], palette: Palette) {
+    let center = (canvas.width() as f32 / 2., canvas.height() as f32 / 2.);
+    let extent = canvas.width().min(canvas.height()) as f32;
+    let inner = extent * 0.2;
+    let max_length = extent * 0.25;
+    let thickness = ((2. * PI * inner / levels.len().max(1) as f32) * 0.6).max(1.) as i32;
+
+    for (i, level) in levels.iter().enumerate() {
+        let angle = 2. * PI * i as f32 / levels.len() as f32 - PI / 2.;
+        let (sin, cos) = angle.sin_cos();
+        let outer = inner + level * max_length;
+
+        canvas.line(
+            (center.0 + cos * inner, center.1 + sin * inner),
+            (center.0 + cos * outer, center.1 + sin * outer),
+            thickness,
+            palette.at(*level),
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, Sample, SampleFormat, SizedSample,
+};
+use gst::prelude::*;
+
+use crate::paths;
+
+/// Rate files are decoded at
+const FILE_RATE: u32 = 44100;
+/// Of a file kept in memory, the rest is never decoded
+const MAX_FILE_DURATION: Duration = Duration::from_secs(120);
+
+/// Where the visualized samples come from
+pub(crate) enum Input {
+    Capture(Capture),
+    File(FileInput),
+}
+
+impl Input {
+    #[inline]
+    pub(crate) fn rate(&self) -> u32 {
+        match self {
+            Self::Capture(capture) => capture.rate,
+            Self::File(_) => FILE_RATE,
+        }
+    }
+
+    /// Fills `window` with the latest mono samples.
+    pub(crate) fn read(&mut self, window: &mut [f32]) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Capture(capture) => capture.read(window),
+            Self::File(file) => {
+                file.read(window);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct CaptureShared {
+    /// Latest mono samples
+    samples: Mutex<VecDeque<f32>>,
+    error: Mutex<Option<String>>,
+}
+
+/// Live capture through cpal, on a thread which owns the stream since it isn't `Send`
+/// everywhere.
+pub(crate) struct Capture {
+    rate: u32,
+    /// How many samples are kept
+    capacity: usize,
+    shared: Arc<CaptureShared>,
+
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Capture {
+    /// Captures `device`, an input device or an output device as loopback, the default input
+    /// device if `None`.
+    pub(crate) fn open(device: Option<&str>, capacity: usize) -> Result<Self, anyhow::Error> {
+        let shared = Arc::new(CaptureShared::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let shared_ref = shared.clone();
+        let stop_ref = stop.clone();
+        let device = device.map(str::to_owned);
+        let (init_tx, init_rx) = mpsc::channel();
+
+        let handle = std::thread::Builder::new()
+            .name("awa-capture".to_owned())
+            .spawn(
+                move || match open_stream(device.as_deref(), shared_ref, capacity) {
+                    Ok((stream, rate)) => {
+                        let _ = init_tx.send(Ok(rate));
+                        while !stop_ref.load(Ordering::Acquire) {
+                            std::thread::park_timeout(Duration::from_millis(100));
+                        }
+                        drop(stream);
+                    }
+                    Err(e) => {
+                        let _ = init_tx.send(Err(e));
+                    }
+                },
+            )?;
+
+        let rate = init_rx
+            .recv()
+            .map_err(|_| anyhow::anyhow!("Capture thread exited"))??;
+
+        Ok(Self {
+            rate,
+            capacity,
+            shared,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Fills `window` with the latest mono samples, silence before the capture started.
+    pub(crate) fn read(&self, window: &mut [f32]) -> Result<(), anyhow::Error> {
+        if let Some(e) = self.shared.error.lock().unwrap().take() {
+            return Err(anyhow::anyhow!("Error capturing audio: {}", e));
+        }
+
+        let samples = self.shared.samples.lock().unwrap();
+        let available = samples.len().min(window.len()).min(self.capacity);
+        let silent = window.len() - available;
+
+        window[..silent].fill(0.);
+        for (out, sample) in window[silent..]
+            .iter_mut()
+            .zip(samples.range(samples.len() - available..))
+        {
+            *out = *sample;
+        }
+        Ok(())
+    }
+}
+
+fn find_device(name: Option<&str>) -> Result<cpal::Device, anyhow::Error> {
+    let host = cpal::default_host();
+
+    let Some(name) = name else {
+        return host
+            .default_input_device()
+            .ok_or_else(|| anyhow::anyhow!("No audio input device"));
+    };
+
+    let matches = |device: &cpal::Device| device.name().map_or(false, |n| n == name);
+    host.input_devices()?
+        .find(matches)
+        // Loopback, e.g. on WASAPI
+        .or_else(|| host.output_devices().ok()?.find(matches))
+        .ok_or_else(|| anyhow::anyhow!("Audio device {:?} not found", name))
+}
+
+fn open_stream(
+    name: Option<&str>,
+    shared: Arc<CaptureShared>,
+    capacity: usize,
+) -> Result<(cpal::Stream, u32), anyhow::Error> {
+    let device = find_device(name)?;
+    let supported = device
+        .default_input_config()
+        .or_else(|_| device.default_output_config())?;
+    let config = supported.config();
+
+    let stream = match supported.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, shared, capacity),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, shared, capacity),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, shared, capacity),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, shared, capacity),
+        format => return Err(anyhow::anyhow!("Unsupported sample format {:?}", format)),
+    }?;
+    stream.play()?;
+
+    Ok((stream, config.sample_rate.0))
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    shared: Arc<CaptureShared>,
+    capacity: usize,
+) -> Result<cpal::Stream, anyhow::Error>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels.max(1) as usize;
+    let error_shared = shared.clone();
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let mut samples = shared.samples.lock().unwrap();
+
+            // Mixed down to mono
+            samples.extend(data.chunks_exact(channels).map(|frame| {
+                frame.iter().map(|s| f32::from_sample(*s)).sum::<f32>() / channels as f32
+            }));
+
+            let excess = samples.len().saturating_sub(capacity);
+            samples.drain(..excess);
+        },
+        move |e| *error_shared.error.lock().unwrap() = Some(e.to_string()),
+        None,
+    )?;
+
+    Ok(stream)
+}
+
+/// A file decoded up front, at most its first `MAX_FILE_DURATION`, and played in a loop, advancing
+/// by one video frame per read, so that the visualization doesn't depend on a sound server or on
+/// timing.
+pub(crate) struct FileInput {
+    samples: Vec<f32>,
+    position: usize,
+    step: usize,
+}
+
+impl FileInput {
+    pub(crate) fn open(path: &Path, framerate: f64) -> Result<Self, anyhow::Error> {
+        let max_samples = (MAX_FILE_DURATION.as_secs_f64() * FILE_RATE as f64) as usize;
+        Self::new(decode(path, max_samples)?, framerate)
+            .ok_or_else(|| anyhow::anyhow!("{} has no audio", path.display()))
+    }
+
+    /// Plays `samples` at `FILE_RATE`, `None` if there are none.
+    fn new(samples: Vec<f32>, framerate: f64) -> Option<Self> {
+        (!samples.is_empty()).then(|| Self {
+            samples,
+            position: 0,
+            step: (FILE_RATE as f64 / framerate.max(1.)) as usize,
+        })
+    }
+
+    fn read(&mut self, window: &mut [f32]) {
+        let len = self.samples.len();
+        self.position = (self.position + self.step) % len;
+
+        // The window ends at the current position, wrapping around the loop
+        let start = (self.position + len - window.len() % len) % len;
+        for (i, out) in window.iter_mut().enumerate() {
+            *out = self.samples[(start + i) % len];
+        }
+    }
+}
+
+/// Decodes the audio of `path` to mono `f32` samples at `FILE_RATE`, at most `max_samples`.
+fn decode(path: &Path, max_samples: usize) -> Result<Vec<f32>, anyhow::Error> {
+    gst::init()?;
+
+    let uri = paths::path_to_uri(path)?;
+    let pipeline = gst::parse_launch(&format!(
+        "uridecodebin uri=\"{}\" ! audioconvert ! audioresample ! \
+         appsink name=sink sync=false caps=audio/x-raw,format={},layout=interleaved,channels=1,rate={}",
+        uri,
+        gst_audio::AUDIO_FORMAT_F32.to_str(),
+        FILE_RATE
+    ))?
+    .downcast::<gst::Pipeline>()
+    .map_err(|_| anyhow::anyhow!("Decoding pipeline is not a pipeline"))?;
+
+    let appsink = pipeline
+        .by_name("sink")
+        .and_then(|sink| sink.downcast::<gst_app::AppSink>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Decoding pipeline has no appsink"))?;
+
+    let result = pull_samples(&pipeline, &appsink, path, max_samples);
+    let _ = pipeline.set_state(gst::State::Null);
+    result
+}
+
+fn pull_samples(
+    pipeline: &gst::Pipeline,
+    appsink: &gst_app::AppSink,
+    path: &Path,
+    max_samples: usize,
+) -> Result<Vec<f32>, anyhow::Error> {
+    pipeline.set_state(gst::State::Playing)?;
+
+    let mut samples = Vec::new();
+    // Fails on EOS
+    while let Ok(sample) = appsink.pull_sample() {
+        let Some(buffer) = sample.buffer() else {
+            continue;
+        };
+        let map = buffer.map_readable()?;
+        samples.extend(
+            map.chunks_exact(4)
+                .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])),
+        );
+
+        if samples.len() >= max_samples {
+            samples.truncate(max_samples);
+            break;
+        }
+    }
+
+    let bus = pipeline
+        .bus()
+        .ok_or_else(|| anyhow::anyhow!("Pipeline has no bus"))?;
+    if let Some(msg) = bus.pop_filtered(&[gst::MessageType::Error]) {
+        if let gst::MessageView::Error(e) = msg.view() {
+            return Err(anyhow::anyhow!(
+                "Failed to decode {}: {}",
+                path.display(),
+                e.error()
+            ));
+        }
+    }
+
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a mono 16-bit WAV file of `samples` at `FILE_RATE`.
+    fn write_wav(path: &Path, samples: &[i16]) {
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        // PCM, mono
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(FILE_RATE.to_le_bytes());
+        wav.extend((FILE_RATE * 2).to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend(sample.to_le_bytes());
+        }
+        std::fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn loops_one_frame_per_read() {
+        let samples = (0..10).map(|sample| sample as f32).collect();
+        // 3 samples per frame
+        let mut input = Input::File(FileInput::new(samples, FILE_RATE as f64 / 3.).unwrap());
+        let mut window = [0.; 4];
+
+        input.read(&mut window).unwrap();
+        assert_eq!(window, [9., 0., 1., 2.]);
+        input.read(&mut window).unwrap();
+        assert_eq!(window, [2., 3., 4., 5.]);
+
+        assert!(FileInput::new(Vec::new(), 30.).is_none());
+    }
+
+    #[test]
+    fn decodes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("half.wav");
+        write_wav(&path, &[i16::MAX / 2; FILE_RATE as usize]);
+
+        let samples = decode(&path, usize::MAX).unwrap();
+        assert_eq!(samples.len(), FILE_RATE as usize);
+        assert!(samples.iter().all(|sample| (sample - 0.5).abs() < 0.01));
+
+        // Stops decoding at the limit
+        assert_eq!(decode(&path, 1000).unwrap().len(), 1000);
+
+        let mut input = FileInput::open(&path, 30.).unwrap();
+        let mut window = [0.; 1024];
+        input.read(&mut window);
+        assert!(window.iter().all(|sample| (sample - 0.5).abs() < 0.01));
+
+        assert!(FileInput::open(&dir.path().join("missing.wav"), 30.).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+const MIN_FREQUENCY: f32 = 30.;
+const MAX_FREQUENCY: f32 = 16000.;
+
+/// Levels below are shown as silence, a full scale sine is 0 dB
+const MIN_DB: f32 = -60.;
+
+/// Windowed FFT of the latest samples, grouped into log-spaced bands.
+pub(crate) struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    rate: u32,
+
+    /// Hann window
+    window: Vec<f32>,
+    window_sum: f32,
+    buffer: Vec<Complex<f32>>,
+}
+
+impl Spectrum {
+    pub(crate) fn new(size: usize, rate: u32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(size);
+
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / size as f32).cos())
+            .collect::<Vec<_>>();
+        let window_sum = window.iter().sum();
+
+        Self {
+            fft,
+            rate,
+            window,
+            window_sum,
+            buffer: vec![Complex::default(); size],
+        }
+    }
+
+    /// Levels of `count` bands from 0 to 1, lowest frequencies first.
+    ///
+    /// `samples` must be as long as the FFT.
+    pub(crate) fn bands(&mut self, samples: &[f32], count: usize) -> Vec<f32> {
+        let max_frequency = MAX_FREQUENCY.min(self.rate as f32 / 2.);
+        let ratio = max_frequency / MIN_FREQUENCY;
+
+        let ranges = (0..count)
+            .map(|band| {
+                (
+                    MIN_FREQUENCY * ratio.powf(band as f32 / count as f32),
+                    MIN_FREQUENCY * ratio.powf((band + 1) as f32 / count as f32),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.levels(samples, &ranges)
+    }
+
+    /// Levels of the `(low, high)` frequency ranges from 0 to 1.
+    ///
+    /// `samples` must be as long as the FFT.
+    pub(crate) fn levels(&mut self, samples: &[f32], ranges: &[(f32, f32)]) -> Vec<f32> {
+        for ((out, sample), window) in self.buffer.iter_mut().zip(samples).zip(&self.window) {
+            *out = Complex::new(sample * window, 0.);
+        }
+        self.fft.process(&mut self.buffer);
+
+        let bins = self.buffer.len() / 2;
+        let bin_width = self.rate as f32 / self.buffer.len() as f32;
+
+        ranges
+            .iter()
+            .map(|(low, high)| {
+                let first = ((low / bin_width) as usize).clamp(1, bins - 1);
+                let last = ((high / bin_width) as usize).clamp(first + 1, bins);
+
+                let magnitude = self.buffer[first..last]
+                    .iter()
+                    .map(|c| c.norm() * 2. / self.window_sum)
+                    .fold(0., f32::max);
+
+                normalize_db(20. * (magnitude + f32::EPSILON).log10())
+            })
+            .collect()
+    }
+}
+
+/// Maps decibels to 0 (`MIN_DB` or less) to 1 (full scale).
+#[inline]
+pub(crate) fn normalize_db(db: f32) -> f32 {
+    ((db - MIN_DB) / -MIN_DB).clamp(0., 1.)
+}